@@ -0,0 +1,404 @@
+//! Sproc state archives (`sproc export-state`/`sproc import-state`)
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Read, Result},
+};
+
+use crate::doctor::program_exists;
+use crate::model::{split_command, sproc_dir, Service, ServicesConfiguration};
+
+/// Files (relative to the sproc directory) included in state archives
+///
+/// Services' `env_file`s are also included (as `env/{service}`) when secrets are.
+pub const ARCHIVE_FILES: [&str; 2] = ["services.toml", "audit.jsonl"];
+
+/// Where services' `env_file`s are kept in state archives
+const ENV_FILE_DIR: &str = "env/";
+
+/// How imported services are combined with the existing configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Add services that don't already exist
+    Merge,
+    /// Replace the configuration (and audit log) entirely
+    Replace,
+}
+
+/// Remove secrets (server key and tokens, webhook secrets, remote keys) from a configuration
+fn redact(config: &mut ServicesConfiguration) {
+    config.server.key = String::new();
+
+    // a blank token would let anyone in, so they're removed entirely
+    config.server.tokens.clear();
+
+    if let Some(ref mut webhooks) = config.webhooks {
+        for webhook in webhooks {
+            webhook.secret = String::new();
+        }
+    }
+
+    if let Some(ref mut remotes) = config.remotes {
+        for remote in remotes.values_mut() {
+            remote.key = String::new();
+        }
+    }
+}
+
+/// Add a file to an archive
+fn append<W: std::io::Write>(
+    archive: &mut tar::Builder<W>,
+    name: &str,
+    content: &str,
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o600);
+    header.set_cksum();
+    archive.append_data(&mut header, name, content.as_bytes())
+}
+
+/// Write the pinned configuration and audit log into a `.tar.gz` archive
+///
+/// Secrets are blanked out unless `include_secrets` is set, which also includes the `env_file` of
+/// every service (env files that don't exist are skipped).
+pub fn export_state(output: &str, include_secrets: bool) -> Result<()> {
+    let dir = sproc_dir().to_string_lossy().to_string();
+
+    // the pinned file (wherever it is), without states
+    let mut config = ServicesConfiguration::try_get_config()?;
+    config.service_states.clear();
+
+    if !include_secrets {
+        redact(&mut config);
+    }
+
+    let file = std::fs::File::create(output)?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    for name in ARCHIVE_FILES {
+        let content = if name == "services.toml" {
+            toml::to_string_pretty(&config).unwrap()
+        } else {
+            match std::fs::read_to_string(format!("{dir}/{name}")) {
+                Ok(c) => c,
+                Err(_) => continue,
+            }
+        };
+
+        append(&mut archive, name, &content)?;
+    }
+
+    // env files
+    if include_secrets {
+        let mut names: Vec<&String> = config.services.keys().collect();
+        names.sort();
+
+        for name in names {
+            let content = match config.services[name].env_file_path() {
+                Some(path) => match std::fs::read_to_string(path) {
+                    Ok(c) => c,
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+
+            append(&mut archive, &format!("{ENV_FILE_DIR}{name}"), &content)?;
+        }
+    }
+
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Check if a service's program exists (see [`program_exists`])
+fn command_exists(service: &Service) -> bool {
+    match split_command(&service.command) {
+        Ok(args) => args
+            .first()
+            .is_some_and(|program| program_exists(program, &service.working_directory)),
+        Err(_) => false,
+    }
+}
+
+/// Write an imported service's `env_file`
+fn restore_env_file(service: &Service, content: &str) -> Result<()> {
+    let path = match service.env_file_path() {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    std::fs::write(&path, content)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(())
+}
+
+/// Import a `.tar.gz` archive created by [`export_state`]
+///
+/// Env files in the archive are written to the `env_file` of the services that are imported.
+/// Returns warnings for services whose commands don't exist on this machine.
+pub fn import_state(path: &str, mode: ImportMode) -> Result<Vec<String>> {
+    let dir = sproc_dir().to_string_lossy().to_string();
+    let mut archive = tar::Archive::new(GzDecoder::new(std::fs::File::open(path)?));
+
+    let mut imported: Option<ServicesConfiguration> = None;
+    let mut files: Vec<(String, String)> = Vec::new();
+    let mut env_files: HashMap<String, String> = HashMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let env_service = name.strip_prefix(ENV_FILE_DIR).map(|s| s.to_string());
+
+        if env_service.is_none() && !ARCHIVE_FILES.contains(&name.as_str()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unexpected file in archive. ({name})"),
+            ));
+        }
+
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+
+        if let Some(service) = env_service {
+            env_files.insert(service, content);
+        } else if name == "services.toml" {
+            imported = match toml::from_str(&content) {
+                Ok(c) => Some(c),
+                Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+            };
+        } else {
+            files.push((name, content));
+        }
+    }
+
+    let imported = match imported {
+        Some(c) => c,
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Archive does not contain services.toml.",
+            ))
+        }
+    };
+
+    // validate
+    let mut warnings = Vec::new();
+
+    for (name, service) in &imported.services {
+        if !service.shell && !command_exists(service) {
+            warnings.push(format!(
+                "command for service \"{name}\" does not exist on this machine ({})",
+                service.command
+            ));
+        }
+    }
+
+    // apply
+    ServicesConfiguration::modify(|config| {
+        match mode {
+            ImportMode::Merge => {
+                for (name, service) in imported.services {
+                    if config.services.contains_key(&name) {
+                        continue;
+                    }
+
+                    if let Some(content) = env_files.get(&name) {
+                        restore_env_file(&service, content)?;
+                    }
+
+                    config.services.insert(name, service);
+                }
+            }
+            ImportMode::Replace => {
+                for state in config.service_states.values() {
+                    if state.0.is_running() {
+                        return Err(Error::new(
+                            ErrorKind::Other,
+                            "Cannot replace state with active service. Please run \"sproc kill-all\"",
+                        ));
+                    }
+                }
+
+                let source = config.source.clone();
+                *config = imported;
+                config.source = source;
+
+                for (name, content) in files {
+                    std::fs::write(format!("{dir}/{name}"), content)?;
+                }
+
+                for (name, content) in &env_files {
+                    if let Some(service) = config.services.get(name) {
+                        restore_env_file(service, content)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    })??;
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::testing;
+
+    fn config(contents: &str) -> ServicesConfiguration {
+        ServicesConfiguration::read(contents.to_string())
+    }
+
+    /// Compare configurations by their definitions
+    fn definitions(config: &ServicesConfiguration) -> toml::Value {
+        toml::Value::try_from(config).unwrap()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn export_and_replace_round_trip() {
+        let (dir, _env) = testing::config_dir("archive-replace");
+        let env_file = dir.join("env").join("api.env");
+        let original = config(&format!(
+            r#"
+            [server]
+            port = 6374
+            key = "secret"
+
+            [services.api]
+            command = "sh -c 'exit 0'"
+            working_directory = "{}"
+            restart = true
+            env_file = "env/api.env"
+            "#,
+            dir.display()
+        ));
+
+        ServicesConfiguration::update_config(original.clone()).unwrap();
+        std::fs::write(dir.join("audit.jsonl"), "{}\n").unwrap();
+        std::fs::create_dir_all(env_file.parent().unwrap()).unwrap();
+        std::fs::write(&env_file, "TOKEN=secret\n").unwrap();
+
+        let archive = dir.join("state.tar.gz").to_string_lossy().to_string();
+        export_state(&archive, true).unwrap();
+
+        // change everything, then import
+        ServicesConfiguration::update_config(config(
+            r#"
+            [services.worker]
+            command = "sh"
+            working_directory = "/"
+            "#,
+        ))
+        .unwrap();
+        std::fs::remove_file(dir.join("audit.jsonl")).unwrap();
+        std::fs::remove_dir_all(env_file.parent().unwrap()).unwrap();
+
+        let warnings = import_state(&archive, ImportMode::Replace).unwrap();
+        assert!(warnings.is_empty());
+
+        assert_eq!(
+            definitions(&ServicesConfiguration::get_config()),
+            definitions(&original)
+        );
+        // the import itself is audited after the restored log
+        assert!(std::fs::read_to_string(dir.join("audit.jsonl"))
+            .unwrap()
+            .starts_with("{}\n"));
+        assert_eq!(
+            std::fs::read_to_string(&env_file).unwrap(),
+            "TOKEN=secret\n"
+        );
+    }
+
+    #[test]
+    fn exports_leave_out_secrets() {
+        let (dir, _env) = testing::config_dir("archive-secrets");
+        let env_file = dir.join("api.env");
+        std::fs::write(&env_file, "TOKEN=secret\n").unwrap();
+
+        ServicesConfiguration::update_config(config(&format!(
+            r#"
+            [server]
+            port = 6374
+            key = "secret"
+
+            [[server.tokens]]
+            token = "token secret"
+            scopes = ["info"]
+
+            [[webhooks]]
+            url = "http://localhost/hook"
+            secret = "webhook secret"
+
+            [services.api]
+            command = "sh"
+            working_directory = "/"
+            env_file = "{}"
+            "#,
+            env_file.display()
+        )))
+        .unwrap();
+
+        let archive = dir.join("state.tar.gz").to_string_lossy().to_string();
+        export_state(&archive, false).unwrap();
+        std::fs::remove_file(&env_file).unwrap();
+        import_state(&archive, ImportMode::Replace).unwrap();
+
+        let imported = ServicesConfiguration::get_config();
+        assert!(imported.server.key.is_empty());
+        assert!(imported.server.tokens.is_empty());
+        assert!(imported.webhooks.unwrap()[0].secret.is_empty());
+        assert!(!env_file.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn merged_imports_keep_existing_services() {
+        let (dir, _env) = testing::config_dir("archive-merge");
+        ServicesConfiguration::update_config(config(
+            r#"
+            [services.api]
+            command = "sh -c 'exit 1'"
+            working_directory = "/"
+
+            [services.missing]
+            command = "sproc-test-program-that-does-not-exist"
+            working_directory = "/"
+            "#,
+        ))
+        .unwrap();
+
+        let archive = dir.join("state.tar.gz").to_string_lossy().to_string();
+        export_state(&archive, true).unwrap();
+
+        ServicesConfiguration::update_config(config(
+            r#"
+            [services.api]
+            command = "sh -c 'exit 0'"
+            working_directory = "/"
+            "#,
+        ))
+        .unwrap();
+
+        let warnings = import_state(&archive, ImportMode::Merge).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("\"missing\""));
+
+        let merged = ServicesConfiguration::get_config();
+        assert_eq!(merged.services["api"].command, "sh -c 'exit 0'");
+        assert!(merged.services.contains_key("missing"));
+    }
+}
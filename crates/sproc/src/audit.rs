@@ -0,0 +1,211 @@
+//! Sproc audit log (`audit.jsonl`)
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Result, Write};
+
+use crate::model::{epoch_timestamp, sproc_dir};
+
+/// The size (in bytes) at which `audit.jsonl` is rotated into `audit.jsonl.1`
+pub const AUDIT_ROTATE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// The operation an [`AuditEntry`] describes
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Start,
+    Kill,
+    Restart,
+    ConfigUpdate,
+    Renamed,
+    Adopt,
+    Scale,
+    Signal,
+    Stdin,
+}
+
+/// Who performed an audited operation (HTTP requests only)
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct AuditContext {
+    /// The IP of the caller (`X-Forwarded-For`/`X-Real-IP`)
+    pub caller_ip: Option<String>,
+    /// The ID of the request (`X-Request-ID`)
+    pub request_id: Option<String>,
+}
+
+/// A single line in `audit.jsonl`
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AuditEntry {
+    /// Unix timestamp (milliseconds)
+    pub timestamp: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub action: AuditAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_value: Option<String>,
+    pub success: bool,
+}
+
+impl AuditEntry {
+    /// Create a new [`AuditEntry`] timestamped now
+    pub fn new(
+        context: AuditContext,
+        action: AuditAction,
+        service_name: Option<String>,
+        success: bool,
+    ) -> Self {
+        Self {
+            timestamp: epoch_timestamp(),
+            caller_ip: context.caller_ip,
+            request_id: context.request_id,
+            action,
+            service_name,
+            old_value: None,
+            new_value: None,
+            success,
+        }
+    }
+}
+
+/// Filters for [`read`]
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct AuditFilter {
+    /// Only include entries for this service
+    pub service: Option<String>,
+    /// Only include entries at or after this Unix timestamp (milliseconds)
+    pub since: Option<u128>,
+    /// Only include the last `limit` entries
+    pub limit: Option<usize>,
+}
+
+/// Get the SHA-256 digest (hex) of `contents`, recorded instead of values that can't be
+/// recorded in full (like configuration definitions)
+pub fn digest(contents: &str) -> String {
+    hex::encode(Sha256::digest(contents.as_bytes()))
+}
+
+/// Get the path of `audit.jsonl`
+pub fn audit_path() -> String {
+    sproc_dir()
+        .join("audit.jsonl")
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Append an entry to `audit.jsonl`, rotating the file when it gets too large
+pub fn record(entry: AuditEntry) -> Result<()> {
+    let path = audit_path();
+
+    if let Ok(meta) = std::fs::metadata(&path) {
+        if meta.len() >= AUDIT_ROTATE_SIZE {
+            std::fs::rename(&path, format!("{path}.1"))?;
+        }
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+
+    writeln!(file, "{}", serde_json::to_string(&entry).unwrap())
+}
+
+/// Read entries from `audit.jsonl` (oldest first)
+pub fn read(filter: AuditFilter) -> Result<Vec<AuditEntry>> {
+    let file = match std::fs::File::open(audit_path()) {
+        Ok(f) => f,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut entries: Vec<AuditEntry> = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|l| serde_json::from_str(&l).ok())
+        .filter(|e: &AuditEntry| match filter.service {
+            Some(ref s) => e.service_name.as_ref() == Some(s),
+            None => true,
+        })
+        .filter(|e: &AuditEntry| match filter.since {
+            Some(since) => e.timestamp >= since,
+            None => true,
+        })
+        .collect();
+
+    if let Some(limit) = filter.limit {
+        if entries.len() > limit {
+            entries.drain(..entries.len() - limit);
+        }
+    }
+
+    // return
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::testing;
+
+    #[test]
+    fn recorded_entries_are_read_back() {
+        let (_, _env) = testing::config_dir("audit");
+
+        for (action, service) in [
+            (AuditAction::Start, "api"),
+            (AuditAction::Kill, "worker"),
+            (AuditAction::Restart, "api"),
+        ] {
+            record(AuditEntry::new(
+                AuditContext {
+                    caller_ip: Some("127.0.0.1".to_string()),
+                    request_id: None,
+                },
+                action,
+                Some(service.to_string()),
+                true,
+            ))
+            .unwrap();
+        }
+
+        let entries = read(AuditFilter::default()).unwrap();
+        let actions: Vec<AuditAction> = entries.iter().map(|e| e.action.clone()).collect();
+
+        assert_eq!(
+            actions,
+            [AuditAction::Start, AuditAction::Kill, AuditAction::Restart]
+        );
+        assert_eq!(entries[0].caller_ip.as_deref(), Some("127.0.0.1"));
+
+        // filters
+        let api = read(AuditFilter {
+            service: Some("api".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(api.len(), 2);
+        assert!(api.iter().all(|e| e.service_name.as_deref() == Some("api")));
+
+        let last = read(AuditFilter {
+            limit: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].action, AuditAction::Restart);
+
+        let since = read(AuditFilter {
+            since: Some(entries[2].timestamp + 1),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(since.is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+//! Sproc daemon boot integration (systemd, launchd, Windows scheduled tasks)
+use std::{
+    env,
+    io::{Error, ErrorKind, Result},
+    process::Command,
+};
+
+use crate::model::data_dir;
+
+/// Where the daemon is installed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootScope {
+    /// Run for every user at boot (requires root/administrator)
+    System,
+    /// Run for the current user at login
+    User,
+}
+
+/// The init system to generate a service file for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// systemd `.service` unit
+    Linux,
+    /// launchd plist
+    MacOS,
+    /// Scheduled task
+    Windows,
+}
+
+impl Platform {
+    /// Get the platform sproc was built for
+    pub fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            Self::MacOS
+        } else if cfg!(target_os = "windows") {
+            Self::Windows
+        } else {
+            Self::Linux
+        }
+    }
+}
+
+/// The label used for the generated service
+pub const BOOT_LABEL: &str = "org.swmff.sprocd";
+
+/// A generated service file
+pub struct BootFile {
+    /// Where the file is installed
+    pub path: String,
+    /// The contents of the file
+    pub content: String,
+}
+
+/// Generate the service file that runs `sprocd` at boot
+///
+/// `home` is the daemon's `$HOME`, and `config` is the pinned file it reads (`SPROC_CONFIG`),
+/// so it uses the same file as the CLI that installed it.
+pub fn generate(
+    platform: Platform,
+    scope: BootScope,
+    daemon: &str,
+    home: &str,
+    config: &str,
+) -> BootFile {
+    match platform {
+        Platform::Linux => BootFile {
+            path: match scope {
+                BootScope::System => "/etc/systemd/system/sprocd.service".to_string(),
+                BootScope::User => format!("{home}/.config/systemd/user/sprocd.service"),
+            },
+            content: format!(
+                "[Unit]
+Description=Sproc daemon
+After=network.target
+
+[Service]
+ExecStart={daemon}
+Environment=HOME={home}
+Environment=SPROC_CONFIG={config}
+Restart=on-failure
+
+[Install]
+WantedBy={}
+",
+                match scope {
+                    BootScope::System => "multi-user.target",
+                    BootScope::User => "default.target",
+                }
+            ),
+        },
+        Platform::MacOS => BootFile {
+            path: match scope {
+                BootScope::System => format!("/Library/LaunchDaemons/{BOOT_LABEL}.plist"),
+                BootScope::User => format!("{home}/Library/LaunchAgents/{BOOT_LABEL}.plist"),
+            },
+            content: format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{BOOT_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{daemon}</string>
+    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>HOME</key>
+        <string>{home}</string>
+        <key>SPROC_CONFIG</key>
+        <string>{config}</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+</dict>
+</plist>
+"#
+            ),
+        },
+        Platform::Windows => BootFile {
+            path: data_dir().join("sprocd.cmd").to_string_lossy().to_string(),
+            content: format!(
+                "@echo off\r\nset HOME={home}\r\nset SPROC_CONFIG={config}\r\n\"{daemon}\"\r\n"
+            ),
+        },
+    }
+}
+
+/// Run a command, failing if it exits with a non-zero status
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new(program).args(args).status()?;
+
+    if !status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "Command failed with {status}. ({program} {})",
+                args.join(" ")
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Get the path of the `sprocd` binary (next to the running `sproc` binary)
+pub fn daemon_path() -> Result<String> {
+    let exe = env::current_exe()?;
+    let name = if cfg!(target_os = "windows") {
+        "sprocd.exe"
+    } else {
+        "sprocd"
+    };
+
+    Ok(exe.with_file_name(name).to_string_lossy().to_string())
+}
+
+/// Install and enable the daemon service file
+pub fn install(platform: Platform, scope: BootScope, file: &BootFile) -> Result<()> {
+    if let Some(parent) = std::path::Path::new(&file.path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::write(&file.path, &file.content)?;
+
+    match platform {
+        Platform::Linux => match scope {
+            BootScope::System => run("systemctl", &["enable", "--now", "sprocd.service"]),
+            BootScope::User => run(
+                "systemctl",
+                &["--user", "enable", "--now", "sprocd.service"],
+            ),
+        },
+        Platform::MacOS => run("launchctl", &["load", "-w", &file.path]),
+        Platform::Windows => run(
+            "schtasks",
+            &[
+                "/Create",
+                "/F",
+                "/TN",
+                "sprocd",
+                "/TR",
+                &file.path,
+                "/SC",
+                match scope {
+                    BootScope::System => "ONSTART",
+                    BootScope::User => "ONLOGON",
+                },
+            ],
+        ),
+    }
+}
+
+/// Disable and remove the daemon service file
+pub fn uninstall(platform: Platform, scope: BootScope, file: &BootFile) -> Result<()> {
+    match platform {
+        Platform::Linux => match scope {
+            BootScope::System => run("systemctl", &["disable", "--now", "sprocd.service"])?,
+            BootScope::User => run(
+                "systemctl",
+                &["--user", "disable", "--now", "sprocd.service"],
+            )?,
+        },
+        Platform::MacOS => run("launchctl", &["unload", "-w", &file.path])?,
+        Platform::Windows => run("schtasks", &["/Delete", "/F", "/TN", "sprocd"])?,
+    }
+
+    std::fs::remove_file(&file.path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::testing;
+
+    #[test]
+    fn systemd_units() {
+        let file = generate(
+            Platform::Linux,
+            BootScope::System,
+            "/usr/bin/sprocd",
+            "/root",
+            "/root/.config/xsu-apps/sproc/services.toml",
+        );
+        assert_eq!(file.path, "/etc/systemd/system/sprocd.service");
+        assert!(file.content.contains("ExecStart=/usr/bin/sprocd\n"));
+        assert!(file.content.contains("Environment=HOME=/root\n"));
+        assert!(file
+            .content
+            .contains("Environment=SPROC_CONFIG=/root/.config/xsu-apps/sproc/services.toml\n"));
+        assert!(file.content.contains("WantedBy=multi-user.target\n"));
+
+        let file = generate(
+            Platform::Linux,
+            BootScope::User,
+            "/usr/bin/sprocd",
+            "/home/a",
+            "/home/a/services.yaml",
+        );
+        assert_eq!(file.path, "/home/a/.config/systemd/user/sprocd.service");
+        assert!(file.content.contains("WantedBy=default.target\n"));
+        assert!(file
+            .content
+            .contains("Environment=SPROC_CONFIG=/home/a/services.yaml\n"));
+    }
+
+    #[test]
+    fn launchd_plists() {
+        let file = generate(
+            Platform::MacOS,
+            BootScope::System,
+            "/usr/local/bin/sprocd",
+            "/var/root",
+            "/var/root/.config/xsu-apps/sproc/services.toml",
+        );
+        assert_eq!(
+            file.path,
+            format!("/Library/LaunchDaemons/{BOOT_LABEL}.plist")
+        );
+
+        let file = generate(
+            Platform::MacOS,
+            BootScope::User,
+            "/usr/local/bin/sprocd",
+            "/Users/a",
+            "/Users/a/services.toml",
+        );
+        assert_eq!(
+            file.path,
+            format!("/Users/a/Library/LaunchAgents/{BOOT_LABEL}.plist")
+        );
+        assert!(file
+            .content
+            .contains(&format!("<string>{BOOT_LABEL}</string>")));
+        assert!(file
+            .content
+            .contains("<string>/usr/local/bin/sprocd</string>"));
+        assert!(file.content.contains("<string>/Users/a</string>"));
+        assert!(file
+            .content
+            .contains("<key>SPROC_CONFIG</key>\n        <string>/Users/a/services.toml</string>"));
+        assert!(file.content.contains("<key>RunAtLoad</key>"));
+    }
+
+    #[test]
+    fn windows_scripts() {
+        let (dir, _env) = testing::config_dir("boot");
+        let file = generate(
+            Platform::Windows,
+            BootScope::User,
+            "C:\\sproc\\sprocd.exe",
+            "C:\\Users\\a",
+            "C:\\Users\\a\\services.toml",
+        );
+
+        assert_eq!(file.path, dir.join("sprocd.cmd").to_string_lossy());
+        assert_eq!(
+            file.content,
+            "@echo off\r\nset HOME=C:\\Users\\a\r\nset SPROC_CONFIG=C:\\Users\\a\\services.toml\r\n\"C:\\sproc\\sprocd.exe\"\r\n"
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Sproc HTTP API client
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{Error, ErrorKind, Result};
+
+use crate::doctor::DoctorReport;
+use crate::model::{AuthMode, BulkResults, ServerConfiguration, ServicesInfo};
+use crate::remote::Remote;
+use crate::server::{
+    APIReturn, BasicServiceRequestBody, HealthReport, InfoWithDiff, KeyRequestBody, ReloadReport,
+    ScaleRequestBody, SignalRequestBody, StdinRequestBody,
+};
+use crate::webhook;
+
+/// Turn a response status that isn't a success into an error
+fn check_status(endpoint: &str, status: StatusCode) -> Result<()> {
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let kind = match status {
+        StatusCode::UNAUTHORIZED => ErrorKind::PermissionDenied,
+        StatusCode::NOT_FOUND => ErrorKind::NotFound,
+        StatusCode::CONFLICT => ErrorKind::AlreadyExists,
+        StatusCode::BAD_REQUEST => ErrorKind::InvalidInput,
+        _ => ErrorKind::Other,
+    };
+
+    Err(Error::new(
+        kind,
+        format!(
+            "Request failed with status {}. ({endpoint})",
+            status.as_u16()
+        ),
+    ))
+}
+
+/// A client for a running sproc server's HTTP API (`/api/sproc`)
+#[derive(Clone, Debug)]
+pub struct Client {
+    /// The URL of the server
+    pub url: String,
+    /// The server's key (or a token)
+    key: String,
+    /// How the server expects requests to prove they know the key
+    auth_mode: AuthMode,
+    /// Send service operations through the server to this remote (see [`Client::through`])
+    remote: Option<String>,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Create a new [`Client`]
+    pub fn new(url: &str, key: &str, auth_mode: AuthMode) -> Self {
+        Self {
+            url: url.trim_end_matches("/").to_string(),
+            key: key.to_string(),
+            auth_mode,
+            remote: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Create a [`Client`] for the local server
+    ///
+    /// Fails if the server is served on a Unix socket (`unix_socket`), since it doesn't listen
+    /// on `host` and `port` then.
+    pub fn from_config(server: &ServerConfiguration) -> Result<Self> {
+        if let Some(ref path) = server.unix_socket {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("The server is served on a Unix socket, which the CLI can't connect to. ({path})"),
+            ));
+        }
+
+        Ok(Self::new(
+            &server.url(),
+            &server.key,
+            server.auth_mode.clone(),
+        ))
+    }
+
+    /// Create a [`Client`] for a remote server
+    pub fn from_remote(remote: &Remote) -> Self {
+        Self::new(&remote.url, &remote.key, remote.auth_mode.clone())
+    }
+
+    /// Send `start`, `kill`, and `info` through the server to one of its `remotes`
+    pub fn through(mut self, remote: &str) -> Self {
+        self.remote = Some(remote.to_string());
+        self
+    }
+
+    /// The key sent in request bodies (signed requests don't send it)
+    fn key(&self) -> String {
+        match self.auth_mode {
+            AuthMode::Hmac => String::new(),
+            _ => self.key.clone(),
+        }
+    }
+
+    /// Request body for an operation on a service
+    fn service_body(&self, service: &str) -> BasicServiceRequestBody {
+        BasicServiceRequestBody {
+            service: service.to_string(),
+            key: self.key(),
+            remote: self.remote.clone(),
+        }
+    }
+
+    /// Request body for an operation that only needs the key
+    fn key_body(&self) -> KeyRequestBody {
+        KeyRequestBody { key: self.key() }
+    }
+
+    /// Send a request (signing it under [`AuthMode::Hmac`])
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let mut request = match request.build() {
+            Ok(r) => r,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidInput, e.to_string())),
+        };
+
+        if self.auth_mode == AuthMode::Hmac {
+            webhook::sign_api_request(&self.key, &mut request);
+        }
+
+        match self.http.execute(request).await {
+            Ok(r) => Ok(r),
+            Err(e) => Err(Error::new(ErrorKind::NotConnected, e.to_string())),
+        }
+    }
+
+    /// Send a request (`POST /api/sproc/{endpoint}`), returning the response's `data`
+    ///
+    /// Responses that aren't a success are returned as errors.
+    pub async fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        body: &B,
+    ) -> Result<T> {
+        let request = self
+            .http
+            .post(format!("{}/api/sproc/{endpoint}", self.url))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(body).unwrap());
+
+        let res = self.send(request).await?;
+
+        check_status(endpoint, res.status())?;
+
+        match res.json::<APIReturn<T>>().await {
+            Ok(r) => Ok(r.data),
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+
+    /// Send a `GET` request (`/api/sproc/{endpoint}`, with the key in the query)
+    ///
+    /// Responses that aren't a success are returned as errors.
+    pub async fn get(&self, endpoint: &str, query: &[(&str, String)]) -> Result<reqwest::Response> {
+        let request = self
+            .http
+            .get(format!("{}/api/sproc/{endpoint}", self.url))
+            .query(&[("key", self.key())])
+            .query(query);
+
+        let res = self.send(request).await?;
+
+        check_status(endpoint, res.status())?;
+        Ok(res)
+    }
+
+    /// Get the server's health and version (`/health`)
+    pub async fn health(&self) -> Result<HealthReport> {
+        match self.get("health", &[]).await?.json().await {
+            Ok(r) => Ok(r),
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+
+    /// Look for common problems with the server's configuration and services (`/doctor`)
+    pub async fn doctor(&self) -> Result<DoctorReport> {
+        let res = self.get("doctor", &[]).await?;
+
+        let res: APIReturn<Option<DoctorReport>> = match res.json().await {
+            Ok(r) => r,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        };
+
+        match res.data {
+            Some(r) => Ok(r),
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                "Server returned no report.",
+            )),
+        }
+    }
+
+    /// Start a service (`/start`)
+    pub async fn start(&self, service: &str) -> Result<()> {
+        self.post::<_, u16>("start", &self.service_body(service))
+            .await
+            .map(|_| ())
+    }
+
+    /// Kill a service (`/kill`)
+    pub async fn kill(&self, service: &str) -> Result<()> {
+        self.post::<_, u16>("kill", &self.service_body(service))
+            .await
+            .map(|_| ())
+    }
+
+    /// Kill and re-spawn a service (`/restart`)
+    pub async fn restart(&self, service: &str) -> Result<()> {
+        self.post::<_, u16>("restart", &self.service_body(service))
+            .await
+            .map(|_| ())
+    }
+
+    /// Send a signal (`"HUP"`, `"USR1"`, ...) to a running service (`/signal`)
+    pub async fn signal(&self, service: &str, signal: &str) -> Result<()> {
+        let body = SignalRequestBody {
+            service: service.to_string(),
+            signal: signal.to_string(),
+            key: self.key(),
+        };
+
+        self.post::<_, u16>("signal", &body).await.map(|_| ())
+    }
+
+    /// Write to a running service's stdin (`/stdin`)
+    pub async fn write_stdin(&self, service: &str, data: &str) -> Result<()> {
+        let body = StdinRequestBody {
+            service: service.to_string(),
+            data: data.to_string(),
+            key: self.key(),
+        };
+
+        self.post::<_, u16>("stdin", &body).await.map(|_| ())
+    }
+
+    /// Get a service's info (`/info/v2`)
+    pub async fn info(&self, service: &str) -> Result<InfoWithDiff> {
+        match self
+            .post::<_, Option<InfoWithDiff>>("info/v2", &self.service_body(service))
+            .await?
+        {
+            Some(i) => Ok(i),
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Server returned no info. ({service})"),
+            )),
+        }
+    }
+
+    /// Get every running service's info (`/info-all`)
+    pub async fn info_all(&self) -> Result<ServicesInfo> {
+        match self
+            .post::<_, Option<ServicesInfo>>("info-all", &self.key_body())
+            .await?
+        {
+            Some(i) => Ok(i),
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                "Server returned no info.",
+            )),
+        }
+    }
+
+    /// Spawn every service that isn't running (`/start-all`)
+    pub async fn start_all(&self) -> Result<BulkResults> {
+        self.post("start-all", &self.key_body()).await
+    }
+
+    /// Kill every running service (`/kill-all`)
+    pub async fn kill_all(&self) -> Result<BulkResults> {
+        self.post("kill-all", &self.key_body()).await
+    }
+
+    /// Run exactly `replicas` instances of a template (`/scale`)
+    pub async fn scale(&self, template: &str, replicas: u32) -> Result<BulkResults> {
+        let body = ScaleRequestBody {
+            service: template.to_string(),
+            replicas,
+            key: self.key(),
+        };
+
+        self.post("scale", &body).await
+    }
+
+    /// Make the server re-read the pinned configuration (`/reload`)
+    pub async fn reload(&self) -> Result<ReloadReport> {
+        match self
+            .post::<_, Option<ReloadReport>>("reload", &self.key_body())
+            .await?
+        {
+            Some(r) => Ok(r),
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                "Server returned no report.",
+            )),
+        }
+    }
+
+    /// Call `on_line` with the last `lines` lines of a service's log file (`/logs/:service`)
+    ///
+    /// With `follow`, this keeps calling `on_line` as lines are written until the server
+    /// closes the connection.
+    pub async fn logs(
+        &self,
+        service: &str,
+        lines: usize,
+        follow: bool,
+        mut on_line: impl FnMut(&str),
+    ) -> Result<()> {
+        let query = [("lines", lines.to_string()), ("follow", follow.to_string())];
+        let mut res = self.get(&format!("logs/{service}"), &query).await?;
+
+        // read events (only `data` is used, every event is a line)
+        let mut buf: Vec<u8> = Vec::new();
+
+        loop {
+            match res.chunk().await {
+                Ok(Some(chunk)) => buf.extend_from_slice(&chunk),
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(Error::new(ErrorKind::ConnectionAborted, e.to_string())),
+            }
+
+            while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=i).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end_matches(['\n', '\r']);
+
+                if let Some(data) = line.strip_prefix("data:") {
+                    on_line(data.strip_prefix(" ").unwrap_or(data));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_socket_servers_are_rejected() {
+        let mut server = ServerConfiguration::default();
+        assert_eq!(
+            Client::from_config(&server).unwrap().url,
+            "http://127.0.0.1:6374"
+        );
+
+        server.unix_socket = Some("/run/sproc/sproc.sock".to_string());
+        let error = Client::from_config(&server).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert!(error.to_string().contains("/run/sproc/sproc.sock"));
+    }
+}
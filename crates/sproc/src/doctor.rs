@@ -0,0 +1,236 @@
+//! Sproc self-diagnosis (`sproc doctor`, `GET /doctor`)
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::model::{
+    config_path, split_command, verified_process, ServicesConfiguration, ValidationError,
+};
+use crate::snapshot;
+
+/// A problem found by [`diagnose`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DoctorFinding {
+    /// A service is marked as running, but its process doesn't exist (or is another process)
+    StalePid { service: String, pid: u32 },
+    /// The configuration file can be written by other users, or read by anyone (it has the
+    /// server key)
+    ConfigPermissions { path: String, mode: u32 },
+    /// The configuration file can't be read
+    ConfigUnreadable { path: String, error: String },
+    /// A service's working directory doesn't exist or can't be entered
+    UnreachableWorkingDirectory { service: String, path: String },
+    /// A service's program isn't in `PATH` (or doesn't exist)
+    CommandNotFound { service: String, program: String },
+    /// Services' health checks (or `wait_for`) connect to the same address
+    PortConflict {
+        services: Vec<String>,
+        address: String,
+    },
+    /// The running server is a different version than this one
+    VersionMismatch { local: String, server: String },
+}
+
+impl std::fmt::Display for DoctorFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DoctorFinding::StalePid { service, pid } => write!(
+                f,
+                "Service is marked as running but its process is gone. ({service}: {pid})"
+            ),
+            DoctorFinding::ConfigPermissions { path, mode } => write!(
+                f,
+                "Configuration file is accessible to other users. ({path}: {mode:o})"
+            ),
+            DoctorFinding::ConfigUnreadable { path, error } => {
+                write!(f, "Configuration file can't be read. ({path}: {error})")
+            }
+            DoctorFinding::UnreachableWorkingDirectory { service, path } => write!(
+                f,
+                "Service working directory can't be entered. ({service}: {path})"
+            ),
+            DoctorFinding::CommandNotFound { service, program } => {
+                write!(f, "Service program was not found. ({service}: {program})")
+            }
+            DoctorFinding::PortConflict { services, address } => write!(
+                f,
+                "Services check the same address. ({}: {address})",
+                services.join(", ")
+            ),
+            DoctorFinding::VersionMismatch { local, server } => write!(
+                f,
+                "Server is running a different version. (local {local}, server {server})"
+            ),
+        }
+    }
+}
+
+/// Output of [`diagnose`]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DoctorReport {
+    /// The version of sproc that ran the checks
+    pub version: String,
+    pub findings: Vec<DoctorFinding>,
+}
+
+/// The extensions tried for programs without one on Windows if `PATHEXT` isn't set
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// Get the file names a program can be run from: its own, and on Windows (if it doesn't have
+/// an extension) its own with each extension in `PATHEXT`
+fn program_files(program: &str) -> Vec<String> {
+    let mut files = vec![program.to_string()];
+
+    if cfg!(windows) && Path::new(program).extension().is_none() {
+        let extensions = std::env::var("PATHEXT").unwrap_or(DEFAULT_PATHEXT.to_string());
+
+        files.extend(
+            extensions
+                .split(';')
+                .filter(|e| !e.is_empty())
+                .map(|e| format!("{program}{e}")),
+        );
+    }
+
+    files
+}
+
+/// Check if a program can be run: a path (relative to `dir`) that exists, or a name in `PATH`
+pub fn program_exists(program: &str, dir: &str) -> bool {
+    let files = program_files(program);
+
+    if program.contains(std::path::MAIN_SEPARATOR) || program.contains("/") {
+        return files.iter().any(|f| Path::new(dir).join(f).is_file());
+    }
+
+    match std::env::var_os("PATH") {
+        Some(paths) => {
+            std::env::split_paths(&paths).any(|p| files.iter().any(|f| p.join(f).is_file()))
+        }
+        None => false,
+    }
+}
+
+/// Check the configuration file's permissions
+#[cfg(unix)]
+fn check_config_file(findings: &mut Vec<DoctorFinding>) {
+    use std::os::unix::fs::PermissionsExt;
+    let path = config_path();
+
+    match std::fs::metadata(&path) {
+        Ok(meta) => {
+            let mode = meta.permissions().mode() & 0o777;
+
+            // writable by group/others, or readable by others
+            if mode & 0o026 != 0 {
+                findings.push(DoctorFinding::ConfigPermissions {
+                    path: path.to_string_lossy().to_string(),
+                    mode,
+                });
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => findings.push(DoctorFinding::ConfigUnreadable {
+            path: path.to_string_lossy().to_string(),
+            error: e.to_string(),
+        }),
+    }
+}
+
+/// Check the configuration file's permissions
+#[cfg(not(unix))]
+fn check_config_file(findings: &mut Vec<DoctorFinding>) {
+    let path = config_path();
+
+    if let Err(e) = std::fs::File::open(&path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            findings.push(DoctorFinding::ConfigUnreadable {
+                path: path.to_string_lossy().to_string(),
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+/// Look for common problems with a configuration and the services it manages
+///
+/// `server_version` is the version reported by the running server (if it's running).
+pub fn diagnose(config: &ServicesConfiguration, server_version: Option<&str>) -> DoctorReport {
+    let version = env!("CARGO_PKG_VERSION").to_string();
+    let mut findings = Vec::new();
+
+    // stale pids
+    let mut running: Vec<(&String, u32)> = config
+        .service_states
+        .iter()
+        .filter(|(_, s)| s.0.is_running())
+        .map(|(name, s)| (name, s.1))
+        .collect();
+
+    running.sort();
+
+    snapshot::with_processes(&config.running_pids(), |sys| {
+        for (name, pid) in running {
+            if verified_process(sys, pid, config.fingerprints.get(name)).is_none() {
+                findings.push(DoctorFinding::StalePid {
+                    service: name.to_owned(),
+                    pid,
+                });
+            }
+        }
+    });
+
+    // configuration file
+    check_config_file(&mut findings);
+
+    // services
+    let mut names: Vec<&String> = config.services.keys().collect();
+    names.sort();
+
+    for name in names {
+        let service = &config.services[name];
+
+        if std::fs::read_dir(&service.working_directory).is_err() {
+            findings.push(DoctorFinding::UnreachableWorkingDirectory {
+                service: name.to_owned(),
+                path: service.working_directory.clone(),
+            });
+        }
+
+        // `shell` commands are run by `sh`
+        if service.shell {
+            continue;
+        }
+
+        if let Some(program) = split_command(&service.command)
+            .ok()
+            .and_then(|args| args.into_iter().next())
+        {
+            if !program_exists(&program, &service.working_directory) {
+                findings.push(DoctorFinding::CommandNotFound {
+                    service: name.to_owned(),
+                    program,
+                });
+            }
+        }
+    }
+
+    // port conflicts
+    for error in config.validate() {
+        if let ValidationError::PortClash { services, address } = error {
+            findings.push(DoctorFinding::PortConflict { services, address });
+        }
+    }
+
+    // server version
+    if let Some(server) = server_version {
+        if server != version {
+            findings.push(DoctorFinding::VersionMismatch {
+                local: version.clone(),
+                server: server.to_string(),
+            });
+        }
+    }
+
+    DoctorReport { version, findings }
+}
@@ -0,0 +1,24 @@
+//! Sproc event bus (`GET /events`)
+use std::sync::OnceLock;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::webhook::WebhookPayload;
+
+/// How many events a slow subscriber can fall behind before it starts missing them
+pub const EVENT_BUFFER: usize = 256;
+
+static BUS: OnceLock<Sender<WebhookPayload>> = OnceLock::new();
+
+fn bus() -> &'static Sender<WebhookPayload> {
+    BUS.get_or_init(|| broadcast::channel(EVENT_BUFFER).0)
+}
+
+/// Send an event to every current subscriber (dropped if there are none)
+pub fn publish(payload: WebhookPayload) {
+    let _ = bus().send(payload);
+}
+
+/// Receive every event published from now on
+pub fn subscribe() -> Receiver<WebhookPayload> {
+    bus().subscribe()
+}
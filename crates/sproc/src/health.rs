@@ -0,0 +1,227 @@
+//! Sproc service health checks
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::Result,
+    net::{TcpStream, ToSocketAddrs},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+use sysinfo::Pid;
+
+use crate::model::{shell_command, ServiceEntry, ServiceState, ServicesConfiguration};
+use crate::snapshot;
+use crate::webhook::{self, ServiceEventKind, WebhookPayload};
+
+/// What a [`HealthCheck`] checks
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthCheckKind {
+    /// A GET request to `url` responds with `status` (200 by default)
+    Http {
+        url: String,
+        #[serde(default = "status_default")]
+        status: u16,
+    },
+    /// A TCP connection to `address` (`host:port`) succeeds
+    Tcp { address: String },
+    /// `command` (run through `sh`) exits successfully
+    Cmd { command: String },
+}
+
+fn status_default() -> u16 {
+    200
+}
+
+/// A check that is repeatedly run against a [`Service`](crate::model::Service) while it's running
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// What is checked
+    pub check: HealthCheckKind,
+    /// How long to wait between checks (10 by default)
+    #[serde(default = "interval_seconds_default")]
+    pub interval_seconds: u64,
+    /// How long a single check can take before it fails (5 by default)
+    #[serde(default = "timeout_seconds_default")]
+    pub timeout_seconds: u64,
+    /// How many consecutive checks must fail before the service is unhealthy (3 by default)
+    #[serde(default = "retries_default")]
+    pub retries: u32,
+    /// Kill the service once it's unhealthy (so it's restarted if it has `restart` set)
+    #[serde(default)]
+    pub restart_unhealthy: bool,
+}
+
+fn interval_seconds_default() -> u64 {
+    10
+}
+
+fn timeout_seconds_default() -> u64 {
+    5
+}
+
+fn retries_default() -> u32 {
+    3
+}
+
+impl HealthCheck {
+    /// Run the check once
+    pub async fn run(&self) -> bool {
+        let timeout = Duration::from_secs(self.timeout_seconds);
+
+        match self.check {
+            HealthCheckKind::Http { ref url, status } => {
+                let client = match reqwest::Client::builder().timeout(timeout).build() {
+                    Ok(c) => c,
+                    Err(_) => return false,
+                };
+
+                match client.get(url).send().await {
+                    Ok(r) => r.status().as_u16() == status,
+                    Err(_) => false,
+                }
+            }
+            HealthCheckKind::Tcp { ref address } => {
+                let address = address.clone();
+
+                tokio::task::spawn_blocking(move || {
+                    let addrs = match address.to_socket_addrs() {
+                        Ok(a) => a,
+                        Err(_) => return false,
+                    };
+
+                    for addr in addrs {
+                        if TcpStream::connect_timeout(&addr, timeout).is_ok() {
+                            return true;
+                        }
+                    }
+
+                    false
+                })
+                .await
+                .unwrap_or(false)
+            }
+            HealthCheckKind::Cmd { ref command } => {
+                let command = command.clone();
+
+                tokio::task::spawn_blocking(move || run_command(&command, timeout).unwrap_or(false))
+                    .await
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
+
+/// Run a command through the shell (see [`crate::model::shell_args`]), killing it if it takes longer than `timeout`
+fn run_command(command: &str, timeout: Duration) -> Result<bool> {
+    let mut child = shell_command(command)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let started = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status.success());
+        }
+
+        if started.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(false);
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Set a service's state if it's still tracked with the given PID
+fn set_state(name: &str, pid: u32, state: ServiceState) {
+    let res = ServicesConfiguration::modify(|config| match config.service_states.get_mut(name) {
+        Some(s) if s.1 == pid && s.0.is_running() => s.0 = state,
+        _ => (),
+    });
+
+    if let Err(e) = res {
+        println!("warn: {e}");
+    }
+}
+
+/// Run a service's [`HealthCheck`] until its process (`pid`) stops being tracked, marking it
+/// [`ServiceState::Unhealthy`] after `retries` consecutive failures
+pub async fn monitor(name: String, pid: u32, check: HealthCheck, log_file: Option<String>) {
+    let interval = Duration::from_secs(check.interval_seconds);
+    let mut failures: u32 = 0;
+    let mut unhealthy = false;
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        // stop once the process has exited (or been replaced)
+        let config = match ServicesConfiguration::try_get_config() {
+            Ok(c) => c,
+            Err(e) => {
+                println!("warn: {e}");
+                continue;
+            }
+        };
+
+        match config.service_states.get(&name) {
+            Some(s) if s.1 == pid && s.0.is_running() => (),
+            _ => return,
+        }
+
+        if check.run().await {
+            failures = 0;
+
+            if unhealthy {
+                unhealthy = false;
+                set_state(&name, pid, ServiceState::Running);
+
+                ServiceEntry::new(&name, ServiceState::Running, Some(pid))
+                    .with_log_file(log_file.clone())
+                    .log_event(ServiceEventKind::Healthy, HashMap::new());
+
+                webhook::dispatch(
+                    &config.webhooks,
+                    WebhookPayload::new(name.clone(), ServiceEventKind::Healthy, Some(pid)),
+                );
+            }
+
+            continue;
+        }
+
+        failures += 1;
+
+        if unhealthy || failures < check.retries {
+            continue;
+        }
+
+        // mark unhealthy
+        unhealthy = true;
+        set_state(&name, pid, ServiceState::Unhealthy);
+
+        ServiceEntry::new(&name, ServiceState::Unhealthy, Some(pid))
+            .with_log_file(log_file.clone())
+            .log_event(
+                ServiceEventKind::Unhealthy,
+                HashMap::from([("failures".to_string(), failures.to_string())]),
+            );
+
+        webhook::dispatch(
+            &config.webhooks,
+            WebhookPayload::new(name.clone(), ServiceEventKind::Unhealthy, Some(pid)),
+        );
+
+        if check.restart_unhealthy {
+            let sys = snapshot::load(&[pid]);
+
+            if let Some(process) = sys.process(Pid::from(pid as usize)) {
+                process.kill();
+            }
+
+            return;
+        }
+    }
+}
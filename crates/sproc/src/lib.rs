@@ -2,7 +2,21 @@
 #![doc = include_str!("../README.md")]
 #![doc(issue_tracker_base_url = "https://github.com/hkauso/sproc/issues/")]
 
+pub mod archive;
+pub mod audit;
+pub mod boot;
+pub mod client;
+pub mod doctor;
+pub mod events;
+pub mod health;
+pub mod limits;
+pub mod logs;
+pub mod metrics;
 pub mod model;
+pub mod remote;
+pub mod scheduler;
 pub mod server;
+pub mod snapshot;
+pub mod webhook;
 
 pub use model::Service;
@@ -0,0 +1,171 @@
+//! Sproc service resource limits (`limits`)
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Result,
+    process::Command,
+    sync::Mutex,
+    time::Duration,
+};
+use sysinfo::{Pid, System};
+
+use crate::model::{ServiceEntry, ServiceState};
+use crate::webhook::ServiceEventKind;
+
+/// The exit code recorded for services killed by [`enforce`] (like the kernel OOM killer)
+pub const MEMORY_KILL_EXIT_CODE: i32 = 137;
+
+/// Resource limits applied to a [`Service`](crate::model::Service) when it's started
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// The most memory (in bytes) the service can use before it's killed
+    ///
+    /// Also set as `memory.max` in the service's cgroup (Linux, cgroups v2).
+    #[serde(default)]
+    pub max_memory_bytes: Option<u64>,
+    /// The most CPU time the service can use, as a percentage of one core (`cpu.max`)
+    #[serde(default)]
+    pub cpu_quota_percent: Option<u32>,
+    /// The service's CPU weight relative to other processes, 1 to 10000 (`cpu.weight`)
+    #[serde(default)]
+    pub cpu_weight: Option<u32>,
+    /// The most files the service can have open (`RLIMIT_NOFILE`)
+    #[serde(default)]
+    pub nofile: Option<u64>,
+}
+
+/// PIDs of processes killed by [`enforce`] that haven't been collected by their observer yet
+static MEMORY_KILLED: Mutex<Option<HashSet<u32>>> = Mutex::new(None);
+
+/// Check (and forget) if a process was killed by [`enforce`]
+pub fn was_memory_killed(pid: u32) -> bool {
+    let mut killed = MEMORY_KILLED.lock().unwrap_or_else(|e| e.into_inner());
+    killed.get_or_insert_with(HashSet::new).remove(&pid)
+}
+
+impl ResourceLimits {
+    /// Set process limits (`setrlimit`) on a command before it's spawned
+    #[cfg(unix)]
+    pub fn apply_rlimits(&self, cmd: &mut Command) {
+        use std::os::unix::process::CommandExt;
+
+        let nofile = match self.nofile {
+            Some(n) => n,
+            None => return,
+        };
+
+        // SAFETY: setrlimit is async-signal-safe and nothing is allocated
+        unsafe {
+            cmd.pre_exec(move || {
+                let limit = libc::rlimit {
+                    rlim_cur: nofile as libc::rlim_t,
+                    rlim_max: nofile as libc::rlim_t,
+                };
+
+                if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply_rlimits(&self, _: &mut Command) {}
+
+    /// Move a process into a cgroup (`sproc/{name}`) with these limits
+    ///
+    /// Does nothing if cgroups v2 isn't mounted at `/sys/fs/cgroup`, or no cgroup limits are set.
+    #[cfg(target_os = "linux")]
+    pub fn apply_cgroup(&self, name: &str, pid: u32) -> Result<()> {
+        let root = std::path::Path::new("/sys/fs/cgroup");
+
+        if !root.join("cgroup.controllers").exists() {
+            return Ok(());
+        }
+
+        let mut files: HashMap<&str, String> = HashMap::new();
+
+        if let Some(bytes) = self.max_memory_bytes {
+            files.insert("memory.max", bytes.to_string());
+        }
+
+        if let Some(percent) = self.cpu_quota_percent {
+            files.insert("cpu.max", format!("{} 100000", percent as u64 * 1000));
+        }
+
+        if let Some(weight) = self.cpu_weight {
+            files.insert("cpu.weight", weight.to_string());
+        }
+
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        // enable controllers for children of the sproc group
+        let group = root.join("sproc");
+        std::fs::create_dir_all(&group)?;
+        std::fs::write(group.join("cgroup.subtree_control"), "+memory +cpu")?;
+
+        let cgroup = group.join(name);
+        std::fs::create_dir_all(&cgroup)?;
+
+        for (file, value) in files {
+            std::fs::write(cgroup.join(file), value)?;
+        }
+
+        std::fs::write(cgroup.join("cgroup.procs"), pid.to_string())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply_cgroup(&self, _: &str, _: u32) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Kill a service's process (`pid`) if it uses more than `max_memory_bytes`, checking every second
+/// until it exits
+pub async fn enforce(name: String, pid: u32, max_memory_bytes: u64, log_file: Option<String>) {
+    let mut sys = System::new();
+    let pid = Pid::from(pid as usize);
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        if !sys.refresh_process(pid) {
+            return;
+        }
+
+        let process = match sys.process(pid) {
+            Some(p) => p,
+            None => return,
+        };
+
+        if process.memory() <= max_memory_bytes {
+            continue;
+        }
+
+        MEMORY_KILLED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert_with(HashSet::new)
+            .insert(pid.as_u32());
+
+        ServiceEntry::new(&name, ServiceState::Running, Some(pid.as_u32()))
+            .with_log_file(log_file)
+            .log_event(
+                ServiceEventKind::Failed,
+                HashMap::from([(
+                    "error".to_string(),
+                    format!(
+                        "exceeded max_memory_bytes ({} > {max_memory_bytes})",
+                        process.memory()
+                    ),
+                )]),
+            );
+
+        process.kill();
+        return;
+    }
+}
@@ -0,0 +1,448 @@
+//! Sproc log file tailing, rotation, and retention
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+
+use crate::model::ServicesConfiguration;
+
+/// How often [`follow`] checks a log file for new lines
+pub const FOLLOW_INTERVAL_MILLIS: u64 = 500;
+
+/// How often [`run`] checks log files
+pub const ROTATE_INTERVAL_SECS: u64 = 60;
+
+/// The number of rotated files kept if `max_files` isn't set
+pub const DEFAULT_MAX_LOG_FILES: u32 = 1;
+
+/// How lines are written to a service's log file (`log_format`)
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Output is written as it is (by the service itself)
+    #[default]
+    Raw,
+    /// Each line is prefixed with an RFC 3339 timestamp and its stream (`stdout` or `stderr`),
+    /// like `2024-01-01T00:00:00.000Z stdout | listening on 8080`
+    ///
+    /// Lines printed to the terminal (without a log file) also include the service's name,
+    /// since other services' output is mixed in.
+    Annotated,
+}
+
+impl LogFormat {
+    /// Format a line a service wrote to `stream` (including the service's name if given)
+    pub fn format_line(&self, service: Option<&str>, stream: &str, line: &str) -> String {
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+
+        match (self, service) {
+            (Self::Raw, _) => line.to_string(),
+            (Self::Annotated, Some(service)) => format!("{timestamp} {service} {stream} | {line}"),
+            (Self::Annotated, None) => format!("{timestamp} {stream} | {line}"),
+        }
+    }
+}
+
+/// Write every line read from a service's `stream` to its log file (or stdout) in a new thread
+///
+/// `on_line` is called with each line (before it's formatted).
+pub fn forward(
+    reader: impl Read + Send + 'static,
+    service: String,
+    stream: &'static str,
+    format: LogFormat,
+    mut log: Option<std::fs::File>,
+    mut on_line: impl FnMut(&str) + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            on_line(&line);
+
+            match log {
+                Some(ref mut file) => {
+                    let _ = writeln!(file, "{}", format.format_line(None, stream, &line));
+                }
+                None => println!("{}", format.format_line(Some(&service), stream, &line)),
+            }
+        }
+    });
+}
+
+/// The number of bytes read at a time by [`read_lines`]
+const CHUNK_BYTES: u64 = 8192;
+
+/// Split complete lines (without their line endings) off the front of `buf`
+fn take_lines(buf: &mut Vec<u8>) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+        let line: Vec<u8> = buf.drain(..=i).collect();
+        lines.push(
+            String::from_utf8_lossy(&line)
+                .trim_end_matches(['\n', '\r'])
+                .to_string(),
+        );
+    }
+
+    lines
+}
+
+/// Read the last `lines` lines of a file, returning them and the length of the file (where
+/// [`follow`] should continue from)
+pub fn read_lines(path: &str, lines: usize) -> Result<(Vec<String>, u64)> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+
+    // read backwards until there are enough lines
+    let mut start = len;
+    let mut buf: Vec<u8> = Vec::new();
+
+    while start > 0 && buf.iter().filter(|b| **b == b'\n').count() <= lines {
+        let chunk = CHUNK_BYTES.min(start);
+        start -= chunk;
+
+        let mut part = vec![0; chunk as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut part)?;
+
+        part.extend(buf);
+        buf = part;
+    }
+
+    // the last line might not be finished yet
+    if !buf.ends_with(b"\n") && !buf.is_empty() {
+        buf.push(b'\n');
+    }
+
+    let all = take_lines(&mut buf);
+    let tail = all[all.len().saturating_sub(lines)..].to_vec();
+
+    Ok((tail, len))
+}
+
+/// Send every line written to a file after `offset`, until the receiver is dropped
+///
+/// The file is read from the start again if it gets shorter (it was rotated or truncated).
+pub fn follow(path: String, mut offset: u64) -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel(256);
+
+    tokio::task::spawn(async move {
+        let mut buf: Vec<u8> = Vec::new();
+
+        while !sender.is_closed() {
+            if let Ok(mut file) = std::fs::File::open(&path) {
+                let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+                if len < offset {
+                    offset = 0;
+                    buf.clear();
+                }
+
+                if len > offset && file.seek(SeekFrom::Start(offset)).is_ok() {
+                    if let Ok(read) = file.read_to_end(&mut buf) {
+                        offset += read as u64;
+                    }
+
+                    for line in take_lines(&mut buf) {
+                        if sender.send(line).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(FOLLOW_INTERVAL_MILLIS)).await;
+        }
+    });
+
+    receiver
+}
+
+/// How service log files are rotated and pruned (`[logging]`)
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct LogRetention {
+    /// Rotate a log file when it gets larger than this many bytes (10 MiB by default, a
+    /// service's `max_log_bytes` takes priority)
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// How many rotated files (`{log_file}.1`, `{log_file}.2`, ...) are kept (1 by default)
+    #[serde(default)]
+    pub max_files: Option<u32>,
+    /// Remove rotated files older than this many days (kept forever by default)
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+}
+
+/// Get the path of a log file's `n`th rotated file (`{path}.{n}`)
+pub fn rotated_path(path: &str, n: u32) -> String {
+    format!("{path}.{n}")
+}
+
+impl LogRetention {
+    /// Rotate a log file if it's larger than `max_size`, returning if it was rotated
+    ///
+    /// Rotated files are shifted up (`.1` becomes `.2`) and the oldest is removed. With
+    /// `copy`, the file is copied then truncated instead of renamed, so a running process can
+    /// keep appending to it.
+    pub fn rotate(&self, path: &str, max_size: u64, copy: bool) -> Result<bool> {
+        match std::fs::metadata(path) {
+            Ok(meta) if meta.len() > max_size => (),
+            _ => return Ok(false),
+        }
+
+        let max_files = self.max_files.unwrap_or(DEFAULT_MAX_LOG_FILES);
+
+        // shift rotated files
+        if max_files > 0 {
+            for n in (1..max_files).rev() {
+                let from = rotated_path(path, n);
+
+                if Path::new(&from).exists() {
+                    std::fs::rename(from, rotated_path(path, n + 1))?;
+                }
+            }
+        }
+
+        // rotate
+        if copy {
+            if max_files > 0 {
+                std::fs::copy(path, rotated_path(path, 1))?;
+            }
+
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_len(0)?;
+        } else if max_files > 0 {
+            std::fs::rename(path, rotated_path(path, 1))?;
+        } else {
+            std::fs::remove_file(path)?;
+        }
+
+        self.prune(path)?;
+        Ok(true)
+    }
+
+    /// Remove a log file's rotated files past `max_files` or older than `max_age_days`,
+    /// returning their paths
+    pub fn prune(&self, path: &str) -> Result<Vec<String>> {
+        let max_files = self.max_files.unwrap_or(DEFAULT_MAX_LOG_FILES);
+        let max_age = self
+            .max_age_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+
+        let file = Path::new(path);
+        let (dir, name) = match (file.parent(), file.file_name()) {
+            (Some(dir), Some(name)) => (dir, name.to_string_lossy().to_string()),
+            _ => return Ok(Vec::new()),
+        };
+
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+
+        let mut removed = Vec::new();
+
+        let entries = match std::fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let entry_name = entry.file_name().to_string_lossy().to_string();
+
+            // only `{name}.{n}`
+            let n = match entry_name
+                .strip_prefix(&format!("{name}."))
+                .and_then(|n| n.parse::<u32>().ok())
+            {
+                Some(n) => n,
+                None => continue,
+            };
+
+            let expired = match max_age {
+                Some(max_age) => entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                    .is_some_and(|age| age > max_age),
+                None => false,
+            };
+
+            if n > max_files || expired {
+                std::fs::remove_file(entry.path())?;
+                removed.push(entry.path().to_string_lossy().to_string());
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Rotate and prune every service's log file, forever
+///
+/// Files of running services are copied and truncated, since the service keeps writing to
+/// them.
+pub async fn run() {
+    loop {
+        let config = match ServicesConfiguration::try_get_config() {
+            Ok(c) => c,
+            Err(e) => {
+                println!("warn: {e}");
+                tokio::time::sleep(Duration::from_secs(ROTATE_INTERVAL_SECS)).await;
+                continue;
+            }
+        };
+
+        for (name, service) in &config.services {
+            let path = match service.log_path(name) {
+                Some(p) => p,
+                None => continue,
+            };
+
+            let running = config
+                .service_states
+                .get(name)
+                .is_some_and(|s| s.0.is_running());
+
+            let max_size = service.max_log_size(&config.logging);
+
+            if let Err(e) = config.logging.rotate(&path, max_size, running) {
+                println!("warn: failed to rotate log file: {e} ({name})");
+            }
+
+            if let Err(e) = config.logging.prune(&path) {
+                println!("warn: failed to prune log files: {e} ({name})");
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(ROTATE_INTERVAL_SECS)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::testing;
+    use std::fs::{self, File};
+
+    fn read(path: &str) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn rotate_shifts_rotated_files() {
+        let dir = testing::temp_dir("logs-rotate");
+        let path = dir.join("web.log").to_string_lossy().to_string();
+        let retention = LogRetention {
+            max_files: Some(2),
+            ..Default::default()
+        };
+
+        // small files aren't rotated
+        fs::write(&path, "first").unwrap();
+        assert!(!retention.rotate(&path, 100, false).unwrap());
+        assert!(!Path::new(&rotated_path(&path, 1)).exists());
+
+        assert!(retention.rotate(&path, 1, false).unwrap());
+        assert!(!Path::new(&path).exists());
+        assert_eq!(read(&rotated_path(&path, 1)), "first");
+
+        fs::write(&path, "second").unwrap();
+        assert!(retention.rotate(&path, 1, false).unwrap());
+        fs::write(&path, "third").unwrap();
+        assert!(retention.rotate(&path, 1, false).unwrap());
+
+        // the oldest file is dropped past `max_files`
+        assert_eq!(read(&rotated_path(&path, 1)), "third");
+        assert_eq!(read(&rotated_path(&path, 2)), "second");
+        assert!(!Path::new(&rotated_path(&path, 3)).exists());
+    }
+
+    #[test]
+    fn rotate_copies_files_in_use() {
+        let dir = testing::temp_dir("logs-copy");
+        let path = dir.join("web.log").to_string_lossy().to_string();
+        let retention = LogRetention::default();
+
+        fs::write(&path, "output").unwrap();
+        assert!(retention.rotate(&path, 1, true).unwrap());
+
+        // the file stays (empty) for the process writing to it
+        assert_eq!(read(&path), "");
+        assert_eq!(read(&rotated_path(&path, 1)), "output");
+
+        // without rotated files, the file is just removed (or emptied)
+        let retention = LogRetention {
+            max_files: Some(0),
+            ..Default::default()
+        };
+
+        fs::write(&path, "output").unwrap();
+        assert!(retention.rotate(&path, 1, true).unwrap());
+        assert_eq!(read(&path), "");
+
+        fs::write(&path, "output").unwrap();
+        assert!(retention.rotate(&path, 1, false).unwrap());
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn prune_removes_extra_and_expired_files() {
+        let dir = testing::temp_dir("logs-prune");
+        let path = dir.join("web.log").to_string_lossy().to_string();
+
+        for n in 1..=3 {
+            fs::write(rotated_path(&path, n), n.to_string()).unwrap();
+        }
+
+        // other files in the directory are left alone
+        fs::write(&path, "current").unwrap();
+        fs::write(dir.join("web.log.old"), "").unwrap();
+        fs::write(dir.join("api.log.3"), "").unwrap();
+
+        let retention = LogRetention {
+            max_files: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            retention.prune(&path).unwrap(),
+            vec![rotated_path(&path, 3)]
+        );
+        assert!(Path::new(&rotated_path(&path, 2)).exists());
+        assert!(Path::new(&path).exists());
+        assert!(dir.join("web.log.old").exists());
+        assert!(dir.join("api.log.3").exists());
+
+        // files older than `max_age_days` are removed even under `max_files`
+        let old = SystemTime::now() - Duration::from_secs(3 * 24 * 60 * 60);
+        File::options()
+            .write(true)
+            .open(rotated_path(&path, 2))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let retention = LogRetention {
+            max_files: Some(2),
+            max_age_days: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            retention.prune(&path).unwrap(),
+            vec![rotated_path(&path, 2)]
+        );
+        assert!(Path::new(&rotated_path(&path, 1)).exists());
+
+        // missing directories have nothing to prune
+        let missing = dir.join("missing/web.log").to_string_lossy().to_string();
+        assert!(retention.prune(&missing).unwrap().is_empty());
+    }
+}
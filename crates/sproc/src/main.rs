@@ -2,6 +2,7 @@
 use clap::{Parser, Subcommand};
 use server::APIReturn;
 use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
 
 use xsu_util::{
     fs,
@@ -15,30 +16,78 @@ use xsu_util::{
 struct Sproc {
     #[command(subcommand)]
     command: Commands,
+    /// Send the command to a remote server (`remotes.{name}`, only for `spawn`, `kill`, and `info`)
+    #[arg(long, global = true)]
+    remote: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Load configuration file
     Pin { path: String },
-    /// Run a configured service
+    /// Run a configured service (and the services it depends on)
     Run { names: Vec<String> },
     /// Spawn a service as a new task (HTTP server required: `xsu-cliff`)
     Spawn { names: Vec<String> },
+    /// Kill and re-spawn a service (HTTP server required: `xsu-cliff`)
+    Restart { names: Vec<String> },
     /// Run all services
     RunAll {},
+    /// Spawn all services that aren't running (HTTP server required: `xsu-cliff`)
+    StartAll {},
     /// Kill a running service
-    Kill { names: Vec<String> },
+    Kill {
+        names: Vec<String>,
+        /// Also kill running services that depend on the given services
+        #[arg(long)]
+        cascade: bool,
+    },
     /// Kill all services
     KillAll {},
+    /// Send a signal (HUP, USR1, USR2, ...) to a running service without stopping it
+    Signal { name: String, signal: String },
+    /// Write a line to a running service's stdin (HTTP server required: `xsu-cliff`)
+    Send { name: String, line: String },
     /// Get information about a running service
-    Info { name: String },
+    Info {
+        name: String,
+        /// How to print the service's info
+        #[arg(long, value_enum, default_value_t = OutputFormat::Toml)]
+        format: OutputFormat,
+    },
     /// Get information about all services
-    InfoAll {},
+    InfoAll {
+        /// How to print each service's info
+        #[arg(long, value_enum, default_value_t = OutputFormat::Toml)]
+        format: OutputFormat,
+    },
+    /// Print the end of a service's log file
+    Logs {
+        name: String,
+        /// How many bytes to print from the end of the file
+        #[arg(long, default_value_t = 8192)]
+        bytes: u64,
+        /// How many lines to print from the end of the file (instead of `--bytes`)
+        #[arg(long)]
+        lines: Option<usize>,
+        /// Keep printing lines as they're written (HTTP server required: `xsu-cliff`)
+        #[arg(short, long)]
+        follow: bool,
+    },
     /// Wait for service to stop and update its state accordingly
     Track { name: String },
     /// View pinned config
     Pinned {},
+    /// Make the server re-read the pinned config (HTTP server required: `xsu-cliff`)
+    Reload {},
+    /// Check the pinned config (or the given file) for problems
+    Check { path: Option<String> },
+    /// Look for common problems with the pinned config, its services, and the server
+    Doctor {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Merge services from given file into **source** configuration file (unpinned file)
     Merge { path: String },
     /// Pull services from given file into **pinned** configuration file (use `merge` for unpinned)
@@ -50,21 +99,194 @@ enum Commands {
     },
     /// "Uninstall" services given their names
     Uninstall { names: Vec<String> },
+    /// Cross-reference service states with real OS processes
+    Ps {
+        /// Kill orphaned processes and mark states without a process as stopped
+        #[arg(long)]
+        reconcile: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the audit trail of operations and configuration changes
+    Audit {
+        /// Only show entries for this service
+        #[arg(long)]
+        service: Option<String>,
+        /// Only show entries at or after this Unix timestamp (milliseconds)
+        #[arg(long)]
+        since: Option<u128>,
+        /// Only show the last N entries
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Print the order services would be started in (services on the same line start in parallel)
+    StartOrder {
+        /// Print the layers as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Export services as a Docker Compose file
+    ExportCompose {
+        /// File to write to (printed if not provided)
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Archive the pinned configuration and audit log for moving to another machine
+    ExportState {
+        /// The archive to write (`.tar.gz`)
+        #[arg(long)]
+        output: String,
+        /// Keep secrets (the server key and tokens, webhook secrets, remote keys, and services'
+        /// env files) in the archive
+        #[arg(long)]
+        include_secrets: bool,
+    },
+    /// Import an archive created by `export-state`
+    ImportState {
+        path: String,
+        /// Only add services that don't already exist (default)
+        #[arg(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Replace the configuration and audit log entirely
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Clean up stale runtime artifacts
+    Gc {
+        /// Print stale artifacts without removing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Manage starting the daemon (`sprocd`) on boot
+    Boot {
+        #[command(subcommand)]
+        command: BootCommands,
+    },
+    /// Manage webhooks
+    Webhooks {
+        #[command(subcommand)]
+        command: WebhooksCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum BootCommands {
+    /// Install the daemon as a system service
+    Install {
+        /// Install for all users (requires root)
+        #[arg(long, conflicts_with = "user")]
+        system: bool,
+        /// Install for the current user (default)
+        #[arg(long)]
+        user: bool,
+        /// Print the service file without installing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Uninstall the daemon system service
+    Uninstall {
+        /// Uninstall the service installed for all users
+        #[arg(long, conflicts_with = "user")]
+        system: bool,
+        /// Uninstall the service installed for the current user (default)
+        #[arg(long)]
+        user: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum WebhooksCommands {
+    /// Send a test event to the given URL (signed with its configured secret)
+    Test {
+        url: String,
+        /// Secret to sign the event with if the URL isn't configured
+        #[arg(long)]
+        secret: Option<String>,
+    },
 }
 
 // ...
+pub mod archive;
+pub mod audit;
+pub mod boot;
+pub mod client;
+pub mod doctor;
+pub mod events;
+pub mod health;
+pub mod limits;
+pub mod logs;
+pub mod metrics;
 pub mod model;
+pub mod remote;
+pub mod scheduler;
 pub mod server;
+pub mod snapshot;
+pub mod webhook;
 
-use model::{Service, ServiceState, ServiceType, ServicesConfiguration};
+use audit::{AuditAction, AuditContext, AuditEntry, AuditFilter};
+use client::Client;
+
+use model::{
+    config_path, home_dir, module_dir, ConfigFormat, OutputFormat, Service, ServiceType,
+    ServicesConfiguration, SprocError,
+};
+use webhook::{ServiceEventKind, Webhook, WebhookPayload};
+
+/// Print a warning for every problem found by [`ServicesConfiguration::validate`]
+fn warn_invalid(services: &ServicesConfiguration) {
+    for error in services.validate() {
+        println!("warn: {error}");
+    }
+}
 
 // real main
 async fn sproc<'a>() -> Result<&'a str> {
     // init
     let args = Sproc::parse();
 
+    // run pre-config check
+    if let Some(ref command) = ServicesConfiguration::get_bootstrap().pre_config_check {
+        ServicesConfiguration::run_pre_config_check(command)?;
+    }
+
     // get current config
-    let mut services = ServicesConfiguration::get_config();
+    let mut services = ServicesConfiguration::try_get_config()?;
+
+    // send to remote
+    if let Some(ref remote) = args.remote {
+        let client = Client::from_remote(services.remote(remote)?);
+
+        match &args.command {
+            Commands::Spawn { names } => {
+                for name in names {
+                    client.start(name).await?;
+                }
+            }
+            Commands::Kill { names, .. } => {
+                for name in names {
+                    client.kill(name).await?;
+                }
+            }
+            Commands::Info { name, format } => {
+                println!("{}", format.render_info(&client.info(name).await?.current));
+            }
+            Commands::Signal { name, signal } => {
+                client.signal(name, signal).await?;
+            }
+            Commands::Send { name, line } => {
+                client.write_stdin(name, &format!("{line}\n")).await?;
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Only \"spawn\", \"kill\", \"info\", \"signal\", and \"send\" can be sent to a remote.",
+                ))
+            }
+        }
+
+        return Ok("Sent all requested requests.");
+    }
 
     // ...
     match &args.command {
@@ -72,15 +294,8 @@ async fn sproc<'a>() -> Result<&'a str> {
         Commands::Pin { path } => {
             match fs::read(path) {
                 Ok(s) => {
-                    // make sure no services are running
-                    for service in services.service_states {
-                        if service.1 .0 == ServiceState::Running {
-                            return Err(Error::new(ErrorKind::Other, "Cannot pin config with active service. Please run \"sproc kill-all\""));
-                        }
-                    }
-
                     // ...
-                    let mut config: ServicesConfiguration = toml::from_str(&s).unwrap();
+                    let mut config = ServicesConfiguration::parse_file(path, &s)?;
 
                     // set source to absolute path
                     config.source = fs::canonicalize(path)?
@@ -89,8 +304,19 @@ async fn sproc<'a>() -> Result<&'a str> {
                         .unwrap()
                         .to_string();
 
+                    ServicesConfiguration::modify(|current| {
+                        // make sure no services are running
+                        for service in &current.service_states {
+                            if service.1 .0.is_running() {
+                                return Err(Error::new(ErrorKind::Other, "Cannot pin config with active service. Please run \"sproc kill-all\""));
+                            }
+                        }
+
+                        *current = config;
+                        Ok(())
+                    })??;
+
                     // return
-                    ServicesConfiguration::update_config(config)?;
                     Ok("Services loaded.")
                 }
                 Err(e) => Err(e),
@@ -105,27 +331,32 @@ async fn sproc<'a>() -> Result<&'a str> {
                 ));
             }
 
-            for name in names {
-                match services.services.get(name) {
-                    Some(_) => {
-                        let mut process = Service::run(name.to_string(), services.clone())?;
+            warn_invalid(&services);
 
-                        // if this is an application, wait for it to close and then continue
-                        if process.0.r#type == ServiceType::Application {
-                            process.1.wait()?;
-                            continue; // we must continue so we don't try to add the service pid
-                        }
+            for name in names {
+                // template instances (`{template}@{instance}`)
+                if services.instantiate(name)? {
+                    services = ServicesConfiguration::modify(|config| {
+                        config.instantiate(name).map(|_| config.clone())
+                    })??;
+                }
 
-                        // ...
-                        services
-                            .service_states
-                            .insert(name.to_string(), (ServiceState::Running, process.1.id()));
-                    }
-                    None => return Err(Error::new(ErrorKind::NotFound, "Service does not exist.")),
+                if !services.services.contains_key(name) {
+                    return Err(Error::new(ErrorKind::NotFound, "Service does not exist."));
                 }
+
+                // the states of dependencies that did start are kept even if this fails
+                let res = Service::start_with_deps(name, &mut services).await;
+                audit::record(AuditEntry::new(
+                    AuditContext::default(),
+                    AuditAction::Start,
+                    Some(name.to_string()),
+                    res.is_ok(),
+                ))?;
+
+                res?;
             }
 
-            ServicesConfiguration::update_config(services)?;
             Ok("Started all requested services.")
         }
         // spawn
@@ -137,33 +368,34 @@ async fn sproc<'a>() -> Result<&'a str> {
                 ));
             }
 
-            // post request
-            let client = reqwest::Client::new();
+            warn_invalid(&services);
 
+            // post requests
+            for name in names {
+                // template instances are created by the server
+                if !services.services.contains_key(name) && !services.clone().instantiate(name)? {
+                    return Err(Error::new(ErrorKind::NotFound, "Service does not exist."));
+                }
+
+                Client::from_config(&services.server)?.start(name).await?;
+            }
+
+            Ok("Sent all requested requests.")
+        }
+        // restart
+        Commands::Restart { names } => {
+            if names.len() == 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Please provide at least 1 service name.",
+                ));
+            }
+
+            // post requests
             for name in names {
                 match services.services.get(name) {
                     Some(_) => {
-                        match client
-                            .post(format!(
-                                "http://localhost:{}/api/sproc/start",
-                                services.server.port
-                            ))
-                            .body(format!(
-                                "{{ \"service\":\"{}\",\"key\":\"{}\" }}",
-                                name, services.server.key
-                            ))
-                            .header("Content-Type", "application/json")
-                            .send()
-                            .await
-                        {
-                            Ok(r) => {
-                                let res = r.text().await.expect("Failed to read body");
-                                println!("info: body: {}", res);
-                            }
-                            Err(e) => {
-                                return Err(Error::new(ErrorKind::NotConnected, e.to_string()))
-                            }
-                        }
+                        Client::from_config(&services.server)?.restart(name).await?;
                     }
                     None => return Err(Error::new(ErrorKind::NotFound, "Service does not exist.")),
                 }
@@ -173,8 +405,16 @@ async fn sproc<'a>() -> Result<&'a str> {
         }
         // runall
         Commands::RunAll {} => {
-            for service in &services.services {
-                let mut process = Service::run(service.0.to_string(), services.clone())?;
+            warn_invalid(&services);
+
+            let order: Vec<String> = services
+                .simulate_start_order()?
+                .into_iter()
+                .flatten()
+                .collect();
+
+            for name in order {
+                let mut process = Service::run(name.clone(), services.clone())?;
 
                 // if this is an application, immediately exit
                 if process.0.r#type == ServiceType::Application {
@@ -182,18 +422,47 @@ async fn sproc<'a>() -> Result<&'a str> {
                     continue;
                 }
 
-                // ...
-                services.service_states.insert(
-                    service.0.to_string(),
-                    (ServiceState::Running, process.1.id()),
-                );
+                // wait for one-shot services to finish
+                if process.0.r#type == ServiceType::Oneshot {
+                    let status = process.1.wait()?;
+                    let pid = process.1.id();
+
+                    services = ServicesConfiguration::modify(|config| {
+                        config.record_exit(&name, status, pid);
+                        config.clone()
+                    })?;
+
+                    if !status.success() {
+                        return Err(SprocError::OneshotFailed(name, status.code()).into());
+                    }
+
+                    continue;
+                }
+
+                // wait for it to be ready before starting the services after it
+                process.0.wait_until_ready(&name, &mut process.1).await?;
+
+                let pid = process.1.id();
+                services = ServicesConfiguration::modify(|config| {
+                    config.set_running(&name, pid);
+                    config.clone()
+                })?;
             }
 
-            ServicesConfiguration::update_config(services)?;
             Ok("Started all services.")
         }
+        // startall
+        Commands::StartAll {} => {
+            for (name, result) in Client::from_config(&services.server)?.start_all().await? {
+                if let Some(error) = result.error {
+                    println!("warn: {name}: {error}");
+                }
+            }
+
+            Ok("Sent request.")
+        }
         // kill
-        Commands::Kill { names } => {
+        Commands::Kill { names, cascade } => {
             if names.len() == 0 {
                 return Err(Error::new(
                     ErrorKind::InvalidInput,
@@ -204,63 +473,135 @@ async fn sproc<'a>() -> Result<&'a str> {
             for name in names {
                 match services.services.get(name) {
                     Some(_) => {
-                        Service::kill(name.to_string(), services.clone())?;
-                        services.service_states.remove(name);
+                        // stop dependents first
+                        let dependents = services.running_dependents(name);
+
+                        if !dependents.is_empty() && !cascade {
+                            return Err(Error::new(
+                                ErrorKind::Other,
+                                format!(
+                                    "Service has running dependents ({}). Please pass \"--cascade\" to kill them too.",
+                                    dependents.join(", ")
+                                ),
+                            ));
+                        }
+
+                        for dependent in dependents {
+                            Service::kill(dependent.clone(), services.clone())?;
+                            services = ServicesConfiguration::modify(|config| {
+                                config.service_states.remove(&dependent);
+                                config.clone()
+                            })?;
+                        }
+
+                        let res = Service::kill(name.to_string(), services.clone());
+                        audit::record(AuditEntry::new(
+                            AuditContext::default(),
+                            AuditAction::Kill,
+                            Some(name.to_string()),
+                            res.is_ok(),
+                        ))?;
+
+                        res?;
+                        services = ServicesConfiguration::modify(|config| {
+                            config.service_states.remove(name);
+                            config.clone()
+                        })?;
                     }
                     None => return Err(Error::new(ErrorKind::NotFound, "Service does not exist.")),
                 }
             }
 
             // return
-            ServicesConfiguration::update_config(services.clone())?;
             Ok("Stopped all given services.")
         }
+        // signal
+        Commands::Signal { name, signal } => {
+            let res = Service::signal(name.to_string(), signal);
+            audit::record(AuditEntry::new(
+                AuditContext::default(),
+                AuditAction::Signal,
+                Some(name.to_string()),
+                res.is_ok(),
+            ))?;
+
+            res?;
+            Ok("Sent signal.")
+        }
+        // send
+        Commands::Send { name, line } => {
+            Client::from_config(&services.server)?
+                .write_stdin(name, &format!("{line}\n"))
+                .await?;
+
+            Ok("Sent line.")
+        }
         // kill-all
         Commands::KillAll {} => {
-            for service in &services.services {
-                if let Err(e) = Service::kill(service.0.to_string(), services.clone()) {
-                    println!("warn: {}", e.to_string());
+            for (name, result) in Service::kill_all().await? {
+                if let Some(error) = result.error {
+                    println!("warn: {name}: {error}");
                 }
-
-                // if we couldn't get the pid then the service probably ran and exited already
-                services.service_states.remove(service.0);
             }
 
             // return
-            ServicesConfiguration::update_config(services)?;
             Ok("Stopped all services.")
         }
         // info
-        Commands::Info { name } => match services.service_states.get(name) {
+        Commands::Info { name, format } => match services.service_states.get(name) {
             Some(_) => {
-                println!(
-                    "{}",
-                    Service::info(name.to_string(), services.service_states.clone())?
-                );
-
+                let info = Service::info(name.to_string(), services.service_states.clone())?;
+                println!("{}", format.render_info(&info));
                 Ok("Finished.")
             }
             None => Err(Error::new(ErrorKind::NotFound, "Service is not loaded.")),
         },
         // info-all
-        Commands::InfoAll {} => {
-            for service in &services.service_states {
-                if let Ok(i) = Service::info(service.0.to_string(), services.service_states.clone())
-                {
-                    println!("{i}");
-                }
-            }
+        Commands::InfoAll { format } => {
+            println!("{}", format.render_infos(&services.info_all().services));
 
             // return
             Ok("Finished.")
         }
+        // logs
+        Commands::Logs {
+            name,
+            bytes,
+            lines,
+            follow,
+        } => match services.services.get(name) {
+            Some(_) if *follow => {
+                Client::from_config(&services.server)?
+                    .logs(name, lines.unwrap_or(200), true, |line| println!("{line}"))
+                    .await?;
+
+                Ok("Finished.")
+            }
+            Some(service) => match service.log_path(name) {
+                Some(path) => {
+                    match lines {
+                        Some(lines) => {
+                            for line in logs::read_lines(&path, *lines)?.0 {
+                                println!("{line}");
+                            }
+                        }
+                        None => print!("{}", model::read_tail(&path, *bytes)?),
+                    }
+
+                    Ok("Finished.")
+                }
+                None => Err(Error::new(ErrorKind::NotFound, "Service has no log file.")),
+            },
+            None => Err(Error::new(ErrorKind::NotFound, "Service does not exist.")),
+        },
         // track
         Commands::Track { name } => match services.services.get(name) {
             Some(_) => {
                 Service::observe(name.to_string(), services.service_states.clone()).await?;
 
-                services.service_states.remove(name);
-                ServicesConfiguration::update_config(services)?;
+                ServicesConfiguration::modify(|config| {
+                    config.service_states.remove(name);
+                })?;
 
                 // return
                 Ok("Service stopped.")
@@ -272,16 +613,74 @@ async fn sproc<'a>() -> Result<&'a str> {
             println!("{}", toml::to_string_pretty(&services).unwrap());
             Ok("Finished.")
         }
+        // reload
+        Commands::Reload {} => {
+            let report = Client::from_config(&services.server)?.reload().await?;
+            println!("{}", toml::to_string_pretty(&report).unwrap());
+            Ok("Sent request.")
+        }
+        // check
+        Commands::Check { path } => {
+            let config = match path {
+                Some(path) => ServicesConfiguration::try_read_file(path)?,
+                None => services,
+            };
+
+            let report = config.check();
+
+            for warning in &report.warnings {
+                println!("warn: {warning}");
+            }
+
+            for error in &report.errors {
+                println!("error: {error}");
+            }
+
+            if !report.errors.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Configuration has {} error(s).", report.errors.len()),
+                ));
+            }
+
+            Ok("Configuration is valid.")
+        }
+        // doctor
+        Commands::Doctor { json } => {
+            // the server's version (if it's running)
+            let server = match Client::from_config(&services.server) {
+                Ok(client) => client.health().await.ok(),
+                Err(_) => None,
+            };
+            let report = Service::doctor(server.as_ref().map(|h| h.version.as_str()))?;
+
+            if *json {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            } else {
+                for finding in &report.findings {
+                    println!("warn: {finding}");
+                }
+            }
+
+            if !report.findings.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("Found {} problem(s).", report.findings.len()),
+                ));
+            }
+
+            Ok("No problems found.")
+        }
         // merge
         Commands::Merge { path } => {
             // read file
-            let other_config = ServicesConfiguration::read(std::fs::read_to_string(path)?);
+            let other_config = ServicesConfiguration::try_read_file(path)?;
 
-            // merge and write
+            // merge and write (in the source file's format)
             services.merge_config(other_config);
             std::fs::write(
                 services.source.clone(),
-                toml::to_string_pretty(&services).unwrap(),
+                ConfigFormat::from_path(Path::new(&services.source)).serialize(&services),
             )?;
 
             // return
@@ -290,11 +689,10 @@ async fn sproc<'a>() -> Result<&'a str> {
         // pull
         Commands::Pull { path } => {
             // read file
-            let other_config = ServicesConfiguration::read(std::fs::read_to_string(path)?);
+            let other_config = ServicesConfiguration::try_read_file(path)?;
 
             // merge and write
-            services.merge_config(other_config);
-            ServicesConfiguration::update_config(services)?;
+            ServicesConfiguration::modify(|config| config.merge_config(other_config))?;
 
             // return
             Ok("Pulled configuration. (pinned + other)")
@@ -328,7 +726,8 @@ async fn sproc<'a>() -> Result<&'a str> {
                         }
 
                         // add service
-                        let home = std::env::var("HOME").expect("failed to read $HOME");
+                        let home = home_dir().expect("failed to read $HOME");
+                        let home = home.to_string_lossy();
                         let mut service: Service = match toml::from_str(&res.data) {
                             Ok(s) => s,
                             Err(e) => {
@@ -343,13 +742,14 @@ async fn sproc<'a>() -> Result<&'a str> {
                         service.working_directory = service.working_directory.replace("~", &home);
 
                         // make build dir exact
-                        service.working_directory = service.working_directory.replace(
-                            "@",
-                            &format!("{home}/.config/xsu-apps/sproc/modules/{name}"),
-                        );
+                        service.working_directory = service
+                            .working_directory
+                            .replace("@", &module_dir(name).to_string_lossy());
 
                         // push service
-                        services.services.insert(name.to_owned(), service);
+                        ServicesConfiguration::modify(|config| {
+                            config.services.insert(name.to_owned(), service);
+                        })?;
 
                         // log
                         println!("info: installed service to pinned file: {}", name);
@@ -358,7 +758,6 @@ async fn sproc<'a>() -> Result<&'a str> {
                 }
             }
 
-            ServicesConfiguration::update_config(services.clone())?;
             Ok("Sent all requested requests.")
         }
         // uninstall
@@ -377,19 +776,227 @@ async fn sproc<'a>() -> Result<&'a str> {
                 }
 
                 // remove directory
-                let home = std::env::var("HOME").expect("failed to read $HOME");
-                if let Ok(_) = fs::read_dir(format!("{home}/.config/xsu-apps/sproc/modules/{name}"))
-                {
-                    fs::remove_dir_all(format!("{home}/.config/xsu-apps/sproc/modules/{name}"))?
+                let dir = module_dir(name);
+                if dir.is_dir() {
+                    std::fs::remove_dir_all(dir)?
                 }
 
                 // remove service
-                services.services.remove(name);
+                ServicesConfiguration::modify(|config| {
+                    config.services.remove(name);
+                })?;
             }
 
-            ServicesConfiguration::update_config(services.clone())?;
             Ok("Finished.")
         }
+        // ps
+        Commands::Ps { reconcile, json } => {
+            let report = services.ps();
+
+            if *json {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            } else {
+                println!("SERVICES");
+                println!(
+                    "{:<24} {:<8} {:<8} {:<6} {:<12} {}",
+                    "NAME", "STATE", "PID", "ALIVE", "MEMORY", "CPU"
+                );
+
+                for p in &report.tracked {
+                    println!(
+                        "{:<24} {:<8} {:<8} {:<6} {:<12} {:.1}",
+                        p.name,
+                        format!("{:?}", p.state),
+                        p.pid,
+                        p.alive,
+                        p.memory,
+                        p.cpu
+                    );
+                }
+
+                println!("\nORPHANS");
+                println!(
+                    "{:<24} {:<8} {:<16} {}",
+                    "SERVICE", "PID", "EXECUTABLE", "WORKING DIRECTORY"
+                );
+
+                for p in &report.orphans {
+                    println!(
+                        "{:<24} {:<8} {:<16} {}",
+                        p.name, p.pid, p.executable, p.working_directory
+                    );
+                }
+            }
+
+            if *reconcile {
+                services.reconcile(&report)?;
+                return Ok("Reconciled services.");
+            }
+
+            Ok("Finished.")
+        }
+        // audit
+        Commands::Audit {
+            service,
+            since,
+            limit,
+        } => {
+            for entry in audit::read(AuditFilter {
+                service: service.to_owned(),
+                since: since.to_owned(),
+                limit: limit.to_owned(),
+            })? {
+                println!("{}", serde_json::to_string(&entry).unwrap());
+            }
+
+            Ok("Finished.")
+        }
+        // start-order
+        Commands::StartOrder { json } => {
+            let layers = services.simulate_start_order()?;
+
+            if *json {
+                println!("{}", serde_json::to_string(&layers).unwrap());
+            } else {
+                for (i, layer) in layers.iter().enumerate() {
+                    println!("{}: {}", i + 1, layer.join(", "));
+                }
+            }
+
+            Ok("Finished.")
+        }
+        // export-compose
+        Commands::ExportCompose { output } => {
+            let compose = services.as_docker_compose();
+
+            match output {
+                Some(path) => {
+                    std::fs::write(path, compose)?;
+                    Ok("Exported services.")
+                }
+                None => {
+                    println!("{compose}");
+                    Ok("Finished.")
+                }
+            }
+        }
+        // export-state
+        Commands::ExportState {
+            output,
+            include_secrets,
+        } => {
+            archive::export_state(output, *include_secrets)?;
+            Ok("Exported state.")
+        }
+        // import-state
+        Commands::ImportState { path, replace, .. } => {
+            let mode = if *replace {
+                archive::ImportMode::Replace
+            } else {
+                archive::ImportMode::Merge
+            };
+
+            for warning in archive::import_state(path, mode)? {
+                println!("warn: {warning}");
+            }
+
+            Ok("Imported state.")
+        }
+        // gc
+        Commands::Gc { dry_run } => {
+            let artifacts = match dry_run {
+                true => services.gc(true)?,
+                false => ServicesConfiguration::modify(|config| config.gc(false))??,
+            };
+
+            for artifact in artifacts {
+                println!("{}: {artifact}", if *dry_run { "stale" } else { "removed" });
+            }
+
+            if *dry_run {
+                return Ok("Finished. (dry run)");
+            }
+
+            Ok("Finished.")
+        }
+        // boot
+        Commands::Boot { command } => {
+            let home = home_dir().expect("failed to read $HOME");
+            let home = home.to_string_lossy();
+            let config = config_path();
+            let config = config.to_string_lossy();
+            let platform = boot::Platform::current();
+
+            match command {
+                BootCommands::Install {
+                    system, dry_run, ..
+                } => {
+                    let scope = if *system {
+                        boot::BootScope::System
+                    } else {
+                        boot::BootScope::User
+                    };
+
+                    let file =
+                        boot::generate(platform, scope, &boot::daemon_path()?, &home, &config);
+
+                    if *dry_run {
+                        println!("# {}\n{}", file.path, file.content);
+                        return Ok("Finished. (dry run)");
+                    }
+
+                    boot::install(platform, scope, &file)?;
+                    Ok("Installed daemon service.")
+                }
+                BootCommands::Uninstall { system, .. } => {
+                    let scope = if *system {
+                        boot::BootScope::System
+                    } else {
+                        boot::BootScope::User
+                    };
+
+                    let file =
+                        boot::generate(platform, scope, &boot::daemon_path()?, &home, &config);
+                    boot::uninstall(platform, scope, &file)?;
+                    Ok("Uninstalled daemon service.")
+                }
+            }
+        }
+        // webhooks
+        Commands::Webhooks { command } => match command {
+            WebhooksCommands::Test { url, secret } => {
+                let configured = services
+                    .webhooks
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|w| &w.url == url);
+
+                let webhook = match configured {
+                    Some(w) => w,
+                    None => match secret {
+                        Some(secret) => Webhook {
+                            url: url.to_owned(),
+                            secret: secret.to_owned(),
+                            events: vec![ServiceEventKind::Test],
+                        },
+                        None => return Err(Error::new(
+                            ErrorKind::NotFound,
+                            "Webhook is not configured. Please provide a secret with \"--secret\".",
+                        )),
+                    },
+                };
+
+                webhook
+                    .deliver(&WebhookPayload::new(
+                        "sproc".to_string(),
+                        ServiceEventKind::Test,
+                        None,
+                    ))
+                    .await?;
+
+                Ok("Test event delivered.")
+            }
+        },
     }
 }
 
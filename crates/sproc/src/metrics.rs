@@ -0,0 +1,86 @@
+//! Sproc Prometheus metrics (`GET /metrics`)
+use std::fmt::Write;
+
+use crate::model::ServicesConfiguration;
+
+/// Service gauges: (name, help text)
+const GAUGES: [(&str, &str); 5] = [
+    (
+        "sproc_service_up",
+        "Whether the service is running (1) or not (0)",
+    ),
+    (
+        "sproc_service_memory_bytes",
+        "Memory used by the service process",
+    ),
+    (
+        "sproc_service_cpu_percent",
+        "CPU usage of the service process",
+    ),
+    (
+        "sproc_service_restarts",
+        "Consecutive automatic restarts of the service",
+    ),
+    (
+        "sproc_service_uptime_seconds",
+        "How long the service process has been running",
+    ),
+];
+
+/// Escape a label value (`\`, `"`, and newlines)
+fn escape(value: &str) -> String {
+    value
+        .replace("\\", "\\\\")
+        .replace("\"", "\\\"")
+        .replace("\n", "\\n")
+}
+
+/// Render every service's gauges in the Prometheus text format
+pub fn render(config: &ServicesConfiguration) -> String {
+    let summary = config.summary();
+    let mut out = String::new();
+
+    for (i, (metric, help)) in GAUGES.iter().enumerate() {
+        writeln!(out, "# HELP {metric} {help}").unwrap();
+        writeln!(out, "# TYPE {metric} gauge").unwrap();
+
+        for service in &summary.services {
+            let value = match i {
+                0 => (service.state.is_running() as u8).to_string(),
+                1 => service
+                    .info
+                    .as_ref()
+                    .map(|i| i.memory)
+                    .unwrap_or(0)
+                    .to_string(),
+                2 => service
+                    .info
+                    .as_ref()
+                    .map(|i| i.cpu)
+                    .unwrap_or(0.0)
+                    .to_string(),
+                3 => config
+                    .restart_counts
+                    .get(&service.name)
+                    .copied()
+                    .unwrap_or(0)
+                    .to_string(),
+                _ => service
+                    .info
+                    .as_ref()
+                    .map(|i| i.running_for_seconds)
+                    .unwrap_or(0)
+                    .to_string(),
+            };
+
+            writeln!(
+                out,
+                "{metric}{{service=\"{}\"}} {value}",
+                escape(&service.name)
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
@@ -1,16 +1,354 @@
 //! Sproc process management (service handling)
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     env,
-    io::{BufRead, BufReader, Error, ErrorKind, Result},
-    process::{Child, Command, Stdio},
+    io::{BufRead, BufReader, Error, ErrorKind, Result, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex, MutexGuard, TryLockError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use sysinfo::{Pid, System};
+use fs2::FileExt;
+use sysinfo::{Pid, Process, ProcessStatus, Signal, System};
 use xsu_util::fs;
 
+use crate::audit::{self, AuditAction, AuditContext, AuditEntry};
+use crate::doctor::{self, DoctorReport};
+use crate::health::{self, HealthCheck, HealthCheckKind};
+use crate::limits::{self, ResourceLimits};
+use crate::logs::{self, LogFormat, LogRetention};
+use crate::remote::Remote;
+use crate::scheduler::{self, ScheduleState};
+use crate::snapshot;
+use crate::webhook::{self, ServiceEventKind, Webhook, WebhookPayload};
+
 pub type ServiceStates = HashMap<String, (ServiceState, u32)>;
 
+/// Get the current Unix timestamp (milliseconds)
+pub fn epoch_timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+// platform
+
+/// Read an environment variable as a path, unless it's unset or empty
+fn env_path(key: &str) -> Option<PathBuf> {
+    env::var_os(key)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Get the current user's home directory (`$HOME`, or `%USERPROFILE%` on Windows)
+pub fn home_dir() -> Option<PathBuf> {
+    match env_path("HOME") {
+        Some(home) => Some(home),
+        None if cfg!(windows) => env_path("USERPROFILE"),
+        None => None,
+    }
+}
+
+/// Get the directory user configuration is kept in
+///
+/// `$XDG_CONFIG_HOME` if it's set, then `%APPDATA%` on Windows or `$HOME/.config` elsewhere.
+pub fn config_home() -> PathBuf {
+    if let Some(dir) = env_path("XDG_CONFIG_HOME") {
+        return dir;
+    }
+
+    #[cfg(windows)]
+    if let Some(dir) = env_path("APPDATA") {
+        return dir;
+    }
+
+    home_dir().expect("failed to read $HOME").join(".config")
+}
+
+/// Get the directory built services (`modules`) and registry files are kept in
+/// (`xsu-apps/sproc` in [`config_home`])
+pub fn data_dir() -> PathBuf {
+    config_home().join("xsu-apps").join("sproc")
+}
+
+/// Get the directory a service's build steps are run in (`modules/{name}` in [`data_dir`])
+pub fn module_dir(name: &str) -> PathBuf {
+    data_dir().join("modules").join(name)
+}
+
+/// Get the program and arguments a command is run through the shell with (`sh -c`, or
+/// `cmd /C` on Windows)
+pub fn shell_args(command: &str) -> Vec<String> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    vec![shell.to_string(), flag.to_string(), command.to_string()]
+}
+
+/// Create a [`Command`] that runs `command` through the shell (see [`shell_args`])
+pub fn shell_command(command: &str) -> Command {
+    let args = shell_args(command);
+    let mut cmd = Command::new(&args[0]);
+    cmd.args(&args[1..]);
+    cmd
+}
+
+/// If services can be started in their own process group (see [`new_process_group`])
+pub const PROCESS_GROUPS: bool = cfg!(any(unix, windows));
+
+/// Start a command in its own process group, so the whole group can be stopped with
+/// [`signal_group`]
+#[cfg(unix)]
+pub fn new_process_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+}
+
+/// Start a command in its own process group (`CREATE_NEW_PROCESS_GROUP`), so it doesn't
+/// receive the console's Ctrl+C and can be stopped along with its children by
+/// [`signal_group`]
+#[cfg(windows)]
+pub fn new_process_group(cmd: &mut Command) {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn new_process_group(_: &mut Command) {}
+
+/// Get the path of the pinned configuration file
+///
+/// Checked in order: `$SPROC_CONFIG`, then `xsu-apps/sproc/services.toml` in [`config_home`]
+/// (`$XDG_CONFIG_HOME`, `%APPDATA%` on Windows, or `$HOME/.config`).
+pub fn config_path() -> PathBuf {
+    if let Some(path) = env_path("SPROC_CONFIG") {
+        return path;
+    }
+
+    config_home()
+        .join("xsu-apps")
+        .join("sproc")
+        .join("services.toml")
+}
+
+/// Get the directory the pinned configuration file (and other runtime files) are kept in
+pub fn sproc_dir() -> PathBuf {
+    match config_path().parent() {
+        Some(p) => p.to_path_buf(),
+        None => PathBuf::from("."),
+    }
+}
+
+/// Get the path of the runtime state file (`state.toml` in [`sproc_dir`])
+pub fn state_path() -> PathBuf {
+    sproc_dir().join("state.toml")
+}
+
+/// Get the path of a file's backup copy (`{path}.bak`)
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// Write a file atomically
+///
+/// Contents are written to `{path}.tmp` and synced to disk before being renamed over `path`, so
+/// readers never see a partially written file.
+pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&tmp, path)?;
+
+    // sync the directory too, so the rename itself survives a crash
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = std::fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the path of a service's PID file (`pids/{name}.pid` in [`sproc_dir`])
+pub fn pid_path(name: &str) -> PathBuf {
+    sproc_dir().join("pids").join(format!("{name}.pid"))
+}
+
+/// Get the directory a service's default log files are kept in (`logs/{name}` in [`sproc_dir`])
+pub fn log_dir(name: &str) -> PathBuf {
+    sproc_dir().join("logs").join(name)
+}
+
+/// The size (in bytes) at which log files are rotated if `max_log_bytes` (or `logging.max_size`)
+/// isn't set
+pub const DEFAULT_MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Read up to the last `bytes` bytes of a file
+pub fn read_tail(path: &str, bytes: u64) -> Result<String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(bytes)))?;
+
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+/// Split a command into words like a shell would
+///
+/// Words are separated by whitespace. Single quotes keep everything inside them, double quotes
+/// keep everything but `\"` and `\\` escapes, and a backslash outside of quotes escapes the
+/// next character.
+pub fn split_command(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                format!("Unterminated single quote. ({command})"),
+                            ))
+                        }
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => {
+                                return Err(Error::new(
+                                    ErrorKind::InvalidInput,
+                                    format!("Unterminated double quote. ({command})"),
+                                ))
+                            }
+                        },
+                        Some(c) => word.push(c),
+                        None => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                format!("Unterminated double quote. ({command})"),
+                            ))
+                        }
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    if words.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Command is empty."));
+    }
+
+    Ok(words)
+}
+
+/// Replace `${VAR}` in a string with `VAR` from the current environment (empty if it isn't set)
+pub fn expand_env(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find("}") {
+            Some(e) => start + e,
+            None => break,
+        };
+
+        out.push_str(&rest[..start]);
+        out.push_str(&env::var(&rest[start + 2..end]).unwrap_or_default());
+        rest = &rest[end + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Parse a dotenv file (`KEY=value` lines, `#` comments, optional `export` and quotes)
+pub fn read_env_file(contents: &str) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.split_once("=") {
+            Some(kv) => kv,
+            None => continue,
+        };
+
+        let value = value.trim();
+        let value = match (value.chars().next(), value.chars().last()) {
+            (Some('"'), Some('"')) | (Some('\''), Some('\'')) if value.len() >= 2 => {
+                &value[1..value.len() - 1]
+            }
+            _ => value,
+        };
+
+        vars.insert(key.trim().to_string(), value.to_string());
+    }
+
+    vars
+}
+
 /// [`Service`] metadata/extra information that isn't needed to run the service
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ServiceMetadata {
@@ -26,7 +364,7 @@ pub struct ServiceMetadata {
     /// Source license
     #[serde(default)]
     pub license: String,
-    /// Service build steps run in `~/.config/xsu-apps/sproc/modules/:name`
+    /// Service build steps run in `~/.config/xsu-apps/sproc/modules/:name` (see [`module_dir`])
     #[serde(default)]
     pub build: Vec<String>,
 }
@@ -43,6 +381,145 @@ impl Default for ServiceMetadata {
     }
 }
 
+/// Parse a signal name (`"SIGHUP"`, `"HUP"`, or `"hup"`)
+pub fn parse_signal(name: &str) -> Result<Signal> {
+    let name = name.to_uppercase();
+
+    Ok(match name.trim_start_matches("SIG") {
+        "TERM" => Signal::Term,
+        "INT" => Signal::Interrupt,
+        "QUIT" => Signal::Quit,
+        "HUP" => Signal::Hangup,
+        "USR1" => Signal::User1,
+        "USR2" => Signal::User2,
+        "KILL" => Signal::Kill,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown signal. ({name})"),
+            ))
+        }
+    })
+}
+
+/// Send a signal to every process in a process group (`pgid`), returning if it was sent
+#[cfg(unix)]
+pub fn signal_group(pgid: Pid, signal: Signal) -> bool {
+    let number = match signal {
+        Signal::Term => libc::SIGTERM,
+        Signal::Interrupt => libc::SIGINT,
+        Signal::Quit => libc::SIGQUIT,
+        Signal::Hangup => libc::SIGHUP,
+        Signal::User1 => libc::SIGUSR1,
+        Signal::User2 => libc::SIGUSR2,
+        _ => libc::SIGKILL,
+    };
+
+    unsafe { libc::kill(-(pgid.as_u32() as libc::pid_t), number) == 0 }
+}
+
+/// Stop a process and every process it started (`taskkill /T`), returning if it was stopped
+///
+/// Only `Kill` (forcefully) and `Term`/`Interrupt` (by asking its windows to close, which
+/// console programs ignore) are supported.
+#[cfg(windows)]
+pub fn signal_group(pgid: Pid, signal: Signal) -> bool {
+    let pid = pgid.as_u32().to_string();
+    let mut args = vec!["/PID", &pid, "/T"];
+
+    match signal {
+        Signal::Kill => args.push("/F"),
+        Signal::Term | Signal::Interrupt => (),
+        _ => return false,
+    }
+
+    Command::new("taskkill")
+        .args(&args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn signal_group(_: Pid, _: Signal) -> bool {
+    false
+}
+
+/// Get a user's ID and primary group ID from its name (or ID)
+#[cfg(unix)]
+pub fn lookup_user(user: &str) -> Result<(u32, Option<u32>)> {
+    let name = std::ffi::CString::new(user)?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+
+    let id = user.parse::<u32>().ok();
+    let res = unsafe {
+        match id {
+            Some(uid) => libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            ),
+            None => libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            ),
+        }
+    };
+
+    if res != 0 || result.is_null() {
+        // numeric IDs don't need to exist
+        return match id {
+            Some(uid) => Ok((uid, None)),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("User does not exist. ({user})"),
+            )),
+        };
+    }
+
+    Ok((passwd.pw_uid, Some(passwd.pw_gid)))
+}
+
+/// Get a group's ID from its name (or ID)
+#[cfg(unix)]
+pub fn lookup_group(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+
+    let name = std::ffi::CString::new(group)?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+
+    let res = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    if res != 0 || result.is_null() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Group does not exist. ({group})"),
+        ));
+    }
+
+    Ok(entry.gr_gid)
+}
+
 /// [`Service`] type
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum ServiceType {
@@ -50,6 +527,11 @@ pub enum ServiceType {
     Service,
     /// A service that does not run in the background and does not track PID
     Application,
+    /// A service that is expected to exit (e.g. migrations or backups)
+    ///
+    /// `sproc run` waits for it to exit, and it's never restarted.
+    #[serde(alias = "oneshot")]
+    Oneshot,
 }
 
 impl Default for ServiceType {
@@ -64,583 +546,6045 @@ pub struct Service {
     /// What the type of the service is
     #[serde(default)]
     pub r#type: ServiceType,
-    /// What command is run to start the service
+    /// What command is run to start the service (split into arguments like a shell would)
     pub command: String,
+    /// Run `command` through `sh -c` instead of splitting it
+    #[serde(default)]
+    pub shell: bool,
     /// Where the `command` is run
     pub working_directory: String,
-    /// Environment variables map
+    /// Environment variables map (`${VAR}` is replaced with `VAR` from sproc's environment)
     pub environment: Option<HashMap<String, String>>,
+    /// A dotenv file (relative to `working_directory`) loaded before `environment`
+    #[serde(default)]
+    pub env_file: Option<String>,
     /// If the service should restart automatically when exited (HTTP server required)
     #[serde(default)]
     pub restart: bool,
+    /// If the service should be started when the server starts (and when it's added by a
+    /// configuration reload)
+    #[serde(default)]
+    pub autostart: bool,
     /// Metadata
     #[serde(default)]
     pub metadata: ServiceMetadata,
+    /// A condition that must be met before the service is considered running (HTTP server required)
+    #[serde(default)]
+    pub wait_for: Option<WaitCondition>,
+    /// How long to wait for `wait_for` before treating the start as failed (30 by default)
+    #[serde(default = "wait_timeout_seconds_default")]
+    pub wait_timeout_seconds: u64,
+    /// The names of services that must be started before this one
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// File that the service's stdout and stderr are appended to
+    /// (`logs/{name}/{name}.log` next to the pinned configuration by default)
+    #[serde(default)]
+    pub log_file: Option<String>,
+    /// Rotate `log_file` (into `{log_file}.1`) when it gets larger than this many bytes
+    /// (`logging.max_size` by default)
+    #[serde(default)]
+    pub max_log_bytes: Option<u64>,
+    /// How lines are written to `log_file` (`annotated` prefixes each line with a timestamp and
+    /// its stream, `raw` by default)
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Give up restarting after this many consecutive quick exits (unlimited by default)
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+    /// The delay before the first restart, multiplied by `restart_backoff_factor` for each
+    /// consecutive quick exit (1 by default)
+    #[serde(default)]
+    pub restart_delay_secs: Option<u64>,
+    /// How much the restart delay grows after each consecutive quick exit (2 by default)
+    #[serde(default)]
+    pub restart_backoff_factor: Option<u64>,
+    /// The longest delay between restarts (300 by default)
+    #[serde(default)]
+    pub restart_delay_max_secs: Option<u64>,
+    /// How long the service must run before its consecutive exit count is reset (60 by default)
+    #[serde(default)]
+    pub stable_after_secs: Option<u64>,
+    /// Command run (and waited for) before the service starts; the service isn't started if it
+    /// fails (see `hook_failure`)
+    #[serde(default)]
+    pub pre_start: Option<String>,
+    /// Command run once the service is running (HTTP server required); the service is killed if
+    /// it fails (see `hook_failure`)
+    #[serde(default)]
+    pub post_start: Option<String>,
+    /// Command run (and waited for) before the service is stopped by sproc
+    #[serde(default)]
+    pub pre_stop: Option<String>,
+    /// Command run after the service exits (HTTP server required)
+    #[serde(default, alias = "post_exit")]
+    pub post_stop: Option<String>,
+    /// How long a hook can run before it's killed and fails (30 by default)
+    #[serde(default)]
+    pub hook_timeout_seconds: Option<u64>,
+    /// What happens when `pre_start` or `post_start` fails (`"abort"` by default)
+    #[serde(default)]
+    pub hook_failure: HookFailurePolicy,
+    /// The signal sent to stop the service before it is killed (`"SIGTERM"` by default)
+    #[serde(default)]
+    pub stop_signal: Option<String>,
+    /// How long to wait for the service to exit after `stop_signal` before killing it (10 by default)
+    #[serde(default)]
+    pub stop_timeout_seconds: Option<u64>,
+    /// A check run against the service while it's running (HTTP server required)
+    #[serde(default)]
+    pub healthcheck: Option<HealthCheck>,
+    /// Where the service's stdin comes from (inherited by default)
+    #[serde(default)]
+    pub stdin_mode: Option<StdioMode>,
+    /// Where the service's stdout goes (`log_file`, or inherited, by default)
+    #[serde(default)]
+    pub stdout_mode: Option<StdioMode>,
+    /// Where the service's stderr goes (`log_file`, or inherited, by default)
+    #[serde(default)]
+    pub stderr_mode: Option<StdioMode>,
+    /// Keep the service's stdin open so commands can be written to it (`POST /stdin`) when it's
+    /// spawned by the server (true by default, ignored if `stdin_mode` is set)
+    #[serde(default)]
+    pub accept_stdin: Option<bool>,
+    /// A cron expression the service is run on (HTTP server required)
+    ///
+    /// Standard 5-field expressions (`min hour day month weekday`, in UTC) are accepted, as well
+    /// as 6 and 7-field expressions starting with seconds.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Resource limits applied when the service is started
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>,
+    /// The user (name or ID) the service and its hooks are run as (sproc must be running as root)
+    #[serde(default)]
+    pub user: Option<String>,
+    /// The group (name or ID) the service and its hooks are run as (`user`'s primary group by
+    /// default)
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Start the service in its own process group and stop the whole group, so processes it
+    /// starts are stopped with it (true by default, Unix and Windows)
+    #[serde(default)]
+    pub process_group: Option<bool>,
+}
+
+/// What happens when a [`Service`]'s `pre_start` or `post_start` hook fails
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HookFailurePolicy {
+    /// Don't start the service (or kill it, for `post_start`)
+    #[default]
+    Abort,
+    /// Log the failure and keep going
+    Warn,
+}
+
+/// Where a [`Service`]'s stdin, stdout, or stderr is connected
+///
+/// Written as `"inherit"`, `"null"`, or `{ file = "/path/to/file" }`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StdioMode {
+    /// Use sproc's own stream
+    Inherit,
+    /// Discard output (or give no input)
+    Null,
+    /// Append to (or read from) the given file, created if it doesn't exist
+    File(String),
+}
+
+/// Sent once a service started by [`Service::spawn_task`] is ready (or fails to start)
+type ReadySender = tokio::sync::oneshot::Sender<std::result::Result<(), SprocError>>;
+
+/// Receivers for the exit statuses of supervised processes, by PID
+type ChildExits = HashMap<u32, tokio::sync::oneshot::Receiver<Result<ExitStatus>>>;
+
+/// Exit statuses of the processes spawned by this process, sent once they exit
+static CHILDREN: Mutex<Option<ChildExits>> = Mutex::new(None);
+
+/// Stdin of the supervised processes that accept it (`accept_stdin`), by PID
+///
+/// Each handle has its own lock, so writing to one service never holds up the registry.
+static STDIN: Mutex<Option<HashMap<u32, Arc<Mutex<ChildStdin>>>>> = Mutex::new(None);
+
+/// Files (and their lengths when the process was spawned) watched for `wait_for` log lines, by PID
+///
+/// Stdout written to a file is read back from it, since a pipe would close along with the
+/// process that started the service.
+static STDOUT_FILES: Mutex<Option<HashMap<u32, (String, u64)>>> = Mutex::new(None);
+
+/// How long [`Service::write_stdin`] waits for a service to accept what's written
+pub const STDIN_WRITE_TIMEOUT_SECS: u64 = 10;
+
+/// How long [`Service::start_all`] waits after spawning a group of services before spawning the
+/// services that depend on them
+pub const DEPENDENCY_READY_DELAY_SECS: u64 = 1;
+
+/// How old the configuration's backup (`services.toml.bak`) can get before
+/// [`ServicesConfiguration::gc`] removes it
+pub const BACKUP_MAX_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+
+fn wait_timeout_seconds_default() -> u64 {
+    30
+}
+
+/// A condition a [`Service`] must meet before it is considered running
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum WaitCondition {
+    /// A TCP connection to the given port (on localhost) succeeds
+    Port(u16),
+    /// A line of the service's stdout matches the given regex
+    LogLine { pattern: String },
+    /// The given file path exists
+    File(String),
+    /// The given number of seconds has passed
+    Seconds(u64),
 }
 
 impl Service {
     /// Spawn service process
-    pub fn run(name: String, config: ServicesConfiguration) -> Result<(Service, Child)> {
+    pub fn run(
+        name: String,
+        config: ServicesConfiguration,
+    ) -> std::result::Result<(Service, Child), SprocError> {
+        Service::run_with_stdin(name, config, false)
+    }
+
+    /// Spawn service process, piping its stdin (if it accepts stdin, see `accept_stdin`) so it
+    /// can be kept by [`Service::supervise`]
+    ///
+    /// The pipe is closed when the returned [`Child`] is dropped (unless it's supervised), which
+    /// some services treat as a request to exit.
+    pub fn run_with_stdin(
+        name: String,
+        config: ServicesConfiguration,
+        pipe_stdin: bool,
+    ) -> std::result::Result<(Service, Child), SprocError> {
         // check current state
         if let Some(s) = config.service_states.get(&name) {
             // make sure service isn't already running
-            if s.0 == ServiceState::Running {
-                return Err(Error::new(
-                    ErrorKind::AlreadyExists,
-                    format!("Service is already running. ({name})"),
-                ));
+            if s.0.is_running() {
+                return Err(SprocError::AlreadyRunning(name, None));
             }
         };
 
-        let service = match config.services.get(&name) {
-            Some(s) => s,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Service does not exist. ({name})"),
-                ))
-            }
+        // make sure the service wasn't started by another sproc
+        if let Some(pid) = Service::pid_file_process(&name) {
+            return Err(SprocError::AlreadyRunning(name, Some(pid)));
+        }
+
+        let mut service = match config.services.get(&name) {
+            Some(s) => s.to_owned(),
+            None => return Err(SprocError::ServiceNotFound(name)),
         };
 
-        // create command
-        println!("info: cmd: {}", service.command);
-        let command_split: Vec<&str> = service.command.split(" ").collect();
-        let mut cmd = Command::new(command_split.get(0).unwrap());
+        // resolve the default log file so everything given this service writes to it
+        service.log_file = service.log_path(&name);
+
+        let environment = service.effective_environment(&config.environment)?;
 
-        for arg in command_split.iter().skip(1) {
-            cmd.arg(arg);
+        // make sure `wait_for` can be checked before anything is run
+        if let Some(WaitCondition::LogLine { ref pattern }) = service.wait_for {
+            if let Err(e) = regex::Regex::new(pattern) {
+                return Err(SprocError::Io(Error::new(
+                    ErrorKind::InvalidInput,
+                    e.to_string(),
+                )));
+            }
         }
 
-        if let Some(env) = service.environment.clone() {
-            for var in env {
-                cmd.env(var.0, var.1);
+        // run pre-start hook
+        if let Some(ref hook) = service.pre_start {
+            if let Err(e) = service.run_hook_logged(&name, hook, &environment, true) {
+                return Err(SprocError::HookFailed(name, e));
             }
         }
 
+        // create command
+        let args = service.command_args()?;
+        let mut cmd = Command::new(&args[0]);
+        cmd.args(&args[1..]);
+
+        cmd.envs(&environment);
         cmd.current_dir(&service.working_directory);
 
-        // spawn
-        Ok((service.to_owned(), cmd.spawn()?))
-    }
+        // redirect streams (rotating the log file first if it's too large)
+        if let Some(ref path) = service.log_file {
+            config
+                .logging
+                .rotate(path, service.max_log_size(&config.logging), false)?;
+        }
 
-    /// Kill service process
-    pub fn kill(name: String, config: ServicesConfiguration) -> Result<()> {
-        let s = match config.service_states.get(&name) {
-            Some(s) => s,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Service is not loaded. ({name})"),
-                ))
-            }
+        let log = service.open_log()?;
+        let watch_stdout = matches!(service.wait_for, Some(WaitCondition::LogLine { .. }));
+
+        // annotated lines are written to the log file by sproc
+        let annotate = service.log_format == LogFormat::Annotated;
+        let annotate_stdout = annotate && service.stdout_mode.is_none();
+        let annotate_stderr = annotate && service.stderr_mode.is_none();
+
+        // watched stdout is read back from its file, or piped and forwarded in
+        // `wait_until_ready` if it doesn't have one
+        let watched_file = match watch_stdout {
+            true => service.stdout_file().map(|path| {
+                (
+                    path.clone(),
+                    std::fs::metadata(&path).map_or(0, |m| m.len()),
+                )
+            }),
+            false => None,
         };
+        let forward_watched = watch_stdout && watched_file.is_none();
 
-        if s.0 != ServiceState::Running {
-            return Err(Error::new(
-                ErrorKind::NotConnected,
-                "Service is not running.",
-            ));
+        if pipe_stdin && service.stdin_mode.is_none() && service.accept_stdin.unwrap_or(true) {
+            cmd.stdin(Stdio::piped());
+        } else {
+            cmd.stdin(service.stdio(&service.stdin_mode, &None)?);
         }
 
-        let mut config_c = config.clone();
-        let service = match config_c.services.get_mut(&name) {
-            Some(s) => s,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Service does not exist. ({name})"),
-                ))
-            }
-        };
+        if forward_watched || annotate_stdout {
+            // watched stdout is forwarded in `wait_until_ready`, annotated stdout after spawning
+            cmd.stdout(Stdio::piped());
+        } else {
+            cmd.stdout(service.stdio(&service.stdout_mode, &log)?);
+        }
 
-        // stop service
-        let sys = System::new_all();
+        if annotate_stderr {
+            cmd.stderr(Stdio::piped());
+        } else {
+            cmd.stderr(service.stdio(&service.stderr_mode, &log)?);
+        }
 
-        match sys.process(Pid::from(s.1 as usize)) {
-            Some(process) => {
-                let supposed_to_restart = service.restart.clone();
+        if let Some(ref limits) = service.limits {
+            limits.apply_rlimits(&mut cmd);
+        }
 
-                // if service is supposed to restart, toggle off and update config
-                if supposed_to_restart {
-                    // we must do this so threads that will restart this service don't
-                    service.restart = false;
-                    ServicesConfiguration::update_config(config_c.clone())?;
-                }
+        if let Err(e) = service.apply_credentials(&mut cmd) {
+            return Err(SprocError::SpawnFailed(name, e));
+        }
 
-                // kill process
-                process.kill();
-                std::thread::sleep(std::time::Duration::from_secs(1)); // wait for 1s so the server can catch up
+        if PROCESS_GROUPS && service.process_group.unwrap_or(true) {
+            new_process_group(&mut cmd);
+        }
 
-                // if service was previously supposed to restart, re-enable restart
-                if supposed_to_restart {
-                    // set config back to original form
-                    ServicesConfiguration::update_config(config.clone())?;
-                }
+        // spawn
+        let mut child = match cmd.spawn() {
+            Ok(c) => c,
+            Err(e) => return Err(SprocError::SpawnFailed(name, e)),
+        };
 
-                // return
-                Ok(())
+        if let Some(file) = watched_file {
+            STDOUT_FILES
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get_or_insert_with(HashMap::new)
+                .insert(child.id(), file);
+        }
+
+        if annotate_stdout && !forward_watched {
+            if let Some(stdout) = child.stdout.take() {
+                logs::forward(
+                    stdout,
+                    name.clone(),
+                    "stdout",
+                    service.log_format,
+                    service.open_log()?,
+                    |_| (),
+                );
             }
-            None => Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Failed to get process from PID. ({name})"),
-            )),
         }
-    }
 
-    /// Get service process info
-    pub fn info(name: String, service_states: ServiceStates) -> Result<String> {
-        let s = match service_states.get(&name) {
-            Some(s) => s,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Service is not loaded. ({name})"),
-                ))
+        if annotate_stderr {
+            if let Some(stderr) = child.stderr.take() {
+                logs::forward(
+                    stderr,
+                    name.clone(),
+                    "stderr",
+                    service.log_format,
+                    service.open_log()?,
+                    |_| (),
+                );
             }
-        };
+        }
 
-        if s.0 != ServiceState::Running {
-            return Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Service is not running. ({name})"),
-            ));
+        if let Some(ref limits) = service.limits {
+            if let Err(e) = limits.apply_cgroup(&name, child.id()) {
+                println!("warn: failed to apply cgroup limits: {e} ({name})");
+            }
         }
 
-        // get service info
-        let sys = System::new_all();
+        ServiceEntry::new(&name, ServiceState::Running, Some(child.id()))
+            .with_log_file(service.log_file.clone())
+            .log_event(
+                ServiceEventKind::Started,
+                HashMap::from([("command".to_string(), service.command.clone())]),
+            );
 
-        if let Some(process) = sys.process(Pid::from(s.1 as usize)) {
-            let info = ServiceInfo {
-                name: name.to_string(),
-                pid: process.pid().to_string().parse().unwrap(),
-                memory: process.memory(),
-                cpu: process.cpu_usage(),
-                status: process.status().to_string(),
+        Ok((service, child))
+    }
+
+    /// Run a service after running its dependencies (`depends_on`, recursively) that aren't
+    /// already running, recording their states (`config` is updated to the written configuration)
+    ///
+    /// Each service is waited on until it's ready (`wait_for`) before the next one is started.
+    /// Returns the names of the services that were started.
+    pub async fn start_with_deps(
+        name: &str,
+        config: &mut ServicesConfiguration,
+    ) -> std::result::Result<Vec<String>, SprocError> {
+        let mut started = Vec::new();
+
+        for service in config.dependency_order(name)? {
+            if service != name {
+                // skip dependencies that are already running
+                if let Some(s) = config.service_states.get(&service) {
+                    if s.0.is_running() {
+                        continue;
+                    }
+                }
+            }
+
+            let mut process = Service::run(service.clone(), config.clone())?;
+
+            // if this is an application, wait for it to close and then continue
+            if process.0.r#type == ServiceType::Application {
+                process.1.wait()?;
+                continue;
+            }
+
+            // one-shot services must finish (successfully) before anything that depends on them
+            if process.0.r#type == ServiceType::Oneshot {
+                let status = process.1.wait()?;
+                let pid = process.1.id();
+
+                *config = ServicesConfiguration::modify(|config| {
+                    config.record_exit(&service, status, pid);
+                    config.clone()
+                })
+                .map_err(SprocError::ConfigIo)?;
+
+                if !status.success() {
+                    return Err(SprocError::OneshotFailed(service, status.code()));
+                }
+
+                continue;
+            }
+
+            // don't start anything that depends on it until it's ready
+            process.0.wait_until_ready(&service, &mut process.1).await?;
+
+            let pid = process.1.id();
+            *config = ServicesConfiguration::modify(|config| {
+                config.set_running(&service, pid);
+                config.clone()
+            })
+            .map_err(SprocError::ConfigIo)?;
+
+            started.push(service);
+        }
+
+        Ok(started)
+    }
+
+    /// Kill service process
+    ///
+    /// The service is added to `stopping` (in [`RuntimeState`]) so its observer doesn't restart it.
+    pub fn kill(
+        name: String,
+        config: ServicesConfiguration,
+    ) -> std::result::Result<(), SprocError> {
+        let s = match config.service_states.get(&name) {
+            Some(s) => s,
+            None => return Err(SprocError::NotLoaded(name)),
+        };
+
+        if !s.0.is_running() {
+            return Err(SprocError::NotRunning(name));
+        }
+
+        let service = match config.services.get(&name) {
+            Some(s) => s,
+            None => return Err(SprocError::ServiceNotFound(name)),
+        };
+
+        // stop service
+        let signal = service.stop_signal()?;
+        let found = snapshot::with_processes(&[s.1], |sys| {
+            verified_process(sys, s.1, config.fingerprints.get(&name)).is_some()
+        });
+
+        match found {
+            true => {
+                // mark it as stopping so the observer doesn't restart it (the mark is cleared
+                // by the observer, or when the service is started again)
+                if service.restarts() {
+                    ServicesConfiguration::modify(|config| {
+                        config.stopping.insert(name.clone());
+                    })
+                    .map_err(SprocError::ConfigIo)?;
+                }
+
+                // run pre-stop hook
+                if let Some(ref hook) = service.pre_stop {
+                    let _ = service.effective_environment(&config.environment).and_then(
+                        |environment| service.run_hook_logged(&name, hook, &environment, false),
+                    );
+                }
+
+                // kill process
+                service.stop(Pid::from(s.1 as usize), signal);
+                std::thread::sleep(std::time::Duration::from_secs(1)); // wait for 1s so the server can catch up
+
+                Service::remove_pid_file(&name);
+
+                // return
+                Ok(())
+            }
+            false => Err(SprocError::ProcessNotFound(name)),
+        }
+    }
+
+    /// [`Service::kill`] on the blocking thread pool, for async callers
+    ///
+    /// Killing waits for the process to exit (up to `stop_timeout_seconds`), which would
+    /// otherwise hold up a runtime worker.
+    pub async fn kill_task(
+        name: String,
+        config: ServicesConfiguration,
+    ) -> std::result::Result<(), SprocError> {
+        match tokio::task::spawn_blocking(move || Service::kill(name, config)).await {
+            Ok(res) => res,
+            Err(e) => Err(SprocError::Io(Error::new(ErrorKind::Other, e.to_string()))),
+        }
+    }
+
+    /// Send a signal (`HUP`, `USR1`, `USR2`, ...) to a running service without stopping it,
+    /// so it can reload its configuration or reopen its log files
+    ///
+    /// The signal is sent to the service's process group if `process_group` is set (like
+    /// [`Service::stop`]).
+    pub fn signal(name: String, signal: &str) -> std::result::Result<(), SprocError> {
+        let signal = parse_signal(signal)?;
+        let config = ServicesConfiguration::try_get_config()?;
+
+        let s = match config.service_states.get(&name) {
+            Some(s) => s,
+            None => return Err(SprocError::NotLoaded(name)),
+        };
+
+        if !s.0.is_running() {
+            return Err(SprocError::NotRunning(name));
+        }
+
+        let service = match config.services.get(&name) {
+            Some(s) => s,
+            None => return Err(SprocError::ServiceNotFound(name)),
+        };
+
+        // send signal
+        let group = PROCESS_GROUPS && service.process_group.unwrap_or(true);
+        let sent = snapshot::with_processes(&[s.1], |sys| {
+            match verified_process(sys, s.1, config.fingerprints.get(&name)) {
+                Some(_) if group => Some(signal_group(Pid::from(s.1 as usize), signal)),
+                Some(process) => Some(process.kill_with(signal).unwrap_or(false)),
+                None => None,
+            }
+        });
+
+        match sent {
+            Some(true) => Ok(()),
+            Some(false) => Err(SprocError::Io(Error::new(
+                ErrorKind::Unsupported,
+                format!("Failed to send signal. ({name})"),
+            ))),
+            None => Err(SprocError::ProcessNotFound(name)),
+        }
+    }
+
+    /// Write `data` to a running service's stdin (as it is, include a newline to end a command)
+    ///
+    /// Only services spawned by this process that accept stdin (`accept_stdin`) can be written to.
+    /// Fails if the service doesn't accept the write within [`STDIN_WRITE_TIMEOUT_SECS`] (its
+    /// pipe is full), or a previous write to it hasn't finished yet.
+    pub async fn write_stdin(name: String, data: &str) -> std::result::Result<(), SprocError> {
+        let config = ServicesConfiguration::try_get_config()?;
+
+        let s = match config.service_states.get(&name) {
+            Some(s) => s,
+            None => return Err(SprocError::NotLoaded(name)),
+        };
+
+        if !s.0.is_running() {
+            return Err(SprocError::NotRunning(name));
+        }
+
+        let stdin = STDIN
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert_with(HashMap::new)
+            .get(&s.1)
+            .cloned();
+
+        let stdin = match stdin {
+            Some(stdin) => stdin,
+            None => return Err(SprocError::StdinUnavailable(name)),
+        };
+
+        // write (the pipe blocks once it's full)
+        let data = data.as_bytes().to_vec();
+        let busy = format!("Previous write to service stdin has not finished. ({name})");
+
+        let write = tokio::task::spawn_blocking(move || {
+            let mut stdin = match stdin.try_lock() {
+                Ok(s) => s,
+                Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                Err(TryLockError::WouldBlock) => {
+                    return Err(Error::new(ErrorKind::WouldBlock, busy))
+                }
+            };
+
+            stdin.write_all(&data)?;
+            stdin.flush()
+        });
+
+        let timeout = Duration::from_secs(STDIN_WRITE_TIMEOUT_SECS);
+
+        match tokio::time::timeout(timeout, write).await {
+            Ok(Ok(res)) => Ok(res?),
+            Ok(Err(e)) => Err(SprocError::Io(Error::new(ErrorKind::Other, e.to_string()))),
+            Err(_) => Err(SprocError::Io(Error::new(
+                ErrorKind::TimedOut,
+                format!("Service is not reading its stdin. ({name})"),
+            ))),
+        }
+    }
+
+    /// Get service process info
+    pub fn info(
+        name: String,
+        service_states: ServiceStates,
+    ) -> std::result::Result<ServiceInfo, SprocError> {
+        let pids: Vec<u32> = match service_states.get(&name) {
+            Some(s) if s.0.is_running() => vec![s.1],
+            _ => Vec::new(),
+        };
+
+        let mut config = ServicesConfiguration::try_get_config()?;
+        config.service_states = service_states;
+
+        snapshot::with_processes(&pids, |sys| Service::info_from(name, &config, sys))
+    }
+
+    /// Get service process info from a [`System`] snapshot (which should include the service's
+    /// process)
+    pub fn info_from(
+        name: String,
+        config: &ServicesConfiguration,
+        sys: &System,
+    ) -> std::result::Result<ServiceInfo, SprocError> {
+        let s = match config.service_states.get(&name) {
+            Some(s) => s,
+            None => return Err(SprocError::NotLoaded(name)),
+        };
+
+        let log_file = config.services.get(&name).and_then(|s| s.log_path(&name));
+        let last_exit = config.last_exits.get(&name).cloned();
+
+        // completed one-shot services have no process
+        if let ServiceState::Completed(_) = s.0 {
+            return Ok(ServiceInfo {
+                name: name.to_string(),
+                pid: s.1,
+                memory: 0,
+                cpu: 0.0,
+                status: "Completed".to_string(),
+                running_for_seconds: 0,
+                log_file,
+                last_exit_code: Some(0),
+                last_exit,
+            });
+        }
+
+        // failed services report their exit code
+        if let ServiceState::Failed(code, _) = s.0 {
+            return Ok(ServiceInfo {
+                name: name.to_string(),
+                pid: s.1,
+                memory: 0,
+                cpu: 0.0,
+                status: "Failed".to_string(),
+                running_for_seconds: 0,
+                log_file,
+                last_exit_code: Some(code),
+                last_exit,
+            });
+        }
+
+        if !s.0.is_running() {
+            return Err(SprocError::NotRunning(name));
+        }
+
+        // get service info
+        if let Some(process) = verified_process(sys, s.1, config.fingerprints.get(&name)) {
+            let info = ServiceInfo {
+                name: name.to_string(),
+                pid: process.pid().to_string().parse().unwrap(),
+                memory: process.memory(),
+                cpu: process.cpu_usage(),
+                status: process.status().to_string(),
                 running_for_seconds: process.run_time(),
+                log_file,
+                last_exit_code: None,
+                last_exit,
             };
 
-            Ok(toml::to_string_pretty(&info).unwrap())
+            Ok(info)
         } else {
-            Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Failed to get process from PID. ({name})"),
-            ))
+            Err(SprocError::ProcessNotFound(name))
+        }
+    }
+
+    /// Convert service into a Docker Compose service definition
+    ///
+    /// Sproc doesn't manage images, so `image` is always `NONE` and must be filled in.
+    pub fn as_docker_compose_service(&self, _service_name: &str) -> serde_yaml::Value {
+        use serde_yaml::{Mapping, Value};
+        let mut map = Mapping::new();
+
+        map.insert("image".into(), "NONE".into());
+        map.insert(
+            "command".into(),
+            Value::Sequence(
+                self.command_args()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|a| a.into())
+                    .collect(),
+            ),
+        );
+        map.insert("working_dir".into(), self.working_directory.as_str().into());
+
+        if let Some(ref env) = self.environment {
+            let mut environment = Mapping::new();
+            let mut vars: Vec<(&String, &String)> = env.iter().collect();
+            vars.sort();
+
+            for (key, value) in vars {
+                environment.insert(key.as_str().into(), value.as_str().into());
+            }
+
+            map.insert("environment".into(), Value::Mapping(environment));
+        }
+
+        if self.restart {
+            map.insert("restart".into(), "always".into());
         }
+
+        Value::Mapping(map)
     }
 
     // exit handling
 
-    /// Wait for a service process to stop and update its state when it does
-    pub async fn observe(name: String, service_states: ServiceStates) -> Result<()> {
+    /// Wait for a spawned process in the background, keeping its exit status for
+    /// [`Service::observe`] (and its stdin, if it was piped, for [`Service::write_stdin`])
+    pub fn supervise(mut child: Child) -> u32 {
+        let pid = child.id();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+
+        CHILDREN
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert_with(HashMap::new)
+            .insert(pid, receiver);
+
+        if let Some(stdin) = child.stdin.take() {
+            STDIN
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get_or_insert_with(HashMap::new)
+                .insert(pid, Arc::new(Mutex::new(stdin)));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let status = child.wait();
+
+            STDIN
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get_or_insert_with(HashMap::new)
+                .remove(&pid);
+
+            let _ = sender.send(status);
+        });
+
+        pid
+    }
+
+    /// Wait for a service process to stop, returning its exit status
+    ///
+    /// Processes spawned by this process (through [`Service::supervise`]) are awaited directly.
+    /// Others (e.g. spawned by a previous server) are checked every second until they exit,
+    /// and their exit status isn't known.
+    pub async fn observe(
+        name: String,
+        service_states: ServiceStates,
+    ) -> std::result::Result<Option<ExitStatus>, SprocError> {
         let s = match service_states.get(&name) {
             Some(s) => s,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Service is not loaded. ({name})"),
-                ))
+            None => return Err(SprocError::NotLoaded(name)),
+        };
+
+        if !s.0.is_running() {
+            return Err(SprocError::NotRunning(name));
+        }
+
+        // spawned by this process
+        let child = CHILDREN
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert_with(HashMap::new)
+            .remove(&s.1);
+
+        if let Some(exit) = child {
+            return match exit.await {
+                Ok(status) => Ok(Some(status?)),
+                Err(_) => Ok(None),
+            };
+        }
+
+        // spawned by something else
+        let fingerprints = ServicesConfiguration::try_get_config()?.fingerprints;
+        let fingerprint = fingerprints.get(&name);
+        let pid = Pid::from(s.1 as usize);
+        let mut sys = System::new();
+
+        if !sys.refresh_process(pid) || verified_process(&sys, s.1, fingerprint).is_none() {
+            return Err(SprocError::ProcessNotFound(name));
+        }
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            if !sys.refresh_process(pid) || verified_process(&sys, s.1, fingerprint).is_none() {
+                return Ok(None);
             }
+        }
+    }
+
+    // pid files
+
+    /// Write a service's PID file, creating the `pids` directory if needed
+    pub fn write_pid_file(name: &str, pid: u32) -> Result<()> {
+        let path = pid_path(name);
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        std::fs::write(path, pid.to_string())
+    }
+
+    /// Remove a service's PID file (if it exists)
+    pub fn remove_pid_file(name: &str) {
+        let _ = std::fs::remove_file(pid_path(name));
+    }
+
+    /// Get the PID in a service's PID file if that process is still running
+    pub fn pid_file_process(name: &str) -> Option<u32> {
+        let pid: u32 = std::fs::read_to_string(pid_path(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+
+        snapshot::load(&[pid])
+            .process(Pid::from(pid as usize))
+            .map(|_| pid)
+    }
+
+    /// Get the program and arguments the service is run with
+    pub fn command_args(&self) -> Result<Vec<String>> {
+        if self.shell {
+            return Ok(shell_args(&self.command));
+        }
+
+        split_command(&self.command)
+    }
+
+    /// Get the name of the service's executable (nothing for `shell` services)
+    pub fn executable(&self) -> Option<String> {
+        if self.shell {
+            return None;
+        }
+
+        let args = split_command(&self.command).ok()?;
+        std::path::Path::new(&args[0])
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+    }
+
+    /// Check if a process was started from the service's command (comparing the program by
+    /// file name)
+    ///
+    /// Only the executable name is compared if the process's arguments can't be read.
+    pub fn matches_process(&self, process: &Process) -> bool {
+        let args = match self.command_args() {
+            Ok(a) if !a.is_empty() => a,
+            _ => return false,
         };
 
-        if s.0 != ServiceState::Running {
-            return Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Service is not running. ({name})"),
-            ));
+        let cmd = process.cmd();
+
+        if cmd.is_empty() {
+            return self
+                .executable()
+                .map(|e| process.name() == e)
+                .unwrap_or(false);
         }
 
-        // get service
-        let sys = System::new_all();
+        let program = |p: &str| {
+            std::path::Path::new(p)
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+        };
 
-        if let Some(process) = sys.process(Pid::from(s.1 as usize)) {
-            // wait for process to stop
-            process.wait();
-            Ok(())
-        } else {
-            Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Failed to get process from PID. ({name})"),
-            ))
+        cmd.len() == args.len() && program(&cmd[0]) == program(&args[0]) && cmd[1..] == args[1..]
+    }
+
+    /// Get the path of the service's `env_file` (relative paths are in `working_directory`)
+    pub fn env_file_path(&self) -> Option<PathBuf> {
+        self.env_file
+            .as_ref()
+            .map(|path| Path::new(&self.working_directory).join(path))
+    }
+
+    /// Get the environment the service is run with: the global `environment`, overlaid
+    /// with the service's `env_file` and then its own `environment`
+    ///
+    /// `${VAR}` in values is replaced with `VAR` from sproc's own environment.
+    pub fn effective_environment(
+        &self,
+        global: &Option<HashMap<String, String>>,
+    ) -> Result<HashMap<String, String>> {
+        let mut environment = global.clone().unwrap_or_default();
+
+        if let Some(path) = self.env_file_path() {
+            environment.extend(read_env_file(&std::fs::read_to_string(path)?));
+        }
+
+        if let Some(ref env) = self.environment {
+            environment.extend(env.clone());
         }
+
+        for value in environment.values_mut() {
+            *value = expand_env(value);
+        }
+
+        Ok(environment)
     }
 
-    /// Start and observe a service
-    async fn wait(name: String, config: &mut ServicesConfiguration) -> Result<()> {
-        // start service
-        let process = match Service::run(name.clone(), config.clone()) {
-            Ok(p) => p,
-            Err(e) => return Err(e),
+    /// Get the signal the service is stopped with (`stop_signal`, SIGTERM by default)
+    pub fn stop_signal(&self) -> Result<Signal> {
+        match self.stop_signal {
+            Some(ref s) => parse_signal(s),
+            None => Ok(Signal::Term),
+        }
+    }
+
+    /// Send `signal` to a process (or its process group, see `process_group`) and wait up to
+    /// `stop_timeout_seconds` for it to exit, killing it if it doesn't (or if the signal isn't
+    /// supported on this platform)
+    pub fn stop(&self, pid: Pid, signal: Signal) {
+        let mut sys = snapshot::load(&[pid.as_u32()]);
+        let timeout = Duration::from_secs(self.stop_timeout_seconds.unwrap_or(10));
+        let group = PROCESS_GROUPS && self.process_group.unwrap_or(true);
+
+        let sent = match sys.process(pid) {
+            Some(_) if group => signal_group(pid, signal),
+            Some(process) => process.kill_with(signal).unwrap_or(false),
+            None => return,
         };
 
-        // update config
-        config
-            .service_states
-            .insert(name.to_string(), (ServiceState::Running, process.1.id()));
+        if sent {
+            let started = Instant::now();
 
-        ServicesConfiguration::update_config(config.clone()).expect("Failed to update config");
-        Service::observe(name.clone(), config.service_states.clone())
-            .await
-            .expect("Failed to observe service");
+            while started.elapsed() < timeout {
+                // exited processes stay around as zombies until they're waited on
+                if !sys.refresh_process(pid)
+                    || sys.process(pid).map(|p| p.status()) == Some(ProcessStatus::Zombie)
+                {
+                    break;
+                }
+
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+
+        // kill anything left in the group (including processes the service started)
+        if group {
+            signal_group(pid, Signal::Kill);
+            return;
+        }
+
+        if let Some(process) = sys.process(pid) {
+            if process.status() != ProcessStatus::Zombie {
+                process.kill();
+            }
+        }
+    }
+
+    /// Kill a process started for the service (and its process group, see `process_group`)
+    /// and wait for it to exit, for when the service fails to start
+    pub fn abort(&self, child: &mut Child) {
+        if PROCESS_GROUPS && self.process_group.unwrap_or(true) {
+            signal_group(Pid::from(child.id() as usize), Signal::Kill);
+        }
+
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    /// Run a hook command (through the shell, see [`shell_args`]) in the service's working
+    /// directory
+    pub fn run_hook(&self, hook: &str, environment: &HashMap<String, String>) -> Result<()> {
+        let mut cmd = shell_command(hook);
+        cmd.envs(environment);
+        cmd.current_dir(&self.working_directory);
+        self.apply_credentials(&mut cmd)?;
+
+        let mut child = cmd.spawn()?;
+        let timeout = Duration::from_secs(self.hook_timeout_seconds.unwrap_or(30));
+        let started = Instant::now();
+
+        loop {
+            if let Some(status) = child.try_wait()? {
+                if !status.success() {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        format!("Hook failed with {status}. ({hook})"),
+                    ));
+                }
+
+                return Ok(());
+            }
+
+            if started.elapsed() >= timeout {
+                child.kill()?;
+                child.wait()?;
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("Hook timed out after {}s. ({hook})", timeout.as_secs()),
+                ));
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Run a hook, logging its failure as an event instead of returning it if the failure is
+    /// allowed (`hook_failure`, or `fatal` isn't set)
+    pub fn run_hook_logged(
+        &self,
+        name: &str,
+        hook: &str,
+        environment: &HashMap<String, String>,
+        fatal: bool,
+    ) -> Result<()> {
+        let e = match self.run_hook(hook, environment) {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+
+        if fatal && self.hook_failure == HookFailurePolicy::Abort {
+            return Err(e);
+        }
+
+        println!("warn: {e} ({name})");
+        ServiceEntry::new(name, ServiceState::Running, None)
+            .with_log_file(self.log_path(name))
+            .log_event(
+                ServiceEventKind::Failed,
+                HashMap::from([("error".to_string(), e.to_string())]),
+            );
 
         Ok(())
     }
 
-    /// [`Service::wait`] in a new task
-    pub async fn spawn(name: String) -> Result<()> {
-        // spawn task
-        tokio::task::spawn(async move {
-            loop {
-                // pull config from file
-                let mut config = ServicesConfiguration::get_config();
+    /// Run a command as the service's `user` and `group` (if set)
+    #[cfg(unix)]
+    pub fn apply_credentials(&self, cmd: &mut Command) -> Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        let mut gid = None;
+
+        if let Some(ref user) = self.user {
+            let (uid, primary_gid) = lookup_user(user)?;
+            cmd.uid(uid);
+            gid = primary_gid;
+        }
+
+        if let Some(ref group) = self.group {
+            gid = Some(lookup_group(group)?);
+        }
+
+        if let Some(gid) = gid {
+            cmd.gid(gid);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply_credentials(&self, _: &mut Command) -> Result<()> {
+        if self.user.is_some() || self.group.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Running services as another user is not supported on this platform.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Get the delay before a restart, given the number of consecutive quick exits (from 1)
+    pub fn restart_delay(&self, failures: u32) -> Duration {
+        let base = self.restart_delay_secs.unwrap_or(1);
+        let max = self.restart_delay_max_secs.unwrap_or(300);
+        let multiplier = self
+            .restart_backoff_factor
+            .unwrap_or(2)
+            .saturating_pow(failures.saturating_sub(1));
+
+        Duration::from_secs(base.saturating_mul(multiplier).min(max))
+    }
+
+    /// Get the number of consecutive quick exits after an exit following `uptime`, given the
+    /// count before it (reset once the service stays up for `stable_after_secs`)
+    pub fn consecutive_failures(&self, failures: u32, uptime: Duration) -> u32 {
+        if uptime >= Duration::from_secs(self.stable_after_secs.unwrap_or(60)) {
+            return 1;
+        }
+
+        failures + 1
+    }
+
+    /// Get the `host:port` address the service's health check (or `wait_for` port) connects to
+    pub fn checked_address(&self) -> Option<String> {
+        if let Some(ref check) = self.healthcheck {
+            match check.check {
+                HealthCheckKind::Http { ref url, .. } => {
+                    let url = reqwest::Url::parse(url).ok()?;
+                    return Some(format!(
+                        "{}:{}",
+                        url.host_str()?,
+                        url.port_or_known_default()?
+                    ));
+                }
+                HealthCheckKind::Tcp { ref address } => return Some(address.to_owned()),
+                HealthCheckKind::Cmd { .. } => (),
+            }
+        }
+
+        match self.wait_for {
+            Some(WaitCondition::Port(port)) => Some(format!("localhost:{port}")),
+            _ => None,
+        }
+    }
+
+    /// If the service is restarted when it exits (`restart`, and not a [`ServiceType::Oneshot`])
+    pub fn restarts(&self) -> bool {
+        self.restart && self.r#type != ServiceType::Oneshot
+    }
+
+    /// Get the file the service's output is written to: `log_file`, or a file in
+    /// [`log_dir`] for background services
+    pub fn log_path(&self, name: &str) -> Option<String> {
+        if self.log_file.is_some() {
+            return self.log_file.clone();
+        }
+
+        if self.r#type == ServiceType::Application {
+            // applications keep using the terminal
+            return None;
+        }
+
+        Some(
+            log_dir(name)
+                .join(format!("{name}.log"))
+                .to_string_lossy()
+                .to_string(),
+        )
+    }
+
+    /// Get the size (in bytes) at which the service's log file is rotated
+    pub fn max_log_size(&self, logging: &LogRetention) -> u64 {
+        self.max_log_bytes
+            .or(logging.max_size)
+            .unwrap_or(DEFAULT_MAX_LOG_BYTES)
+    }
+
+    /// Get the file the service's stdout is written to, if it has one
+    fn stdout_file(&self) -> Option<String> {
+        match self.stdout_mode {
+            None => self.log_file.clone(),
+            Some(StdioMode::File(ref path)) => Some(path.clone()),
+            Some(_) => None,
+        }
+    }
+
+    /// Open the service's `log_file` for appending
+    pub fn open_log(&self) -> Result<Option<std::fs::File>> {
+        let path = match self.log_file {
+            Some(ref p) => p,
+            None => return Ok(None),
+        };
+
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() {
+                std::fs::create_dir_all(dir)?;
+            }
+        }
+
+        Ok(Some(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?,
+        ))
+    }
+
+    /// Get the [`Stdio`] for a stream's [`StdioMode`], falling back to `log` (then inheriting)
+    pub fn stdio(&self, mode: &Option<StdioMode>, log: &Option<std::fs::File>) -> Result<Stdio> {
+        let mode = match mode {
+            Some(m) => m,
+            None => match log {
+                Some(file) => return Ok(Stdio::from(file.try_clone()?)),
+                None => return Ok(Stdio::inherit()),
+            },
+        };
+
+        Ok(match mode {
+            StdioMode::Inherit => Stdio::inherit(),
+            StdioMode::Null => Stdio::null(),
+            StdioMode::File(path) => Stdio::from(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .read(true)
+                    .append(true)
+                    .open(path)?,
+            ),
+        })
+    }
+
+    /// Wait for a started service's `wait_for` condition to be met
+    ///
+    /// The process is killed (see [`Service::abort`]) if it doesn't become ready.
+    pub async fn wait_until_ready(&self, name: &str, child: &mut Child) -> Result<()> {
+        let res = self.poll_ready(name, child).await;
+
+        if res.is_err() {
+            self.abort(child);
+        }
+
+        res
+    }
+
+    /// Poll a started service's `wait_for` condition until it's met, the process exits, or
+    /// `wait_timeout_seconds` passes
+    async fn poll_ready(&self, name: &str, child: &mut Child) -> Result<()> {
+        let condition = match self.wait_for {
+            Some(ref c) => c.clone(),
+            None => return Ok(()),
+        };
+
+        let timeout = Duration::from_secs(self.wait_timeout_seconds);
+        let started = Instant::now();
+
+        // watch stdout (read back from its file, or forwarding every line)
+        let matched = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let mut lines = None;
+
+        if let WaitCondition::LogLine { ref pattern } = condition {
+            let regex = match regex::Regex::new(pattern) {
+                Ok(r) => r,
+                Err(e) => return Err(Error::new(ErrorKind::InvalidInput, e.to_string())),
+            };
+
+            let file = STDOUT_FILES
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_mut()
+                .and_then(|files| files.remove(&child.id()));
+
+            if let Some((path, offset)) = file {
+                lines = Some((logs::follow(path, offset), regex));
+            } else if let Some(stdout) = child.stdout.take() {
+                let matched = matched.clone();
+
+                let log = self.open_log()?;
+
+                let name = name.to_string();
+
+                logs::forward(stdout, name, "stdout", self.log_format, log, move |line| {
+                    if regex.is_match(line) {
+                        matched.store(true, std::sync::atomic::Ordering::SeqCst);
+                    }
+                });
+            }
+        }
+
+        // poll
+        loop {
+            let ready = match condition {
+                WaitCondition::Port(port) => {
+                    std::net::TcpStream::connect(("127.0.0.1", port)).is_ok()
+                }
+                WaitCondition::LogLine { .. } => {
+                    if let Some((ref mut receiver, ref regex)) = lines {
+                        while let Ok(line) = receiver.try_recv() {
+                            if regex.is_match(&line) {
+                                matched.store(true, std::sync::atomic::Ordering::SeqCst);
+                            }
+                        }
+                    }
+
+                    matched.load(std::sync::atomic::Ordering::SeqCst)
+                }
+                WaitCondition::File(ref path) => std::path::Path::new(path).exists(),
+                WaitCondition::Seconds(secs) => started.elapsed() >= Duration::from_secs(secs),
+            };
+
+            if ready {
+                return Ok(());
+            }
+
+            if let Some(status) = child.try_wait()? {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("Service exited before it was ready ({status})."),
+                ));
+            }
+
+            if started.elapsed() >= timeout {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "Service did not become ready in {}s.",
+                        self.wait_timeout_seconds
+                    ),
+                ));
+            }
+
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+    }
+
+    /// Start and observe a service, returning its exit status
+    ///
+    /// `ready` is sent to once the service is ready (and its `post_start` hook has run), unless
+    /// it's a one-shot service, which is only done once it exits.
+    async fn wait(
+        name: String,
+        config: &mut ServicesConfiguration,
+        ready: &mut Option<ReadySender>,
+    ) -> std::result::Result<ExitStatus, SprocError> {
+        // start service
+        let mut process = Service::run_with_stdin(name.clone(), config.clone(), true)?;
+
+        // write pid file
+        if let Err(e) = Service::write_pid_file(&name, process.1.id()) {
+            process.0.abort(&mut process.1);
+            return Err(e.into());
+        }
+
+        // wait for service to be ready (it's killed if it isn't)
+        if let Err(e) = process.0.wait_until_ready(&name, &mut process.1).await {
+            Service::remove_pid_file(&name);
+            return Err(e.into());
+        }
+
+        // update config
+        let res = ServicesConfiguration::modify(|config| {
+            config.set_running(&name, process.1.id());
+            config.clone()
+        });
+
+        *config = match res {
+            Ok(c) => c,
+            Err(e) => {
+                process.0.abort(&mut process.1);
+                Service::remove_pid_file(&name);
+                return Err(SprocError::ConfigIo(e));
+            }
+        };
+        webhook::dispatch(
+            &config.webhooks,
+            WebhookPayload::new(
+                name.clone(),
+                ServiceEventKind::Started,
+                Some(process.1.id()),
+            ),
+        );
+
+        // run post-start hook
+        if let Some(ref hook) = process.0.post_start {
+            let res = process
+                .0
+                .effective_environment(&config.environment)
+                .and_then(|environment| process.0.run_hook_logged(&name, hook, &environment, true));
+
+            if let Err(e) = res {
+                process.0.abort(&mut process.1);
+                Service::remove_pid_file(&name);
+
+                ServicesConfiguration::modify(|config| {
+                    config.service_states.remove(&name);
+                })
+                .map_err(SprocError::ConfigIo)?;
+
+                return Err(SprocError::HookFailed(name, e));
+            }
+        }
+
+        if process.0.r#type != ServiceType::Oneshot {
+            if let Some(ready) = ready.take() {
+                let _ = ready.send(Ok(()));
+            }
+        }
+
+        // enforce memory limit
+        if let Some(max) = process.0.limits.as_ref().and_then(|l| l.max_memory_bytes) {
+            tokio::task::spawn(limits::enforce(
+                name.clone(),
+                process.1.id(),
+                max,
+                process.0.log_file.clone(),
+            ));
+        }
+
+        // start health checks
+        if let Some(ref check) = process.0.healthcheck {
+            tokio::task::spawn(health::monitor(
+                name.clone(),
+                process.1.id(),
+                check.clone(),
+                process.0.log_file.clone(),
+            ));
+        }
+
+        Service::supervise(process.1);
+        let status = Service::observe(name.clone(), config.service_states.clone()).await?;
+
+        Service::remove_pid_file(&name);
+
+        // run post-stop hook
+        if let Some(ref hook) = process.0.post_stop {
+            let res = process
+                .0
+                .effective_environment(&config.environment)
+                .and_then(|environment| process.0.run_hook(hook, &environment));
+
+            if let Err(e) = res {
+                ServiceEntry::new(&name, ServiceState::Stopped, None)
+                    .with_log_file(process.0.log_file.clone())
+                    .log_event(
+                        ServiceEventKind::Failed,
+                        HashMap::from([("error".to_string(), e.to_string())]),
+                    );
+            }
+        }
+
+        status.ok_or(SprocError::ProcessNotFound(name))
+    }
+
+    /// [`Service::wait`] in a new task, starting its dependencies (`depends_on`) first
+    ///
+    /// Each dependency is waited on until it's ready (`wait_for`), or one-shot dependencies
+    /// until they've finished successfully, before the next one is started.
+    pub async fn spawn(name: String) -> std::result::Result<(), SprocError> {
+        let mut config = ServicesConfiguration::try_get_config()?;
+
+        // template instances are created when they're first started
+        if config.instantiate(&name)? {
+            config = ServicesConfiguration::modify(|config| {
+                config.instantiate(&name).map(|_| config.clone())
+            })
+            .map_err(SprocError::ConfigIo)??;
+        }
+
+        for dependency in config.dependency_order(&name)? {
+            if dependency == name {
+                continue;
+            }
+
+            // skip dependencies that are already running
+            if let Some(s) = config.service_states.get(&dependency) {
+                if s.0.is_running() {
+                    continue;
+                }
+            }
+
+            // don't start anything that depends on it until it's ready
+            let (ready, started) = tokio::sync::oneshot::channel();
+            Service::spawn_task(dependency.clone(), Some(ready));
+
+            match started.await {
+                Ok(res) => res?,
+                // its task ended without saying
+                Err(_) => return Err(SprocError::ProcessNotFound(dependency)),
+            }
+        }
+
+        Service::spawn_task(name, None);
+        Ok(())
+    }
+
+    /// Kill a running service, wait for its observer to notice it exited, then [`Service::spawn`] it
+    ///
+    /// `restart` is left as it was. If the observer restarts the service itself, it isn't
+    /// spawned again.
+    pub async fn restart(name: String) -> std::result::Result<(), SprocError> {
+        let config = ServicesConfiguration::try_get_config()?;
+
+        let pid = match config.service_states.get(&name) {
+            Some(s) => s.1,
+            None => return Err(SprocError::NotLoaded(name)),
+        };
+
+        let timeout = Duration::from_secs(match config.services.get(&name) {
+            Some(s) => s.stop_timeout_seconds.unwrap_or(10),
+            None => return Err(SprocError::ServiceNotFound(name)),
+        });
+
+        // kill (this waits for the process to exit)
+        Service::kill_task(name.clone(), config).await?;
+
+        // wait for the observer to update the state
+        let started = Instant::now();
+
+        loop {
+            let config = ServicesConfiguration::try_get_config()?;
+
+            match config.service_states.get(&name) {
+                // restarted by its observer
+                Some(s) if s.0.is_running() && s.1 != pid => return Ok(()),
+                Some(s) if s.0.is_running() => {
+                    if started.elapsed() >= timeout {
+                        // not observed, clear it ourselves
+                        ServicesConfiguration::modify(|config| {
+                            config.service_states.remove(&name);
+                        })
+                        .map_err(SprocError::ConfigIo)?;
+                        break;
+                    }
+                }
+                _ => break,
+            }
+
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+
+        // start
+        Service::spawn(name).await
+    }
+
+    /// [`Service::spawn`] every service that isn't already running, in dependency order
+    ///
+    /// Each group of services that only depend on earlier groups is given
+    /// [`DEPENDENCY_READY_DELAY_SECS`] to start before the next one. Applications are skipped.
+    ///
+    /// A broken dependency graph doesn't stop the other services from starting: services whose
+    /// dependencies are missing or circular get the error as their result.
+    pub async fn start_all() -> std::result::Result<BulkResults, SprocError> {
+        let config = ServicesConfiguration::try_get_config()?;
+        let mut results = BulkResults::new();
+
+        for group in config.start_groups() {
+            let mut started = false;
+
+            for name in group {
+                let service = match config.services.get(&name) {
+                    Some(s) => s,
+                    None => continue,
+                };
+
+                if service.r#type == ServiceType::Application {
+                    continue;
+                }
+
+                if let Err(e) = config.dependency_order(&name) {
+                    results.insert(name, Err(SprocError::Io(e)).into());
+                    continue;
+                }
+
+                if let Some(s) = config.service_states.get(&name) {
+                    if s.0.is_running() {
+                        let error = SprocError::AlreadyRunning(name.clone(), Some(s.1));
+                        results.insert(name, Err(error).into());
+                        continue;
+                    }
+                }
+
+                Service::spawn_task(name.clone(), None);
+                results.insert(name, BulkResult::default());
+                started = true;
+            }
+
+            if started {
+                tokio::time::sleep(Duration::from_secs(DEPENDENCY_READY_DELAY_SECS)).await;
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Reconcile service states with running processes and start `autostart` services (when
+    /// the server starts), returning the services that were started
+    ///
+    /// Services marked as running whose process no longer exists are marked as stopped, or
+    /// started again if they restart (`restart`).
+    pub async fn boot() -> std::result::Result<Vec<String>, SprocError> {
+        let pids: Vec<u32> = ServicesConfiguration::try_get_config()?
+            .service_states
+            .values()
+            .map(|s| s.1)
+            .collect();
+        let sys = snapshot::load(&pids);
+
+        let mut start: Vec<String> = ServicesConfiguration::modify(|config| {
+            let mut restart = Vec::new();
+
+            for (name, state) in config.service_states.iter_mut() {
+                if !state.0.is_running()
+                    || verified_process(&sys, state.1, config.fingerprints.get(name)).is_some()
+                {
+                    continue;
+                }
+
+                state.0 = ServiceState::Stopped;
+
+                if config
+                    .services
+                    .get(name)
+                    .map(|s| s.restarts())
+                    .unwrap_or(false)
+                {
+                    restart.push(name.to_owned());
+                }
+            }
+
+            restart
+        })
+        .map_err(SprocError::ConfigIo)?;
+
+        let config = ServicesConfiguration::try_get_config()?;
+
+        for (name, service) in &config.services {
+            let running = config
+                .service_states
+                .get(name)
+                .map(|s| s.0.is_running())
+                .unwrap_or(false);
+
+            if service.autostart
+                && !running
+                && service.r#type != ServiceType::Application
+                && !start.contains(name)
+            {
+                start.push(name.to_owned());
+            }
+        }
+
+        start.sort();
+
+        for name in &start {
+            Service::spawn(name.to_owned()).await?;
+        }
+
+        Ok(start)
+    }
+
+    /// Start managing a process that wasn't started by sproc as the given service
+    ///
+    /// The process must match the service's command ([`Service::matches_process`]). It's
+    /// observed in a new task like spawned services (with its health checks and memory limit),
+    /// but isn't restarted when it exits.
+    pub fn adopt(name: String, pid: u32) -> std::result::Result<(), SprocError> {
+        let config = ServicesConfiguration::try_get_config()?;
+
+        let service = match config.services.get(&name) {
+            Some(s) => s.clone(),
+            None => return Err(SprocError::ServiceNotFound(name)),
+        };
+
+        if let Some(s) = config.service_states.get(&name) {
+            if s.0.is_running() {
+                return Err(SprocError::AlreadyRunning(name, Some(s.1)));
+            }
+        }
+
+        // check process
+        let mut sys = System::new();
+
+        if !sys.refresh_process(Pid::from(pid as usize)) {
+            return Err(SprocError::ProcessNotFound(name));
+        }
+
+        match sys.process(Pid::from(pid as usize)) {
+            Some(process) if service.matches_process(process) => (),
+            Some(_) => return Err(SprocError::ProcessMismatch(name, pid)),
+            None => return Err(SprocError::ProcessNotFound(name)),
+        }
+
+        // update config
+        let config = ServicesConfiguration::modify(|config| {
+            config.set_running(&name, pid);
+            config.clone()
+        })
+        .map_err(SprocError::ConfigIo)?;
+
+        Service::write_pid_file(&name, pid)?;
+
+        ServiceEntry::new(&name, ServiceState::Running, Some(pid))
+            .with_log_file(service.log_path(&name))
+            .log_event(
+                ServiceEventKind::Started,
+                HashMap::from([("adopted".to_string(), "true".to_string())]),
+            );
+
+        webhook::dispatch(
+            &config.webhooks,
+            WebhookPayload::new(name.clone(), ServiceEventKind::Started, Some(pid)),
+        );
+
+        // enforce memory limit
+        if let Some(max) = service.limits.as_ref().and_then(|l| l.max_memory_bytes) {
+            tokio::task::spawn(limits::enforce(
+                name.clone(),
+                pid,
+                max,
+                service.log_file.clone(),
+            ));
+        }
+
+        // start health checks
+        if let Some(ref check) = service.healthcheck {
+            tokio::task::spawn(health::monitor(
+                name.clone(),
+                pid,
+                check.clone(),
+                service.log_file.clone(),
+            ));
+        }
+
+        // observe
+        let service_states = config.service_states.clone();
+
+        tokio::task::spawn(async move {
+            let _ = Service::observe(name.clone(), service_states).await;
+
+            Service::remove_pid_file(&name);
+
+            let config = match ServicesConfiguration::modify(|config| {
+                // only if it wasn't replaced (e.g. restarted) in the meantime
+                if config.service_states.get(&name).map(|s| s.1) == Some(pid) {
+                    config.service_states.remove(&name);
+                }
+
+                config.clone()
+            }) {
+                Ok(c) => c,
+                Err(e) => {
+                    println!("warn: {e}");
+                    return;
+                }
+            };
+
+            ServiceEntry::new(&name, ServiceState::Stopped, None)
+                .with_log_file(service.log_path(&name))
+                .log_event(ServiceEventKind::Stopped, HashMap::new());
+
+            webhook::dispatch(
+                &config.webhooks,
+                WebhookPayload::new(name, ServiceEventKind::Stopped, None),
+            );
+        });
+
+        Ok(())
+    }
+
+    /// [`Service::kill`] every running service, dependents first
+    ///
+    /// The state of every service that was stopped (or whose process had already exited) is
+    /// removed. Services are killed in no particular order if the dependency graph is broken.
+    pub async fn kill_all() -> std::result::Result<BulkResults, SprocError> {
+        let config = ServicesConfiguration::try_get_config()?;
+        let mut results = BulkResults::new();
+
+        for name in config.start_groups().into_iter().rev().flatten() {
+            match config.service_states.get(&name) {
+                Some(s) if s.0.is_running() => (),
+                _ => continue,
+            }
+
+            let res = match ServicesConfiguration::try_get_config() {
+                Ok(config) => Service::kill_task(name.clone(), config).await,
+                Err(e) => Err(e),
+            };
+
+            // if we couldn't find the process then the service probably ran and exited already
+            if matches!(res, Ok(()) | Err(SprocError::ProcessNotFound(_))) {
+                ServicesConfiguration::modify(|config| {
+                    config.service_states.remove(&name);
+                })
+                .map_err(SprocError::ConfigIo)?;
+            }
+
+            results.insert(name, res.into());
+        }
+
+        Ok(results)
+    }
+
+    /// Look for common problems with the pinned configuration and its services
+    ///
+    /// `server_version` is the version reported by the running server (if it's running).
+    pub fn doctor(server_version: Option<&str>) -> std::result::Result<DoctorReport, SprocError> {
+        let config = ServicesConfiguration::try_get_config()?;
+        Ok(doctor::diagnose(&config, server_version))
+    }
+
+    /// Run exactly `replicas` instances of a template (`{template}@1` to `{template}@{replicas}`)
+    ///
+    /// Missing instances are spawned and any other running instance of the template is killed
+    /// (highest first). Instances that are already running are left alone.
+    pub async fn scale(
+        name: String,
+        replicas: u32,
+    ) -> std::result::Result<BulkResults, SprocError> {
+        let config = ServicesConfiguration::try_get_config()?;
+
+        if !config
+            .templates
+            .as_ref()
+            .is_some_and(|t| t.contains_key(&name))
+        {
+            return Err(SprocError::ServiceNotFound(name));
+        }
+
+        let wanted: Vec<String> = (1..=replicas).map(|i| format!("{name}@{i}")).collect();
+        let prefix = format!("{name}@");
+
+        let mut extra: Vec<String> = config
+            .service_states
+            .iter()
+            .filter(|(service, s)| {
+                service.starts_with(&prefix) && s.0.is_running() && !wanted.contains(service)
+            })
+            .map(|(service, _)| service.to_owned())
+            .collect();
+
+        // named instances first, then numbered instances by number
+        extra.sort_by_key(|service| {
+            let instance = &service[prefix.len()..];
+            (instance.parse::<u32>().ok(), instance.to_string())
+        });
+
+        let mut results = BulkResults::new();
+
+        // stop extras
+        for service in extra.into_iter().rev() {
+            let res = match ServicesConfiguration::try_get_config() {
+                Ok(config) => Service::kill_task(service.clone(), config).await,
+                Err(e) => Err(e),
+            };
+
+            if res.is_ok() {
+                ServicesConfiguration::modify(|config| {
+                    config.service_states.remove(&service);
+                })
+                .map_err(SprocError::ConfigIo)?;
+            }
+
+            results.insert(service, res.into());
+        }
+
+        // start missing instances
+        for service in wanted {
+            if let Some(s) = config.service_states.get(&service) {
+                if s.0.is_running() {
+                    continue;
+                }
+            }
+
+            let res = Service::spawn(service.clone()).await;
+            results.insert(service, res.into());
+        }
+
+        Ok(results)
+    }
+
+    /// [`Service::wait`] (and restart) in a new task
+    ///
+    /// `ready` is sent to once the service is first ready, or with the error if it fails to
+    /// start (or a one-shot service fails).
+    fn spawn_task(name: String, mut ready: Option<ReadySender>) {
+        // spawn task
+        tokio::task::spawn(async move {
+            let mut failures: u32 = 0;
+
+            loop {
+                // pull config from file
+                let mut config = match ServicesConfiguration::try_get_config() {
+                    Ok(c) => c,
+                    Err(e) => {
+                        println!("warn: failed to start service ({name}): {e}");
+
+                        if let Some(ready) = ready.take() {
+                            let _ = ready.send(Err(e));
+                        }
+
+                        return;
+                    }
+                };
+
+                // start service
+                let started = Instant::now();
+                let status = match Service::wait(name.clone(), &mut config, &mut ready).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        ServiceEntry::new(&name, ServiceState::Stopped, None)
+                            .with_log_file(
+                                config.services.get(&name).and_then(|s| s.log_path(&name)),
+                            )
+                            .log_event(
+                                ServiceEventKind::Failed,
+                                HashMap::from([("error".to_string(), e.to_string())]),
+                            );
+
+                        webhook::dispatch(
+                            &config.webhooks,
+                            WebhookPayload::new(name.clone(), ServiceEventKind::Failed, None),
+                        );
+
+                        if let Some(ready) = ready.take() {
+                            let _ = ready.send(Err(e));
+                        }
+
+                        return;
+                    }
+                };
+
+                // one-shot services are ready once they've finished successfully
+                if let Some(ready) = ready.take() {
+                    let _ = ready.send(match status.success() {
+                        true => Ok(()),
+                        false => Err(SprocError::OneshotFailed(name.clone(), status.code())),
+                    });
+                }
+
+                // update config
+                // this pulls the real config so we don't restart if it was disabled while the service was running
+                let pid = config.service_states.get(&name).map(|s| s.1).unwrap_or(0);
+                let exit_code = match limits::was_memory_killed(pid) {
+                    true => Some(limits::MEMORY_KILL_EXIT_CODE),
+                    false => status.code(),
+                };
+
+                let res = ServicesConfiguration::modify(|config| {
+                    let stopped = config.stopping.remove(&name);
+                    let restarts = config.restart_counts.get(&name).copied().unwrap_or(0);
+                    config
+                        .last_exits
+                        .insert(name.clone(), ExitInfo::new(status, restarts));
+
+                    let oneshot = config
+                        .services
+                        .get(&name)
+                        .map(|s| s.r#type == ServiceType::Oneshot)
+                        .unwrap_or(false);
+
+                    match exit_code {
+                        Some(0) if oneshot => {
+                            let timestamp = (epoch_timestamp() / 1000) as u64;
+                            let pid = config.service_states.get(&name).map(|s| s.1).unwrap_or(0);
+                            config
+                                .service_states
+                                .insert(name.clone(), (ServiceState::Completed(timestamp), pid));
+                        }
+                        Some(code) if code != 0 => {
+                            let timestamp = (epoch_timestamp() / 1000) as u64;
+                            let pid = config.service_states.get(&name).map(|s| s.1).unwrap_or(0);
+                            config
+                                .service_states
+                                .insert(name.clone(), (ServiceState::Failed(code, timestamp), pid));
+                        }
+                        _ => {
+                            config.service_states.remove(&name);
+                        }
+                    }
+
+                    if stopped
+                        || !config
+                            .services
+                            .get(&name)
+                            .map(|s| s.restarts())
+                            .unwrap_or(false)
+                    {
+                        config.restart_counts.remove(&name);
+                    }
+
+                    (config.clone(), stopped)
+                });
+
+                let (config, stopped) = match res {
+                    Ok(r) => r,
+                    Err(e) => {
+                        println!("warn: failed to record exit ({name}): {e}");
+                        return;
+                    }
+                };
+
+                let service = match config.services.get(&name) {
+                    Some(s) => s,
+                    None => return,
+                };
+
+                ServiceEntry::new(&name, ServiceState::Stopped, None)
+                    .with_log_file(service.log_path(&name))
+                    .log_event(ServiceEventKind::Stopped, HashMap::new());
+
+                webhook::dispatch(
+                    &config.webhooks,
+                    WebhookPayload::new(name.clone(), ServiceEventKind::Stopped, None)
+                        .with_exit_code(exit_code),
+                );
+
+                // ...
+                if stopped || !service.restarts() {
+                    // no need to loop again if we aren't supposed to restart the service (or it
+                    // was killed)
+                    break;
+                }
+
+                // count consecutive quick exits
+                failures = service.consecutive_failures(failures, started.elapsed());
+
+                if let Some(max) = service.max_restarts {
+                    if failures > max {
+                        // give up
+                        let timestamp = (epoch_timestamp() / 1000) as u64;
+                        let res = ServicesConfiguration::modify(|config| {
+                            config.restart_counts.insert(name.clone(), failures);
+                            config.service_states.insert(
+                                name.clone(),
+                                (ServiceState::Failed(exit_code.unwrap_or(-1), timestamp), 0),
+                            );
+                        });
+
+                        if let Err(e) = res {
+                            println!("warn: failed to record failure ({name}): {e}");
+                        }
+
+                        ServiceEntry::new(&name, ServiceState::Stopped, None)
+                            .with_log_file(service.log_path(&name))
+                            .log_event(
+                                ServiceEventKind::Failed,
+                                HashMap::from([(
+                                    "error".to_string(),
+                                    format!("exceeded max_restarts ({max})"),
+                                )]),
+                            );
+
+                        webhook::dispatch(
+                            &config.webhooks,
+                            WebhookPayload::new(name.clone(), ServiceEventKind::Failed, None),
+                        );
+
+                        break;
+                    }
+                }
+
+                // begin restart
+                let delay = service.restart_delay(failures);
+
+                let res = ServicesConfiguration::modify(|config| {
+                    config.restart_counts.insert(name.clone(), failures);
+                });
+
+                if let Err(e) = res {
+                    println!("warn: failed to record restart ({name}): {e}");
+                }
+
+                ServiceEntry::new(&name, ServiceState::Stopped, None)
+                    .with_log_file(service.log_path(&name))
+                    .log_event(
+                        ServiceEventKind::Restarted,
+                        HashMap::from([("delay_secs".to_string(), delay.as_secs().to_string())]),
+                    );
+
+                webhook::dispatch(
+                    &config.webhooks,
+                    WebhookPayload::new(name.clone(), ServiceEventKind::Restarted, None),
+                );
+
+                tokio::time::sleep(delay).await;
+                continue; // service will be run again
+            }
+        });
+    }
+
+    // package manager
+
+    /// Run and init a [`Service`]'s `BuildConfiguration`
+    pub async fn bootstrap(&self, name: String) -> Result<()> {
+        let modules = data_dir().join("modules");
+
+        // verify modules directory
+        if let Err(_) = std::fs::read_dir(&modules) {
+            if let Err(e) = std::fs::create_dir_all(&modules) {
+                panic!("{:?}", e);
+            }
+        }
+
+        // check for existing directory
+        let dir = module_dir(&name).to_string_lossy().to_string();
+
+        if let Ok(_) = fs::read_dir(&dir) {
+            return Err(Error::new(ErrorKind::AlreadyExists, "The requested service has already run its build commands or its build directory already exists."));
+        }
+
+        // create directory
+        fs::create_dir(&dir)?;
+
+        // create build file
+        let extension = if cfg!(windows) { "cmd" } else { "sh" };
+        let build_file = format!("{dir}/build.artifact.{extension}");
+        fs::write(&build_file, self.metadata.build.join("\n"))?;
+
+        // run build file
+        let mut cmd = if cfg!(windows) {
+            shell_command(&build_file)
+        } else {
+            let mut cmd = Command::new("bash");
+            cmd.arg(&build_file);
+            cmd
+        };
+
+        cmd.current_dir(&dir);
+
+        // capture out
+        let child_stdout = cmd
+            .stdout(Stdio::piped())
+            .spawn()?
+            .stdout
+            .expect("failed to capture command output");
+
+        let reader = BufReader::new(child_stdout);
+
+        reader
+            .lines()
+            .filter_map(|l| l.ok())
+            .for_each(|l| println!("build: {l}"));
+
+        // return
+        Ok(())
+    }
+}
+
+/// A service name and its current state, used for lifecycle logging
+#[derive(Clone, Debug)]
+pub struct ServiceEntry {
+    pub name: String,
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    /// The service's `log_file` (events are written to stderr without one)
+    pub log_file: Option<String>,
+}
+
+impl ServiceEntry {
+    /// Create a new [`ServiceEntry`]
+    pub fn new(name: &str, state: ServiceState, pid: Option<u32>) -> Self {
+        Self {
+            name: name.to_string(),
+            state,
+            pid,
+            log_file: None,
+        }
+    }
+
+    /// Set the log file events are written to
+    pub fn with_log_file(mut self, log_file: Option<String>) -> Self {
+        self.log_file = log_file;
+        self
+    }
+
+    /// Format a lifecycle event as a JSON log line
+    ///
+    /// Fixed fields are `timestamp`, `level`, `service`, `pid`, `state`, and `event`;
+    /// `extra` fields are added alongside them (but can't replace them).
+    pub fn to_log_line(&self, event: ServiceEventKind, extra: &HashMap<String, String>) -> String {
+        let mut line = serde_json::Map::new();
+
+        for (key, value) in extra {
+            line.insert(key.to_owned(), value.as_str().into());
+        }
+
+        line.insert("timestamp".to_string(), (epoch_timestamp() as u64).into());
+        line.insert(
+            "level".to_string(),
+            match event {
+                ServiceEventKind::Failed => "error",
+                ServiceEventKind::Stopped
+                | ServiceEventKind::Restarted
+                | ServiceEventKind::Unhealthy => "warn",
+                _ => "info",
+            }
+            .into(),
+        );
+        line.insert("service".to_string(), self.name.as_str().into());
+        line.insert("pid".to_string(), self.pid.into());
+        line.insert(
+            "state".to_string(),
+            serde_json::to_value(&self.state).unwrap(),
+        );
+        line.insert("event".to_string(), serde_json::to_value(&event).unwrap());
+
+        serde_json::Value::Object(line).to_string()
+    }
+
+    /// Write a lifecycle event log line to the service's log file (or stderr)
+    pub fn log_event(&self, event: ServiceEventKind, extra: HashMap<String, String>) {
+        let line = self.to_log_line(event, &extra);
+
+        if let Some(ref path) = self.log_file {
+            if let Ok(mut file) = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+            {
+                if writeln!(file, "{line}").is_ok() {
+                    return;
+                }
+            }
+        }
+
+        eprintln!("{line}");
+    }
+}
+
+/// The state of a [`Service`]
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum ServiceState {
+    Running,
+    Stopped,
+    /// The service exited abnormally, `(exit code, Unix timestamp (seconds))`
+    Failed(i32, u64),
+    /// The service is running but failing its `healthcheck`
+    Unhealthy,
+    /// A [`ServiceType::Oneshot`] service exited successfully, `(Unix timestamp (seconds))`
+    Completed(u64),
+}
+
+impl ServiceState {
+    /// If the service has a running process ([`ServiceState::Running`] or [`ServiceState::Unhealthy`])
+    pub fn is_running(&self) -> bool {
+        matches!(self, ServiceState::Running | ServiceState::Unhealthy)
+    }
+}
+
+impl Default for ServiceState {
+    fn default() -> Self {
+        Self::Stopped
+    }
+}
+
+/// General information about a [`ServiceState`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ServiceInfo {
+    pub name: String,
+    pub pid: u32,
+    pub memory: u64,
+    pub cpu: f32,
+    pub status: String,
+    pub running_for_seconds: u64,
+    /// The service's configured `log_file`
+    #[serde(default)]
+    pub log_file: Option<String>,
+    /// The exit code of the service if it has failed
+    #[serde(default)]
+    pub last_exit_code: Option<i32>,
+    /// How the service last exited
+    #[serde(default)]
+    pub last_exit: Option<ExitInfo>,
+}
+
+/// Every running service's info (from [`ServicesConfiguration::info_all`])
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ServicesInfo {
+    pub services: Vec<ServiceInfo>,
+    /// Memory used by every service process (bytes)
+    pub total_memory: u64,
+    /// CPU usage of every service process
+    pub total_cpu: f32,
+}
+
+/// How service info is printed
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Toml,
+    Json,
+    /// Aligned columns, one row per service
+    Table,
+}
+
+impl OutputFormat {
+    /// Render a single service's info
+    pub fn render_info(&self, info: &ServiceInfo) -> String {
+        match self {
+            Self::Toml => toml::to_string_pretty(info).unwrap(),
+            Self::Json => serde_json::to_string_pretty(info).unwrap(),
+            Self::Table => ServiceInfo::table(std::slice::from_ref(info)),
+        }
+    }
+
+    /// Render every service's info (a JSON array, or TOML documents separated by blank lines)
+    pub fn render_infos(&self, infos: &[ServiceInfo]) -> String {
+        match self {
+            Self::Toml => infos
+                .iter()
+                .map(|i| toml::to_string_pretty(i).unwrap())
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Self::Json => serde_json::to_string_pretty(infos).unwrap(),
+            Self::Table => ServiceInfo::table(infos),
+        }
+    }
+}
+
+impl ServiceInfo {
+    /// Render services as a table (name, PID, status, memory, CPU, and uptime)
+    pub fn table(infos: &[ServiceInfo]) -> String {
+        let width = infos
+            .iter()
+            .map(|i| i.name.len())
+            .chain(std::iter::once(4))
+            .max()
+            .unwrap_or(4);
+
+        let mut out = format!(
+            "{:<width$}  {:>7}  {:<10}  {:>12}  {:>6}  {:>8}\n",
+            "NAME", "PID", "STATUS", "MEMORY", "CPU", "UPTIME"
+        );
+
+        for info in infos {
+            out.push_str(&format!(
+                "{:<width$}  {:>7}  {:<10}  {:>12}  {:>5.1}%  {:>7}s\n",
+                info.name, info.pid, info.status, info.memory, info.cpu, info.running_for_seconds
+            ));
+        }
+
+        out
+    }
+}
+
+/// Identifies a service's process beyond its PID, so a different process that reuses the PID
+/// (e.g. after a reboot) isn't mistaken for the service
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProcessFingerprint {
+    pub pid: u32,
+    /// When the process started (Unix timestamp, seconds)
+    pub start_time: u64,
+    /// The process's executable name
+    pub name: String,
+}
+
+impl ProcessFingerprint {
+    /// Fingerprint a running process
+    pub fn capture(pid: u32) -> Option<Self> {
+        let mut sys = System::new();
+        let pid = Pid::from(pid as usize);
+
+        if !sys.refresh_process(pid) {
+            return None;
+        }
+
+        sys.process(pid).map(|process| Self {
+            pid: pid.as_u32(),
+            start_time: process.start_time(),
+            name: process.name().to_string(),
+        })
+    }
+
+    /// Check if `process` is the fingerprinted process
+    pub fn matches(&self, process: &Process) -> bool {
+        process.pid().as_u32() == self.pid
+            && process.start_time() == self.start_time
+            && process.name() == self.name
+    }
+}
+
+/// Get a service's process from `sys`, unless its fingerprint shows the PID now belongs to
+/// another process
+///
+/// States without a fingerprint (from older versions) only check the PID.
+pub fn verified_process<'a>(
+    sys: &'a System,
+    pid: u32,
+    fingerprint: Option<&ProcessFingerprint>,
+) -> Option<&'a Process> {
+    let process = sys.process(Pid::from(pid as usize))?;
+
+    match fingerprint {
+        Some(f) if f.pid == pid && !f.matches(process) => None,
+        _ => Some(process),
+    }
+}
+
+/// How a service process exited (from [`ServicesConfiguration::last_exits`])
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ExitInfo {
+    /// The exit code (`None` if killed by a signal)
+    pub code: Option<i32>,
+    /// The signal that killed the process
+    pub signal: Option<i32>,
+    /// Unix timestamp (seconds) the process exited at
+    pub timestamp: u64,
+    /// The number of consecutive automatic restarts before the process exited
+    pub restarts: u32,
+}
+
+impl ExitInfo {
+    /// Create a new [`ExitInfo`] timestamped now
+    pub fn new(status: ExitStatus, restarts: u32) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+
+        Self {
+            code: status.code(),
+            signal,
+            timestamp: (epoch_timestamp() / 1000) as u64,
+            restarts,
+        }
+    }
+
+    /// If the process exited on its own with code 0
+    pub fn is_clean(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl ServiceInfo {
+    /// Get the changes between two snapshots of the same service
+    pub fn diff(before: &Self, after: &Self) -> ServiceInfoDiff {
+        ServiceInfoDiff {
+            memory_delta_bytes: after.memory as i64 - before.memory as i64,
+            cpu_delta_percent: after.cpu - before.cpu,
+            status_changed: after.status != before.status,
+            was_restarted: after.pid != before.pid
+                || after.running_for_seconds < before.running_for_seconds,
+        }
+    }
+}
+
+/// The changes between two [`ServiceInfo`] snapshots
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ServiceInfoDiff {
+    pub memory_delta_bytes: i64,
+    pub cpu_delta_percent: f32,
+    pub status_changed: bool,
+    pub was_restarted: bool,
+}
+
+/// A configured service, its state, and its process info (from [`ServicesConfiguration::summary`])
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    pub restart: bool,
+    /// Process info (uptime, memory, CPU) if the service is running
+    pub info: Option<ServiceInfo>,
+    /// How the service last exited
+    pub last_exit: Option<ExitInfo>,
+}
+
+/// Output of [`ServicesConfiguration::summary`]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ServicesSummary {
+    pub running: usize,
+    pub unhealthy: usize,
+    pub failed: usize,
+    pub completed: usize,
+    pub stopped: usize,
+    pub services: Vec<ServiceStatus>,
+}
+
+/// A service in [`ServiceStates`] and its OS process (from [`ServicesConfiguration::ps`])
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrackedProcess {
+    pub name: String,
+    pub state: ServiceState,
+    pub pid: u32,
+    /// If the process actually exists
+    pub alive: bool,
+    pub memory: u64,
+    pub cpu: f32,
+}
+
+/// A process that looks like a service but isn't in [`ServiceStates`]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OrphanProcess {
+    /// The name of the service it looks like
+    pub name: String,
+    pub pid: u32,
+    pub executable: String,
+    pub working_directory: String,
+}
+
+/// Output of [`ServicesConfiguration::ps`]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProcessReport {
+    pub tracked: Vec<TrackedProcess>,
+    pub orphans: Vec<OrphanProcess>,
+}
+
+/// Configuration for `sproc serve`'s registry
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RegistryConfiguration {
+    /// If the registry is enabled
+    pub enabled: bool,
+    /// Registry description, shown on the homepage
+    #[serde(default)]
+    pub description: String,
+    /// Registry name, shown on homepage
+    #[serde(default = "registry_default")]
+    pub name: String,
+    /// Navigation bar buttons, `(Text, Url)`
+    #[serde(default)]
+    pub nav: Vec<(String, String)>,
+}
+
+fn registry_default() -> String {
+    "Registry".to_owned()
+}
+
+impl Default for RegistryConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            description: String::new(),
+            name: registry_default(),
+            nav: Vec::new(),
+        }
+    }
+}
+
+/// Configuration for `sproc serve`
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ServerConfiguration {
+    /// The port to serve the HTTP server on (6374 by default)
+    pub port: u16,
+    /// The address to serve the HTTP server on ("127.0.0.1" by default)
+    #[serde(default = "host_default")]
+    pub host: String,
+    /// Serve the HTTP server on this Unix domain socket instead of `host` and `port`
+    ///
+    /// The socket is created with `0660` permissions.
+    #[serde(default)]
+    pub unix_socket: Option<String>,
+    /// Serve HTTPS with this certificate (not used with `unix_socket`)
+    #[serde(default)]
+    pub tls: Option<TlsConfiguration>,
+    /// Tokens that can run some operations (`key` can run every operation)
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+    /// The key that is required to run operations from the HTTP server
+    pub key: String,
+    /// Configuration for the registry
+    #[serde(default)]
+    pub registry: RegistryConfiguration,
+    /// How requests to the HTTP API prove they know the key
+    #[serde(default)]
+    pub auth_mode: AuthMode,
+}
+
+fn host_default() -> String {
+    "127.0.0.1".to_string()
+}
+
+impl Default for ServerConfiguration {
+    fn default() -> Self {
+        Self {
+            port: 6374,
+            host: host_default(),
+            unix_socket: None,
+            tls: None,
+            tokens: Vec::new(),
+            key: String::new(),
+            registry: RegistryConfiguration::default(),
+            auth_mode: AuthMode::default(),
+        }
+    }
+}
+
+impl ServerConfiguration {
+    /// Get the base URL clients should connect to (`host` is replaced with localhost if it's
+    /// unspecified, e.g. "0.0.0.0")
+    pub fn url(&self) -> String {
+        let host = match self.host.as_str() {
+            "0.0.0.0" | "::" | "[::]" => "localhost",
+            h => h,
+        };
+
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+
+        if host.contains(":") && !host.starts_with("[") {
+            return format!("{scheme}://[{host}]:{}", self.port);
+        }
+
+        format!("{scheme}://{host}:{}", self.port)
+    }
+}
+
+/// An operation an [`ApiToken`] can be allowed to run
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Start (and restart) services
+    Start,
+    /// Kill (and restart) services
+    Kill,
+    /// Read service info, states, metrics, and events
+    Info,
+    /// Read service logs
+    Logs,
+}
+
+/// A key for the HTTP server limited to some operations (`server.tokens`)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ApiToken {
+    /// The token, sent in place of the server key
+    pub token: String,
+    /// The operations this token can run
+    pub scopes: Vec<TokenScope>,
+    /// The services this token can be used on (every service if unset)
+    ///
+    /// Tokens with this set can't run operations on every service (like `/kill-all`).
+    #[serde(default)]
+    pub services: Option<Vec<String>>,
+}
+
+impl ApiToken {
+    /// Check if this token can run an operation on a service (or every service if `None`)
+    pub fn allows(&self, scope: TokenScope, service: Option<&str>) -> bool {
+        if !self.scopes.contains(&scope) {
+            return false;
+        }
+
+        match (&self.services, service) {
+            (None, _) => true,
+            (Some(services), Some(service)) => services.iter().any(|s| s == service),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// TLS configuration for the HTTP server (`server.tls`)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TlsConfiguration {
+    /// Path to the PEM-encoded certificate chain
+    pub cert: String,
+    /// Path to the PEM-encoded private key
+    pub key: String,
+}
+
+/// How requests to the HTTP API are authenticated
+#[derive(Clone, Default, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum AuthMode {
+    /// The key is sent in the request body (`key`)
+    #[default]
+    Plaintext,
+    /// The request body is signed with the key (`X-Sproc-Signature: sha256={hex hmac}`)
+    Hmac,
+}
+
+/// An error in the service definitions of a [`ServicesConfiguration`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A service depends on a service that doesn't exist, `(service, dependency)`
+    MissingDependency(String, String),
+    /// Services depend on each other in a loop (the names of the services in the loop)
+    CircularDependency(Vec<String>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::MissingDependency(service, dependency) => write!(
+                f,
+                "Service depends on a service that does not exist. ({service} -> {dependency})"
+            ),
+            ConfigError::CircularDependency(cycle) => {
+                write!(f, "Circular dependency. ({})", cycle.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for Error {
+    fn from(value: ConfigError) -> Self {
+        Error::new(ErrorKind::InvalidInput, value.to_string())
+    }
+}
+
+/// The outcome of a bulk operation ([`Service::start_all`], [`Service::kill_all`]) for one service
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BulkResult {
+    /// If the operation succeeded
+    pub ok: bool,
+    /// Why the operation failed
+    pub error: Option<String>,
+}
+
+impl Default for BulkResult {
+    fn default() -> Self {
+        Self {
+            ok: true,
+            error: None,
+        }
+    }
+}
+
+impl From<std::result::Result<(), SprocError>> for BulkResult {
+    fn from(res: std::result::Result<(), SprocError>) -> Self {
+        match res {
+            Ok(_) => Self::default(),
+            Err(e) => Self {
+                ok: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// [`BulkResult`]s by service name
+pub type BulkResults = HashMap<String, BulkResult>;
+
+/// An error from managing a [`Service`]
+#[derive(Debug)]
+pub enum SprocError {
+    /// The service isn't defined, `(service)`
+    ServiceNotFound(String),
+    /// The service has no state, `(service)`
+    NotLoaded(String),
+    /// The service is already running, `(service, pid from its PID file)`
+    AlreadyRunning(String, Option<u32>),
+    /// The service isn't running, `(service)`
+    NotRunning(String),
+    /// The service's tracked process doesn't exist, `(service)`
+    ProcessNotFound(String),
+    /// A process doesn't match the service's command, `(service, pid)`
+    ProcessMismatch(String, u32),
+    /// The service process couldn't be spawned, `(service, error)`
+    SpawnFailed(String, Error),
+    /// A hook command failed, `(service, error)`
+    HookFailed(String, Error),
+    /// A [`ServiceType::Oneshot`] service exited unsuccessfully, `(service, exit code)`
+    OneshotFailed(String, Option<i32>),
+    /// The service's stdin isn't kept by this process (it wasn't spawned by this process, or
+    /// `accept_stdin` is off), `(service)`
+    StdinUnavailable(String),
+    /// The configuration file couldn't be read or written
+    ConfigIo(Error),
+    /// The configuration file couldn't be parsed
+    ConfigParse(String),
+    /// Any other I/O error
+    Io(Error),
+}
+
+impl std::fmt::Display for SprocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SprocError::ServiceNotFound(name) => write!(f, "Service does not exist. ({name})"),
+            SprocError::NotLoaded(name) => write!(f, "Service is not loaded. ({name})"),
+            SprocError::AlreadyRunning(name, None) => {
+                write!(f, "Service is already running. ({name})")
+            }
+            SprocError::AlreadyRunning(name, Some(pid)) => {
+                write!(f, "Service is already running with PID {pid}. ({name})")
+            }
+            SprocError::NotRunning(name) => write!(f, "Service is not running. ({name})"),
+            SprocError::ProcessNotFound(name) => {
+                write!(f, "Failed to get process from PID. ({name})")
+            }
+            SprocError::ProcessMismatch(name, pid) => {
+                write!(
+                    f,
+                    "Process does not match the service's command. ({name}, {pid})"
+                )
+            }
+            SprocError::SpawnFailed(name, e) => write!(f, "Failed to spawn service. ({name}: {e})"),
+            SprocError::HookFailed(name, e) => write!(f, "{e} ({name})"),
+            SprocError::OneshotFailed(name, Some(code)) => {
+                write!(f, "Service exited with code {code}. ({name})")
+            }
+            SprocError::OneshotFailed(name, None) => {
+                write!(f, "Service was killed by a signal. ({name})")
+            }
+            SprocError::StdinUnavailable(name) => {
+                write!(f, "Service stdin is not available. ({name})")
+            }
+            SprocError::ConfigIo(e) => write!(f, "Failed to access configuration. ({e})"),
+            SprocError::ConfigParse(e) => write!(f, "Failed to parse configuration. ({e})"),
+            SprocError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SprocError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SprocError::SpawnFailed(_, e)
+            | SprocError::HookFailed(_, e)
+            | SprocError::ConfigIo(e)
+            | SprocError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for SprocError {
+    fn from(value: Error) -> Self {
+        SprocError::Io(value)
+    }
+}
+
+impl From<SprocError> for Error {
+    fn from(value: SprocError) -> Self {
+        let kind = match value {
+            SprocError::Io(e) => return e,
+            SprocError::ServiceNotFound(_) | SprocError::NotLoaded(_) => ErrorKind::NotFound,
+            SprocError::AlreadyRunning(..) => ErrorKind::AlreadyExists,
+            SprocError::NotRunning(_) | SprocError::ProcessNotFound(_) => ErrorKind::NotConnected,
+            SprocError::ConfigParse(_) => ErrorKind::InvalidData,
+            _ => ErrorKind::Other,
+        };
+
+        Error::new(kind, value.to_string())
+    }
+}
+
+/// A problem found by [`ServicesConfiguration::validate`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationError {
+    /// A service depends on a service that doesn't exist
+    UnknownDependency { service: String, dep: String },
+    /// A service's `working_directory` isn't a directory
+    InvalidWorkingDirectory { service: String, path: String },
+    /// An inherited file defines a service that was already defined
+    DuplicateServiceName { name: String },
+    /// A service's `schedule` isn't a valid cron expression
+    InvalidSchedule { service: String, schedule: String },
+    /// A service's `command` is empty
+    EmptyCommand { service: String },
+    /// The configuration file couldn't be parsed
+    InvalidSyntax { error: String },
+    /// Multiple services' health checks (or `wait_for` ports) check the same address
+    PortClash {
+        services: Vec<String>,
+        address: String,
+    },
+}
+
+impl ValidationError {
+    /// If the problem stops the service from working (rather than likely being a mistake)
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            ValidationError::DuplicateServiceName { .. } | ValidationError::PortClash { .. }
+        )
+    }
+}
+
+/// Output of [`ServicesConfiguration::check`]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ValidationReport {
+    /// Problems that stop services from working
+    pub errors: Vec<ValidationError>,
+    /// Problems that are likely mistakes
+    pub warnings: Vec<ValidationError>,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::UnknownDependency { service, dep } => write!(
+                f,
+                "Service depends on a service that does not exist. ({service} -> {dep})"
+            ),
+            ValidationError::InvalidWorkingDirectory { service, path } => write!(
+                f,
+                "Service working directory is not a directory. ({service}: {path})"
+            ),
+            ValidationError::DuplicateServiceName { name } => {
+                write!(
+                    f,
+                    "Inherited service replaces an existing service. ({name})"
+                )
+            }
+            ValidationError::InvalidSchedule { service, schedule } => write!(
+                f,
+                "Service schedule is not a valid cron expression. ({service}: {schedule})"
+            ),
+            ValidationError::EmptyCommand { service } => {
+                write!(f, "Service command is empty. ({service})")
+            }
+            ValidationError::InvalidSyntax { error } => {
+                write!(f, "Failed to parse configuration. ({error})")
+            }
+            ValidationError::PortClash { services, address } => write!(
+                f,
+                "Services check the same address. ({}: {address})",
+                services.join(", ")
+            ),
+        }
+    }
+}
+
+/// `services.toml` file
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ServicesConfiguration {
+    /// The source file location
+    #[serde(default)]
+    pub source: String,
+    /// Inherited service definition files
+    pub inherit: Option<Vec<String>>,
+    /// How services defined in more than one file are handled (`last_wins` by default)
+    #[serde(default)]
+    pub inherit_conflicts: InheritConflictPolicy,
+    /// Server configuration (`sproc serve`)
+    #[serde(default)]
+    pub server: ServerConfiguration,
+    /// Service definitions
+    pub services: HashMap<String, Service>,
+    /// Service states (kept in [`RuntimeState`], only read from here for older files)
+    #[serde(default, skip_serializing)]
+    pub service_states: ServiceStates,
+    /// The number of consecutive automatic restarts of each service (reset once it runs for
+    /// `stable_after_secs`, kept in [`RuntimeState`])
+    #[serde(default, skip_serializing)]
+    pub restart_counts: HashMap<String, u32>,
+    /// How each service last exited (kept in [`RuntimeState`])
+    #[serde(default, skip_serializing)]
+    pub last_exits: HashMap<String, ExitInfo>,
+    /// When each scheduled service last ran and will next run (kept in [`RuntimeState`])
+    #[serde(default, skip_serializing)]
+    pub schedules: HashMap<String, ScheduleState>,
+    /// Fingerprints of running service processes (kept in [`RuntimeState`])
+    #[serde(default, skip_serializing)]
+    pub fingerprints: HashMap<String, ProcessFingerprint>,
+    /// Services being killed, which aren't restarted when they exit (kept in [`RuntimeState`])
+    #[serde(default, skip_serializing)]
+    pub stopping: HashSet<String>,
+    /// Command run before the configuration is loaded (from `bootstrap.toml`)
+    #[serde(default, skip_serializing)]
+    pub pre_config_check: Option<String>,
+    /// Webhooks notified when service states change
+    pub webhooks: Option<Vec<Webhook>>,
+    /// Environment variables given to every service (overridden by each service's `environment`)
+    pub environment: Option<HashMap<String, String>>,
+    /// Service templates, run as `{template}@{instance}` services
+    pub templates: Option<HashMap<String, ServiceTemplate>>,
+    /// Other sproc servers that operations can be sent to (`--remote {name}`)
+    pub remotes: Option<HashMap<String, Remote>>,
+    /// How service log files are rotated and pruned
+    #[serde(default)]
+    pub logging: LogRetention,
+    /// Names of services defined more than once across inherited files (from [`ServicesConfiguration::read`])
+    #[serde(skip)]
+    pub duplicate_services: Vec<String>,
+    /// Definitions of the services that came from inherited files, which aren't written into the
+    /// pinned file (from [`ServicesConfiguration::read`])
+    #[serde(skip)]
+    pub inherited_services: HashMap<String, Service>,
+    /// The file's own definitions of services that an inherited file replaced
+    #[serde(skip)]
+    pub shadowed_services: HashMap<String, Service>,
+}
+
+impl Default for ServicesConfiguration {
+    fn default() -> Self {
+        Self {
+            source: String::new(),
+            inherit: None,
+            inherit_conflicts: InheritConflictPolicy::default(),
+            services: HashMap::new(),
+            server: ServerConfiguration::default(),
+            service_states: HashMap::new(),
+            restart_counts: HashMap::new(),
+            last_exits: HashMap::new(),
+            schedules: HashMap::new(),
+            fingerprints: HashMap::new(),
+            stopping: HashSet::new(),
+            pre_config_check: None,
+            webhooks: None,
+            environment: None,
+            templates: None,
+            remotes: None,
+            logging: LogRetention::default(),
+            duplicate_services: Vec::new(),
+            inherited_services: HashMap::new(),
+            shadowed_services: HashMap::new(),
+        }
+    }
+}
+
+/// A service definition with `{{placeholders}}`, run as `{template}@{instance}` services
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ServiceTemplate {
+    /// Instances created when the configuration is loaded (others can be created by starting
+    /// `{template}@{instance}`)
+    #[serde(default)]
+    pub instances: Vec<String>,
+    /// The service definition (the same fields as [`Service`])
+    #[serde(flatten)]
+    pub definition: toml::Table,
+}
+
+/// Replace `{{key}}` placeholders in every string in `value`
+fn substitute(value: &mut toml::Value, params: &[(&str, &str)]) {
+    match value {
+        toml::Value::String(s) => {
+            for (key, param) in params {
+                *s = s.replace(&format!("{{{{{key}}}}}"), param);
+            }
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                substitute(value, params);
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                substitute(value, params);
+            }
+        }
+        _ => (),
+    }
+}
+
+impl ServiceTemplate {
+    /// Create the service for an instance
+    ///
+    /// `{{name}}` is replaced with the template's name and `{{instance}}` with the instance.
+    /// The instance is also given to the service as `SPROC_INSTANCE`.
+    pub fn instantiate(
+        &self,
+        name: &str,
+        instance: &str,
+    ) -> std::result::Result<Service, SprocError> {
+        let mut definition = toml::Value::Table(self.definition.clone());
+        substitute(&mut definition, &[("name", name), ("instance", instance)]);
+
+        let mut service: Service = definition
+            .try_into()
+            .map_err(|e| SprocError::ConfigParse(format!("template {name}: {e}")))?;
+
+        service
+            .environment
+            .get_or_insert_with(HashMap::new)
+            .entry("SPROC_INSTANCE".to_string())
+            .or_insert_with(|| instance.to_string());
+
+        Ok(service)
+    }
+}
+
+/// A configuration file format, detected from the file's extension
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigFormat {
+    #[default]
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Detect a file's format (`.json`, `.yaml` or `.yml`, anything else is TOML)
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::Json,
+            Some("yaml") | Some("yml") => Self::Yaml,
+            _ => Self::Toml,
+        }
+    }
+
+    /// Parse `contents` in this format
+    pub fn parse<T: DeserializeOwned>(&self, contents: &str) -> std::result::Result<T, SprocError> {
+        match self {
+            Self::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+            Self::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+            Self::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        }
+        .map_err(SprocError::ConfigParse)
+    }
+
+    /// Serialize `value` in this format
+    pub fn serialize<T: Serialize>(&self, value: &T) -> String {
+        match self {
+            Self::Toml => toml::to_string_pretty(value).unwrap(),
+            Self::Json => serde_json::to_string_pretty(value).unwrap(),
+            Self::Yaml => serde_yaml::to_string(value).unwrap(),
+        }
+    }
+}
+
+/// How a service defined in more than one file (through `inherit`) is handled
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InheritConflictPolicy {
+    /// Fail to load the configuration
+    Error,
+    /// Keep the first definition (the main file, then inherited files in order)
+    FirstWins,
+    /// Keep the last definition
+    #[default]
+    LastWins,
+}
+
+/// A service whose definition differs between two configurations
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServiceChange {
+    pub service: String,
+    /// The fields that were added, removed, or changed
+    pub fields: Vec<String>,
+}
+
+/// The differences between two configurations (from [`ServicesConfiguration::diff`])
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// Services only in the new configuration
+    pub added: Vec<String>,
+    /// Services only in the current configuration
+    pub removed: Vec<String>,
+    /// Services in both configurations with different definitions
+    pub changed: Vec<ServiceChange>,
+    /// If anything besides services changed (`server`, `webhooks`, `environment`, ...)
+    pub settings_changed: bool,
+}
+
+impl ConfigDiff {
+    /// Check if the configurations are the same
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && !self.settings_changed
+    }
+}
+
+/// Move a map's entry for `old` (if it has one) to `new`
+fn rename_key<V>(map: &mut HashMap<String, V>, old: &str, new: &str) {
+    if let Some(value) = map.remove(old) {
+        map.insert(new.to_string(), value);
+    }
+}
+
+/// Serializes [`ConfigLock`]s within this process (`flock` only excludes other processes)
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+/// An exclusive lock on the configuration and state files (`.lock` in [`sproc_dir`]),
+/// released when dropped
+pub struct ConfigLock {
+    file: std::fs::File,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl ConfigLock {
+    /// Wait for and take the lock
+    pub fn acquire() -> Result<Self> {
+        let guard = CONFIG_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        std::fs::create_dir_all(sproc_dir())?;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(sproc_dir().join(".lock"))?;
+
+        file.lock_exclusive()?;
+
+        Ok(Self {
+            file,
+            _guard: guard,
+        })
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// `state.toml` file, holding everything sproc changes while services run
+///
+/// Kept apart from `services.toml` so starting and stopping services doesn't rewrite service
+/// definitions.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RuntimeState {
+    /// Service states
+    #[serde(default)]
+    pub service_states: ServiceStates,
+    /// The number of consecutive automatic restarts of each service
+    #[serde(default)]
+    pub restart_counts: HashMap<String, u32>,
+    /// How each service last exited
+    #[serde(default)]
+    pub last_exits: HashMap<String, ExitInfo>,
+    /// When each scheduled service last ran and will next run
+    #[serde(default)]
+    pub schedules: HashMap<String, ScheduleState>,
+    /// Fingerprints of running service processes
+    #[serde(default)]
+    pub fingerprints: HashMap<String, ProcessFingerprint>,
+    /// Services being killed, which aren't restarted when they exit
+    #[serde(default)]
+    pub stopping: HashSet<String>,
+}
+
+impl RuntimeState {
+    /// Pull state file
+    ///
+    /// Returns `None` if the file doesn't exist yet.
+    pub fn get() -> Option<Self> {
+        match std::fs::read_to_string(state_path()) {
+            Ok(c) => Some(toml::from_str(&c).unwrap_or_default()),
+            Err(_) => None,
+        }
+    }
+
+    /// Update state file
+    pub fn update(&self) -> Result<()> {
+        write_atomic(
+            &state_path(),
+            &format!(
+                "# Runtime state managed by sproc. Changes are overwritten.\n{}",
+                toml::to_string_pretty::<Self>(self).unwrap()
+            ),
+        )
+    }
+}
+
+/// `bootstrap.toml` file, read before `services.toml`
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BootstrapConfiguration {
+    /// Command run before the main configuration file is loaded
+    ///
+    /// Its output is ignored, but a non-zero exit prevents startup.
+    #[serde(default)]
+    pub pre_config_check: Option<String>,
+}
+
+impl ServicesConfiguration {
+    /// Parse a configuration file without handling `inherit`
+    pub fn parse(contents: &str) -> std::result::Result<Self, SprocError> {
+        toml::from_str::<Self>(contents).map_err(|e| SprocError::ConfigParse(e.to_string()))
+    }
+
+    /// Read configuration file into [`ServicesConfiguration`]
+    ///
+    /// Panics if the file (or a file it inherits) can't be parsed; use
+    /// [`ServicesConfiguration::try_read`] to handle the error instead.
+    pub fn read(contents: String) -> Self {
+        match Self::try_read(contents) {
+            Ok(c) => c,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Read configuration file into [`ServicesConfiguration`], applying `inherit`
+    pub fn try_read(contents: String) -> std::result::Result<Self, SprocError> {
+        Self::resolve(Self::parse(&contents)?)
+    }
+
+    /// Read a TOML, JSON, or YAML configuration file (by extension), applying `inherit`
+    pub fn try_read_file(path: &str) -> std::result::Result<Self, SprocError> {
+        let contents = std::fs::read_to_string(path).map_err(SprocError::ConfigIo)?;
+        let res = Self::parse_file(path, &contents)?;
+        Self::resolve(res)
+    }
+
+    /// Parse a file's contents in the format its extension says, without applying `inherit`
+    ///
+    /// Parse errors include the file's path.
+    pub fn parse_file(path: &str, contents: &str) -> std::result::Result<Self, SprocError> {
+        ConfigFormat::from_path(Path::new(path))
+            .parse(contents)
+            .map_err(|e| match e {
+                SprocError::ConfigParse(e) => SprocError::ConfigParse(format!("{path}: {e}")),
+                e => e,
+            })
+    }
+
+    /// Add the services of every inherited file and template instance
+    fn resolve(res: Self) -> std::result::Result<Self, SprocError> {
+        let mut res = Self::apply_inherit(res)?;
+        res.expand_templates()?;
+        Ok(res)
+    }
+
+    /// Add the services of every inherited file
+    fn apply_inherit(mut res: Self) -> std::result::Result<Self, SprocError> {
+        let inherit = match res.inherit {
+            Some(ref inherit) => inherit.clone(),
+            None => return Ok(res),
+        };
+
+        // files that would include themselves
+        let mut stack: Vec<PathBuf> = [PathBuf::from(&res.source), config_path()]
+            .iter()
+            .filter_map(|p| std::fs::canonicalize(p).ok())
+            .collect();
+
+        let mut inherited = Vec::new();
+        Self::collect_inherited(&inherit, None, &mut stack, &mut inherited)?;
+
+        for (name, service) in inherited {
+            if let Some(existing) = res.services.get(&name) {
+                // the same definition in two files isn't a conflict
+                if !Self::same_definition(existing, &service) {
+                    res.duplicate_services.push(name.clone());
+
+                    match res.inherit_conflicts {
+                        InheritConflictPolicy::Error => {
+                            return Err(SprocError::ConfigParse(format!(
+                                "service is defined more than once ({name})"
+                            )))
+                        }
+                        InheritConflictPolicy::FirstWins => continue,
+                        InheritConflictPolicy::LastWins => (),
+                    }
+                }
+            }
+
+            // push service to main service stack
+            if let Some(own) = res.services.insert(name.clone(), service.clone()) {
+                if !res.inherited_services.contains_key(&name) {
+                    res.shadowed_services.insert(name.clone(), own);
+                }
+            }
+
+            res.inherited_services.insert(name, service);
+        }
+
+        // return
+        Ok(res)
+    }
+
+    /// Load the services of every inherited file (and the files they inherit) in order
+    ///
+    /// Relative entries in inherited files are resolved from that file's directory. `stack`
+    /// holds the files currently being loaded, so a file inheriting itself (directly or not)
+    /// is an error. Files that don't exist are skipped.
+    fn collect_inherited(
+        inherit: &[String],
+        dir: Option<&Path>,
+        stack: &mut Vec<PathBuf>,
+        services: &mut Vec<(String, Service)>,
+    ) -> std::result::Result<(), SprocError> {
+        let entries: Vec<String> = inherit
+            .iter()
+            .map(|entry| match dir {
+                Some(dir) if Path::new(entry).is_relative() && !entry.starts_with("~/") => {
+                    dir.join(entry).to_string_lossy().to_string()
+                }
+                _ => entry.to_owned(),
+            })
+            .collect();
+
+        for path in Self::expand_inherit(&entries) {
+            let canonical = match std::fs::canonicalize(&path) {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+
+            if stack.contains(&canonical) {
+                return Err(SprocError::ConfigParse(format!(
+                    "circular inherit: configuration inherits itself ({path})"
+                )));
+            }
+
+            let contents = match std::fs::read_to_string(&canonical) {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+
+            let file = Self::parse_file(&path, &contents)?;
+
+            services.extend(file.services);
+
+            if let Some(ref inherit) = file.inherit {
+                stack.push(canonical.clone());
+                Self::collect_inherited(inherit, canonical.parent(), stack, services)?;
+                stack.pop();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the configuration as it's written to the pinned file
+    ///
+    /// Services that are still defined the way an inherited file defines them are left out (or
+    /// written as the file's own definition, if it had one), so they're inherited again the next
+    /// time the file is read instead of being copied into it.
+    pub fn pinned_definitions(&self) -> Self {
+        let mut res = self.clone();
+
+        for (name, inherited) in &self.inherited_services {
+            match res.services.get(name) {
+                Some(s) if Self::same_definition(s, inherited) => (),
+                _ => continue,
+            }
+
+            match self.shadowed_services.get(name) {
+                Some(own) => res.services.insert(name.clone(), own.clone()),
+                None => res.services.remove(name),
+            };
+        }
+
+        res
+    }
+
+    /// Check if two service definitions are the same
+    fn same_definition(a: &Service, b: &Service) -> bool {
+        match (toml::Value::try_from(a), toml::Value::try_from(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Expand glob patterns (`*`, `?`) and `~/` in `inherit` entries
+    ///
+    /// Matches for each pattern are sorted, and patterns that match nothing are skipped.
+    pub fn expand_inherit(inherit: &[String]) -> Vec<String> {
+        let mut paths = Vec::new();
+
+        for entry in inherit {
+            let expanded;
+            let entry = match entry.strip_prefix("~/") {
+                Some(rest) => match home_dir() {
+                    Some(home) => {
+                        expanded = home.join(rest).to_string_lossy().to_string();
+                        &expanded
+                    }
+                    None => entry,
+                },
+                None => entry,
+            };
+
+            if !entry.contains("*") && !entry.contains("?") {
+                paths.push(entry.to_owned());
+                continue;
+            }
+
+            let mut matches: Vec<String> = match glob::glob(entry) {
+                Ok(m) => m
+                    .filter_map(|p| p.ok())
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect(),
+                Err(_) => continue,
+            };
+
+            matches.sort();
+            paths.append(&mut matches);
+        }
+
+        paths
+    }
+
+    /// Check if services are read from the environment (`SPROC_FROM_ENV=1`) instead of the
+    /// configuration file
+    pub fn from_env_enabled() -> bool {
+        env::var("SPROC_FROM_ENV").unwrap_or_default() == "1"
+    }
+
+    /// Pull configuration file
+    ///
+    /// Panics if it can't be read (see [`ServicesConfiguration::try_get_config`]); only use
+    /// this where there's nothing better to do with the error.
+    pub fn get_config() -> Self {
+        match Self::try_get_config() {
+            Ok(c) => c,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Pull configuration file, returning an error if it can't be read or parsed
+    ///
+    /// If the configuration file is corrupted, its backup (`services.toml.bak`) is restored.
+    /// A missing configuration file gives the default configuration. With `SPROC_FROM_ENV=1`,
+    /// the file isn't read at all and services come from [`ServicesConfiguration::from_env`].
+    pub fn try_get_config() -> std::result::Result<Self, SprocError> {
+        let e = match Self::read_config() {
+            Ok(c) => return Ok(c),
+            // the file isn't used at all then
+            Err(e) if Self::from_env_enabled() => return Err(e),
+            Err(e) => e,
+        };
+
+        let path = config_path();
+        let backup = backup_path(&path);
+
+        match std::fs::read_to_string(&backup) {
+            Ok(c) if Self::parse_file(&path.to_string_lossy(), &c).is_ok() => {
+                println!("warn: {e}");
+                println!(
+                    "warn: restoring configuration from backup ({})",
+                    backup.display()
+                );
+
+                write_atomic(&path, &c).map_err(SprocError::ConfigIo)?;
+                Self::read_config()
+            }
+            _ => Err(e),
+        }
+    }
+
+    /// Read the configuration file (and state)
+    fn read_config() -> std::result::Result<Self, SprocError> {
+        let path = config_path();
+
+        // make sure the config directory exists
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(SprocError::ConfigIo)?;
+        }
+
+        let mut res = if Self::from_env_enabled() {
+            // read services from the environment instead
+            Self::from_env()?
+        } else {
+            match Self::try_read_file(&path.to_string_lossy()) {
+                Ok(c) => c,
+                Err(SprocError::ConfigIo(e)) if e.kind() == ErrorKind::NotFound => Self::default(),
+                Err(e) => return Err(e),
+            }
+        };
+
+        // states (older files keep them in the configuration file itself)
+        res.load_state();
+
+        res.pre_config_check = Self::get_bootstrap().pre_config_check;
+        Ok(res)
+    }
+
+    /// Build configuration from environment variables
+    ///
+    /// * `SPROC_SERVICE_{NAME}_COMMAND`
+    /// * `SPROC_SERVICE_{NAME}_WORKDIR`
+    /// * `SPROC_SERVICE_{NAME}_RESTART`
+    /// * `SPROC_SERVICE_{NAME}_AUTOSTART`
+    /// * `SPROC_SERVER_PORT`
+    /// * `SPROC_SERVER_KEY`
+    ///
+    /// Service names are the `{NAME}` part in lowercase with `_` replaced by `-`.
+    pub fn from_env() -> Result<Self> {
+        let mut res = Self::default();
+        let mut commands: HashMap<String, String> = HashMap::new();
+        let mut workdirs: HashMap<String, String> = HashMap::new();
+        let mut restarts: HashMap<String, bool> = HashMap::new();
+        let mut autostarts: HashMap<String, bool> = HashMap::new();
+
+        for (key, value) in env::vars() {
+            if key == "SPROC_SERVER_PORT" {
+                res.server.port = match value.parse() {
+                    Ok(p) => p,
+                    Err(_) => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid server port. ({value})"),
+                        ))
+                    }
+                };
+
+                continue;
+            } else if key == "SPROC_SERVER_KEY" {
+                res.server.key = value;
+                continue;
+            }
+
+            let key = match key.strip_prefix("SPROC_SERVICE_") {
+                Some(k) => k,
+                None => continue,
+            };
+
+            let name = |n: &str| n.to_lowercase().replace("_", "-");
+
+            if let Some(n) = key.strip_suffix("_COMMAND") {
+                commands.insert(name(n), value);
+            } else if let Some(n) = key.strip_suffix("_WORKDIR") {
+                workdirs.insert(name(n), value);
+            } else if let Some(n) = key.strip_suffix("_RESTART") {
+                restarts.insert(name(n), value == "1" || value == "true");
+            } else if let Some(n) = key.strip_suffix("_AUTOSTART") {
+                autostarts.insert(name(n), value == "1" || value == "true");
+            }
+        }
+
+        for (name, command) in commands {
+            let working_directory = match workdirs.remove(&name) {
+                Some(w) => w,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Service is missing a working directory. ({name})"),
+                    ))
+                }
+            };
+
+            // deserialized so every other field gets its usual default
+            let service: Service = serde_json::from_value(serde_json::json!({
+                "command": command,
+                "working_directory": working_directory,
+                "restart": restarts.remove(&name).unwrap_or(false),
+                "autostart": autostarts.remove(&name).unwrap_or(false),
+            }))?;
+
+            res.services.insert(name, service);
+        }
+
+        // return
+        Ok(res)
+    }
+
+    /// Pull bootstrap configuration file (`bootstrap.toml`)
+    pub fn get_bootstrap() -> BootstrapConfiguration {
+        match std::fs::read_to_string(sproc_dir().join("bootstrap.toml")) {
+            Ok(c) => toml::from_str(&c).unwrap_or_default(),
+            Err(_) => BootstrapConfiguration::default(),
+        }
+    }
+
+    /// Run the `pre_config_check` command, waiting up to 30 seconds for it to exit
+    pub fn run_pre_config_check(command: &str) -> Result<()> {
+        let mut child = shell_command(command).stdout(Stdio::null()).spawn()?;
+
+        let started = Instant::now();
+
+        loop {
+            if let Some(status) = child.try_wait()? {
+                if !status.success() {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        format!("Pre-config check failed with {status}. ({command})"),
+                    ));
+                }
+
+                return Ok(());
+            }
+
+            if started.elapsed() >= Duration::from_secs(30) {
+                child.kill()?;
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("Pre-config check timed out. ({command})"),
+                ));
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Pull configuration, change it, and write it back while holding a [`ConfigLock`]
+    ///
+    /// Use this instead of [`ServicesConfiguration::get_config`] and
+    /// [`ServicesConfiguration::update_config`] so concurrent changes (e.g. from observer tasks)
+    /// aren't lost.
+    pub fn modify<T>(f: impl FnOnce(&mut Self) -> T) -> Result<T> {
+        let _lock = ConfigLock::acquire()?;
+
+        let mut config = Self::try_get_config()?;
+        let res = f(&mut config);
+
+        Self::update_config(config)?;
+        Ok(res)
+    }
+
+    /// Replace states with the ones from the [`RuntimeState`] file (if it exists)
+    pub fn load_state(&mut self) {
+        if let Some(state) = RuntimeState::get() {
+            self.service_states = state.service_states;
+            self.restart_counts = state.restart_counts;
+            self.last_exits = state.last_exits;
+            self.schedules = state.schedules;
+            self.fingerprints = state.fingerprints;
+            self.stopping = state.stopping;
+        }
+    }
+
+    /// Update configuration file
+    ///
+    /// States are written to the [`RuntimeState`] file, and the configuration file itself is only
+    /// rewritten if the definitions changed (never with `SPROC_FROM_ENV=1`).
+    pub fn update_config(contents: Self) -> Result<()> {
+        let state = RuntimeState {
+            service_states: contents.service_states.clone(),
+            restart_counts: contents.restart_counts.clone(),
+            last_exits: contents.last_exits.clone(),
+            schedules: contents.schedules.clone(),
+            fingerprints: contents.fingerprints.clone(),
+            stopping: contents.stopping.clone(),
+        };
+
+        // definitions come from the environment, so there's nothing to write
+        if Self::from_env_enabled() {
+            return state.update();
+        }
+
+        // the file is written in the format its extension says
+        let path = config_path();
+        let name = path.to_string_lossy().to_string();
+        let format = ConfigFormat::from_path(&path);
+
+        let definitions = format.serialize::<Self>(&contents.pinned_definitions());
+        let previous = match std::fs::read_to_string(&path) {
+            Ok(c) => Self::parse_file(&name, &c)
+                .ok()
+                .map(|c| format.serialize::<Self>(&c)),
+            _ => None,
+        };
+        let unchanged = previous.as_ref() == Some(&definitions);
+
+        let res = state.update().and_then(|_| {
+            if unchanged {
+                return Ok(());
+            }
+
+            // keep the last good configuration around in case this one ends up corrupted
+            if let Ok(c) = std::fs::read_to_string(&path) {
+                if Self::parse_file(&name, &c).is_ok() {
+                    write_atomic(&backup_path(&path), &c)?;
+                }
+            }
+
+            // JSON doesn't have comments
+            let header = match format {
+                ConfigFormat::Json => "",
+                _ => "# DO **NOT** MANUALLY EDIT THIS FILE! Please edit the source instead and run `sproc pin {path}`.\n",
+            };
+
+            write_atomic(&path, &format!("{header}{definitions}"))
+        });
+
+        if !unchanged {
+            let mut entry = AuditEntry::new(
+                AuditContext::default(),
+                AuditAction::ConfigUpdate,
+                None,
+                res.is_ok(),
+            );
+
+            // digests, since definitions can hold secrets
+            entry.old_value = previous.as_deref().map(audit::digest);
+            entry.new_value = Some(audit::digest(&definitions));
+
+            if let Err(e) = audit::record(entry) {
+                println!("warn: failed to write audit log: {e}");
+            }
+        }
+
+        res
+    }
+
+    /// Rename a service in the pinned configuration, keeping its state, pid file and restart history
+    ///
+    /// Returns the written configuration.
+    pub fn rename_service(old: &str, new: &str) -> Result<Self> {
+        let res = Self::modify(|config| config.rename(old, new).map(|_| config.clone()))
+            .and_then(|res| res);
+
+        let mut entry = AuditEntry::new(
+            AuditContext::default(),
+            AuditAction::Renamed,
+            Some(new.to_string()),
+            res.is_ok(),
+        );
+
+        entry.old_value = Some(old.to_string());
+        entry.new_value = Some(new.to_string());
+
+        if let Err(e) = audit::record(entry) {
+            println!("warn: failed to write audit log: {e}");
+        }
+
+        res
+    }
+
+    /// Rename a service, keeping its state
+    fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        if self.services.contains_key(new) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Service already exists. ({new})"),
+            ));
+        }
+
+        if !self.services.contains_key(old) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Service does not exist. ({old})"),
+            ));
+        }
+
+        // move the pid file first, so nothing is renamed if it can't be
+        if pid_path(old).exists() {
+            std::fs::rename(pid_path(old), pid_path(new))?;
+        }
+
+        rename_key(&mut self.services, old, new);
+
+        // update dependency references
+        for service in self.services.values_mut() {
+            for dependency in service.depends_on.iter_mut() {
+                if dependency == old {
+                    *dependency = new.to_string();
+                }
+            }
+        }
+
+        if let Some(state) = self.service_states.remove(old) {
+            if state.0.is_running() {
+                println!("warn: service \"{old}\" is running and will now be tracked as \"{new}\"");
+            }
+
+            self.service_states.insert(new.to_string(), state);
+        }
+
+        rename_key(&mut self.restart_counts, old, new);
+        rename_key(&mut self.last_exits, old, new);
+        rename_key(&mut self.schedules, old, new);
+        rename_key(&mut self.fingerprints, old, new);
+
+        if self.stopping.remove(old) {
+            self.stopping.insert(new.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Check service definitions for unknown dependencies, missing working directories,
+    /// and services replaced by inherited files
+    ///
+    /// Errors are sorted by service name.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+
+        let mut names: Vec<&String> = self.services.keys().collect();
+        names.sort();
+
+        // services by the address their health check (or `wait_for`) connects to
+        let mut addresses: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for name in names {
+            let service = &self.services[name];
+
+            for dep in &service.depends_on {
+                if !self.services.contains_key(dep) {
+                    errors.push(ValidationError::UnknownDependency {
+                        service: name.to_owned(),
+                        dep: dep.to_owned(),
+                    });
+                }
+            }
+
+            if !std::path::Path::new(&service.working_directory).is_dir() {
+                errors.push(ValidationError::InvalidWorkingDirectory {
+                    service: name.to_owned(),
+                    path: service.working_directory.clone(),
+                });
+            }
+
+            if service.command.trim().is_empty() {
+                errors.push(ValidationError::EmptyCommand {
+                    service: name.to_owned(),
+                });
+            }
+
+            if let Some(address) = service.checked_address() {
+                addresses.entry(address).or_default().push(name.to_owned());
+            }
+
+            if let Some(ref schedule) = service.schedule {
+                if scheduler::parse(schedule).is_err() {
+                    errors.push(ValidationError::InvalidSchedule {
+                        service: name.to_owned(),
+                        schedule: schedule.to_owned(),
+                    });
+                }
+            }
+        }
+
+        let mut duplicates = self.duplicate_services.clone();
+        duplicates.sort();
+        duplicates.dedup();
+
+        for name in duplicates {
+            errors.push(ValidationError::DuplicateServiceName { name });
+        }
+
+        for (address, services) in addresses {
+            if services.len() > 1 {
+                errors.push(ValidationError::PortClash { services, address });
+            }
+        }
+
+        errors
+    }
+
+    /// [`ServicesConfiguration::validate`], split into errors and warnings
+    pub fn check(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        for error in self.validate() {
+            if error.is_error() {
+                report.errors.push(error);
+            } else {
+                report.warnings.push(error);
+            }
+        }
+
+        report
+    }
+
+    /// Get the order a service and its dependencies (`depends_on`, recursively) must be
+    /// started in, ending with the service itself
+    pub fn dependency_order(&self, name: &str) -> Result<Vec<String>> {
+        fn visit(
+            config: &ServicesConfiguration,
+            name: &str,
+            visiting: &mut Vec<String>,
+            order: &mut Vec<String>,
+        ) -> Result<()> {
+            if order.iter().any(|n| n == name) {
+                return Ok(());
+            }
+
+            if visiting.iter().any(|n| n == name) {
+                visiting.push(name.to_string());
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Circular dependency. ({})", visiting.join(" -> ")),
+                ));
+            }
+
+            let service = match config.services.get(name) {
+                Some(s) => s,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("Service does not exist. ({name})"),
+                    ))
+                }
+            };
+
+            visiting.push(name.to_string());
+
+            for dependency in &service.depends_on {
+                visit(config, dependency, visiting, order)?;
+            }
+
+            visiting.pop();
+            order.push(name.to_string());
+            Ok(())
+        }
+
+        let mut order = Vec::new();
+        visit(self, name, &mut Vec::new(), &mut order)?;
+        Ok(order)
+    }
+
+    /// Get the running services that depend on a service (`depends_on`, recursively), in the
+    /// order they must be stopped
+    pub fn running_dependents(&self, name: &str) -> Vec<String> {
+        fn visit(
+            config: &ServicesConfiguration,
+            name: &str,
+            seen: &mut Vec<String>,
+            order: &mut Vec<String>,
+        ) {
+            let mut dependents: Vec<&String> = config
+                .services
+                .iter()
+                .filter(|(_, s)| s.depends_on.iter().any(|d| d == name))
+                .map(|(n, _)| n)
+                .collect();
+            dependents.sort();
+
+            for dependent in dependents {
+                if seen.contains(dependent) {
+                    continue;
+                }
+
+                seen.push(dependent.to_owned());
+
+                // dependents of dependents are stopped first
+                visit(config, dependent, seen, order);
+                order.push(dependent.to_owned());
+            }
+        }
+
+        let mut order = Vec::new();
+        visit(self, name, &mut vec![name.to_string()], &mut order);
+
+        order
+            .into_iter()
+            .filter(|n| match self.service_states.get(n) {
+                Some(s) => s.0.is_running(),
+                None => false,
+            })
+            .collect()
+    }
+
+    /// Get the order services would be started in based on their `depends_on`
+    ///
+    /// Each layer only depends on services in earlier layers, so services in the same
+    /// layer can be started in parallel. Names are sorted within each layer.
+    pub fn simulate_start_order(&self) -> std::result::Result<Vec<Vec<String>>, ConfigError> {
+        // check dependencies exist
+        for (name, service) in &self.services {
+            for dependency in &service.depends_on {
+                if !self.services.contains_key(dependency) {
+                    return Err(ConfigError::MissingDependency(
+                        name.to_owned(),
+                        dependency.to_owned(),
+                    ));
+                }
+            }
+        }
+
+        // build layers
+        let mut layers: Vec<Vec<String>> = Vec::new();
+        let mut placed: std::collections::HashSet<&String> = std::collections::HashSet::new();
+
+        while placed.len() < self.services.len() {
+            let mut layer: Vec<String> = self
+                .services
+                .iter()
+                .filter(|(name, service)| {
+                    !placed.contains(name) && service.depends_on.iter().all(|d| placed.contains(d))
+                })
+                .map(|(name, _)| name.to_owned())
+                .collect();
+
+            if layer.is_empty() {
+                // everything left is in (or waiting on) a cycle
+                return Err(ConfigError::CircularDependency(self.find_cycle(&placed)));
+            }
+
+            layer.sort();
+
+            for name in &layer {
+                placed.insert(self.services.get_key_value(name).unwrap().0);
+            }
+
+            layers.push(layer);
+        }
+
+        // return
+        Ok(layers)
+    }
+
+    /// Get the layers of [`ServicesConfiguration::simulate_start_order`], or every service in
+    /// one layer (sorted by name) if the dependency graph is broken
+    pub fn start_groups(&self) -> Vec<Vec<String>> {
+        match self.simulate_start_order() {
+            Ok(layers) => layers,
+            Err(e) => {
+                println!("warn: {e}");
+
+                let mut names: Vec<String> = self.services.keys().cloned().collect();
+                names.sort();
+                vec![names]
+            }
+        }
+    }
+
+    /// Find a dependency cycle among the services that aren't in `placed`
+    fn find_cycle(&self, placed: &std::collections::HashSet<&String>) -> Vec<String> {
+        let mut names: Vec<&String> = self
+            .services
+            .keys()
+            .filter(|n| !placed.contains(n))
+            .collect();
+        names.sort();
+
+        // every remaining service has a remaining dependency, so following them must loop
+        let mut path: Vec<String> = Vec::new();
+        let mut current = match names.first() {
+            Some(n) => n.to_string(),
+            None => return path,
+        };
+
+        while !path.contains(&current) {
+            path.push(current.clone());
+
+            let mut deps: Vec<&String> = self.services[&current]
+                .depends_on
+                .iter()
+                .filter(|d| !placed.contains(d))
+                .collect();
+            deps.sort();
+
+            current = deps[0].to_string();
+        }
+
+        // trim the path down to just the loop
+        let start = path.iter().position(|n| n == &current).unwrap();
+        let mut cycle = path.split_off(start);
+        cycle.push(current);
+        cycle
+    }
+
+    /// Convert all services into a Docker Compose file
+    pub fn as_docker_compose(&self) -> String {
+        use serde_yaml::{Mapping, Value};
+        let mut services = Mapping::new();
+
+        let mut names: Vec<&String> = self.services.keys().collect();
+        names.sort();
+
+        for name in names {
+            services.insert(
+                name.as_str().into(),
+                self.services
+                    .get(name)
+                    .unwrap()
+                    .as_docker_compose_service(name),
+            );
+        }
+
+        let mut compose = Mapping::new();
+        compose.insert("version".into(), "3".into());
+        compose.insert("services".into(), Value::Mapping(services));
+
+        format!(
+            "# generated by sproc\n# image: NONE must be filled in for every service (sproc doesn't manage images)\n# applications, metadata, and build steps have no equivalent and are not exported\n{}",
+            serde_yaml::to_string(&compose).unwrap()
+        )
+    }
+
+    /// Get the PIDs of running services
+    pub fn running_pids(&self) -> Vec<u32> {
+        self.service_states
+            .values()
+            .filter(|s| s.0.is_running())
+            .map(|s| s.1)
+            .collect()
+    }
+
+    /// Get every configured service with its state and process info (sorted by name)
+    pub fn summary(&self) -> ServicesSummary {
+        snapshot::with_processes(&self.running_pids(), |sys| self.summary_from(sys))
+    }
+
+    /// [`ServicesConfiguration::summary`] from a [`System`] snapshot
+    fn summary_from(&self, sys: &System) -> ServicesSummary {
+        let mut summary = ServicesSummary::default();
+
+        let mut names: Vec<&String> = self.services.keys().collect();
+        names.sort();
+
+        for name in names {
+            let state = self.service_states.get(name);
+            let status = ServiceStatus {
+                name: name.to_owned(),
+                state: state.map(|s| s.0.clone()).unwrap_or_default(),
+                pid: state.map(|s| s.1),
+                restart: self.services[name].restart,
+                info: Service::info_from(name.to_owned(), self, sys).ok(),
+                last_exit: self.last_exits.get(name).cloned(),
+            };
+
+            match status.state {
+                ServiceState::Running => summary.running += 1,
+                ServiceState::Unhealthy => summary.unhealthy += 1,
+                ServiceState::Failed(..) => summary.failed += 1,
+                ServiceState::Completed(_) => summary.completed += 1,
+                ServiceState::Stopped => summary.stopped += 1,
+            }
+
+            summary.services.push(status);
+        }
+
+        summary
+    }
+
+    /// Get every running service's info (sorted by name) with their total resource usage, from
+    /// a single process snapshot
+    pub fn info_all(&self) -> ServicesInfo {
+        snapshot::with_processes(&self.running_pids(), |sys| self.info_all_from(sys))
+    }
+
+    /// [`ServicesConfiguration::info_all`] from a [`System`] snapshot
+    fn info_all_from(&self, sys: &System) -> ServicesInfo {
+        let mut res = ServicesInfo::default();
+
+        let mut names: Vec<&String> = self
+            .service_states
+            .iter()
+            .filter(|(_, s)| s.0.is_running())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        for name in names {
+            if let Ok(info) = Service::info_from(name.to_owned(), self, sys) {
+                res.total_memory += info.memory;
+                res.total_cpu += info.cpu;
+                res.services.push(info);
+            }
+        }
+
+        res
+    }
+
+    /// Record how a service that was waited on exited: [`ServiceState::Completed`] if it exited
+    /// successfully, or [`ServiceState::Failed`]
+    pub fn record_exit(&mut self, name: &str, status: ExitStatus, pid: u32) {
+        let timestamp = (epoch_timestamp() / 1000) as u64;
+        let state = match status.code() {
+            Some(0) => ServiceState::Completed(timestamp),
+            code => ServiceState::Failed(code.unwrap_or(-1), timestamp),
+        };
+
+        self.service_states.insert(name.to_string(), (state, pid));
+        self.last_exits
+            .insert(name.to_string(), ExitInfo::new(status, 0));
+    }
+
+    /// Mark a service as running, fingerprinting its process
+    pub fn set_running(&mut self, name: &str, pid: u32) {
+        self.service_states
+            .insert(name.to_string(), (ServiceState::Running, pid));
+        self.stopping.remove(name);
+
+        match ProcessFingerprint::capture(pid) {
+            Some(f) => self.fingerprints.insert(name.to_string(), f),
+            None => self.fingerprints.remove(name),
+        };
+    }
+
+    /// Cross-reference service states with running OS processes
+    pub fn ps(&self) -> ProcessReport {
+        let sys = System::new_all();
+        let mut report = ProcessReport::default();
+
+        // tracked services
+        let mut names: Vec<&String> = self.service_states.keys().collect();
+        names.sort();
+
+        for name in names {
+            let state = &self.service_states[name];
+            let process = verified_process(&sys, state.1, self.fingerprints.get(name));
+
+            report.tracked.push(TrackedProcess {
+                name: name.to_owned(),
+                state: state.0.clone(),
+                pid: state.1,
+                alive: process.is_some(),
+                memory: process.map(|p| p.memory()).unwrap_or(0),
+                cpu: process.map(|p| p.cpu_usage()).unwrap_or(0.0),
+            });
+        }
+
+        // orphans (same executable and working directory as a service, but not tracked)
+        let tracked_pids: Vec<u32> = self.service_states.values().map(|s| s.1).collect();
+
+        for (pid, process) in sys.processes() {
+            let pid = pid.as_u32();
+
+            if tracked_pids.contains(&pid) {
+                continue;
+            }
+
+            for (name, service) in &self.services {
+                let executable = service.executable().unwrap_or_default();
+
+                let same_exe = !executable.is_empty() && process.name() == executable;
+                let same_cwd = process
+                    .cwd()
+                    .map(|c| c == std::path::Path::new(&service.working_directory))
+                    .unwrap_or(false);
+
+                if same_exe && same_cwd {
+                    report.orphans.push(OrphanProcess {
+                        name: name.to_owned(),
+                        pid,
+                        executable: process.name().to_string(),
+                        working_directory: service.working_directory.clone(),
+                    });
+
+                    break;
+                }
+            }
+        }
+
+        report
+            .orphans
+            .sort_by(|a, b| a.name.cmp(&b.name).then(a.pid.cmp(&b.pid)));
+        report
+    }
+
+    /// Kill orphaned processes and mark states without a process as stopped (`self` is updated
+    /// to the written configuration)
+    pub fn reconcile(&mut self, report: &ProcessReport) -> Result<()> {
+        let sys = System::new_all();
+
+        for orphan in &report.orphans {
+            if let Some(process) = sys.process(Pid::from(orphan.pid as usize)) {
+                process.kill();
+            }
+        }
+
+        *self = ServicesConfiguration::modify(|config| {
+            for tracked in &report.tracked {
+                if tracked.alive {
+                    continue;
+                }
+
+                if let Some(state) = config.service_states.get_mut(&tracked.name) {
+                    state.0 = ServiceState::Stopped;
+                }
+            }
+
+            config.clone()
+        })?;
+
+        Ok(())
+    }
+
+    /// Remove stale runtime artifacts, returning a description of each one removed
+    ///
+    /// Stale artifacts are:
+    /// * service states for services that no longer exist
+    /// * running service states whose process is no longer alive
+    /// * module build directories for services that no longer exist
+    /// * PID files whose process is no longer alive
+    /// * the configuration's backup, once it's older than [`BACKUP_MAX_AGE_SECS`]
+    ///
+    /// When `dry_run` is set, nothing is removed.
+    pub fn gc(&mut self, dry_run: bool) -> Result<Vec<String>> {
+        let mut removed = Vec::new();
+        let sys = snapshot::load(&self.running_pids());
+
+        // service states
+        let stale: Vec<String> = self
+            .service_states
+            .iter()
+            .filter(|(name, state)| {
+                !self.services.contains_key(*name)
+                    || (state.0.is_running()
+                        && verified_process(&sys, state.1, self.fingerprints.get(*name)).is_none())
+            })
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        for name in stale {
+            if !dry_run {
+                self.service_states.remove(&name);
+                self.fingerprints.remove(&name);
+            }
+
+            removed.push(format!("state: {name}"));
+        }
+
+        // module directories
+        let modules = data_dir().join("modules").to_string_lossy().to_string();
+
+        if let Ok(entries) = std::fs::read_dir(&modules) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name().to_string_lossy().to_string();
+
+                if self.services.contains_key(&name) {
+                    continue;
+                }
+
+                if !dry_run {
+                    std::fs::remove_dir_all(entry.path())?;
+                }
+
+                removed.push(format!("module: {modules}/{name}"));
+            }
+        }
+
+        // pid files
+        if let Ok(entries) = std::fs::read_dir(sproc_dir().join("pids")) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+
+                let name = match path.file_stem() {
+                    Some(n) if path.extension().is_some_and(|e| e == "pid") => {
+                        n.to_string_lossy().to_string()
+                    }
+                    _ => continue,
+                };
+
+                if Service::pid_file_process(&name).is_some() {
+                    continue;
+                }
+
+                if !dry_run {
+                    std::fs::remove_file(&path)?;
+                }
+
+                removed.push(format!("pid: {}", path.display()));
+            }
+        }
+
+        // configuration backup
+        let backup = backup_path(&config_path());
+
+        let aged = std::fs::metadata(&backup)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age.as_secs() > BACKUP_MAX_AGE_SECS);
+
+        if aged {
+            if !dry_run {
+                std::fs::remove_file(&backup)?;
+            }
+
+            removed.push(format!("backup: {}", backup.display()));
+        }
+
+        // return
+        Ok(removed)
+    }
+
+    /// Add a service for every template instance in `instances`
+    pub fn expand_templates(&mut self) -> std::result::Result<(), SprocError> {
+        let templates = match self.templates {
+            Some(ref t) => t.clone(),
+            None => return Ok(()),
+        };
+
+        for (name, template) in templates {
+            for instance in &template.instances {
+                self.services.insert(
+                    format!("{name}@{instance}"),
+                    template.instantiate(&name, instance)?,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the service for a template instance (`{template}@{instance}`) if it isn't defined
+    /// yet, returning if it was added
+    pub fn instantiate(&mut self, name: &str) -> std::result::Result<bool, SprocError> {
+        if self.services.contains_key(name) {
+            return Ok(false);
+        }
+
+        let (template, instance) = match name.split_once("@") {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+
+        let template = match self.templates.as_ref().and_then(|t| t.get(template)) {
+            Some(t) => t.instantiate(template, instance)?,
+            None => return Ok(false),
+        };
+
+        self.services.insert(name.to_string(), template);
+        Ok(true)
+    }
+
+    /// Get a remote server by name
+    pub fn remote(&self, name: &str) -> Result<&Remote> {
+        match self.remotes.as_ref().and_then(|r| r.get(name)) {
+            Some(r) => Ok(r),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Remote does not exist. ({name})"),
+            )),
+        }
+    }
+
+    /// Merge services from other [`ServicesConfiguration`]
+    pub fn merge_config(&mut self, other: Self) -> () {
+        for service in other.services {
+            // push service to main service stack
+            self.services.insert(service.0, service.1);
+        }
+    }
+
+    /// Compare this configuration's definitions with `other` (sorted by service name)
+    pub fn diff(&self, other: &Self) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+
+        for (name, service) in &other.services {
+            let existing = match self.services.get(name) {
+                Some(s) => s,
+                None => {
+                    diff.added.push(name.to_owned());
+                    continue;
+                }
+            };
+
+            let (a, b) = match (
+                toml::Value::try_from(existing),
+                toml::Value::try_from(service),
+            ) {
+                (Ok(toml::Value::Table(a)), Ok(toml::Value::Table(b))) => (a, b),
+                _ => continue,
+            };
+
+            let mut fields: Vec<String> = a
+                .keys()
+                .chain(b.keys())
+                .filter(|k| a.get(*k) != b.get(*k))
+                .cloned()
+                .collect();
+
+            if fields.is_empty() {
+                continue;
+            }
+
+            fields.sort();
+            fields.dedup();
+
+            diff.changed.push(ServiceChange {
+                service: name.to_owned(),
+                fields,
+            });
+        }
+
+        for name in self.services.keys() {
+            if !other.services.contains_key(name) {
+                diff.removed.push(name.to_owned());
+            }
+        }
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort_by(|a, b| a.service.cmp(&b.service));
+
+        // everything else is compared as a whole
+        let settings = |config: &Self| {
+            let mut config = config.clone();
+            config.services.clear();
+            config.source.clear();
+            toml::Value::try_from(config).ok()
+        };
+
+        diff.settings_changed = settings(self) != settings(other);
+        diff
+    }
+
+    /// Replace the pinned configuration with a new document (like `sproc pin`), keeping the
+    /// states of services that still exist
+    ///
+    /// `inherit` is applied to the new document. Services that are running can't be removed.
+    /// With `dry_run`, nothing is written and only the [`ConfigDiff`] is returned.
+    pub fn apply_document(
+        contents: &str,
+        format: ConfigFormat,
+        dry_run: bool,
+    ) -> std::result::Result<ConfigDiff, SprocError> {
+        let mut new = Self::resolve(format.parse(contents)?)?;
+
+        let _lock = ConfigLock::acquire()?;
+        let config = Self::try_get_config()?;
+        let diff = config.diff(&new);
+
+        for name in &diff.removed {
+            if let Some((state, pid)) = config.service_states.get(name) {
+                if state.is_running() {
+                    return Err(SprocError::AlreadyRunning(name.to_owned(), Some(*pid)));
+                }
+            }
+        }
+
+        if dry_run || diff.is_empty() {
+            return Ok(diff);
+        }
+
+        if new.source.is_empty() {
+            new.source = config.source;
+        }
+
+        // keep states, forgetting removed services
+        let services = new.services.clone();
+        new.service_states = config.service_states;
+        new.service_states
+            .retain(|name, _| services.contains_key(name));
+        new.restart_counts = config.restart_counts;
+        new.restart_counts
+            .retain(|name, _| services.contains_key(name));
+        new.last_exits = config.last_exits;
+        new.last_exits.retain(|name, _| services.contains_key(name));
+        new.schedules = config.schedules;
+        new.schedules.retain(|name, _| services.contains_key(name));
+        new.fingerprints = config.fingerprints;
+        new.fingerprints
+            .retain(|name, _| services.contains_key(name));
+        new.stopping = config.stopping;
+        new.stopping.retain(|name| services.contains_key(name));
+
+        Self::update_config(new)?;
+        Ok(diff)
+    }
+}
+
+/// Request body for updating a service
+#[derive(Serialize, Deserialize)]
+pub struct RegistryPushRequestBody {
+    /// Auth key
+    pub key: String,
+    /// The service's content in TOML form
+    pub content: String,
+}
+
+/// Request body for deleting a service
+#[derive(Serialize, Deserialize)]
+pub struct RegistryDeleteRequestBody {
+    /// Auth key
+    pub key: String,
+}
+
+/// A simple registry for service files
+#[derive(Debug, Clone)]
+pub struct Registry(pub ServerConfiguration, pub String);
+
+impl Registry {
+    /// Create a new [`Registry`]
+    pub fn new(config: ServerConfiguration) -> Self {
+        // registry file storage location
+        let data = data_dir();
+        let dir = data.join("registry").to_string_lossy().to_string();
+
+        // create registry dir
+        fs::mkdir(&dir).expect("failed to create directory");
+        fs::mkdir(&data.join("static").to_string_lossy().to_string())
+            .expect("failed to create static directory");
+
+        // return
+        Self(config, dir)
+    }
+
+    /// Get a service given its name
+    pub fn get(&self, service: String) -> Result<String> {
+        if self.0.registry.enabled == false {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Registry is disabled",
+            ));
+        }
+
+        // return
+        fs::read(format!("{}/{}.toml", self.1, service))
+    }
+
+    /// Update (or create) a service given its name and value
+    pub fn push(&self, props: RegistryPushRequestBody, service: String) -> Result<()> {
+        if self.0.registry.enabled == false {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Registry is disabled",
+            ));
+        }
+
+        // check key
+        if props.key != self.0.key {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Key is invalid"));
+        }
+
+        // validate
+        if let Err(e) = toml::from_str::<Service>(&props.content) {
+            return Err(Error::new(ErrorKind::InvalidInput, e.to_string()));
+        };
+
+        // return
+        fs::write(format!("{}/{}.toml", self.1, service), &props.content)
+    }
+
+    /// Delete a service given its name
+    pub fn delete(&self, props: RegistryDeleteRequestBody, service: String) -> Result<()> {
+        if self.0.registry.enabled == false {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Registry is disabled",
+            ));
+        }
+
+        // check key
+        if props.key != self.0.key {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Key is invalid"));
+        }
+
+        // return
+        fs::rm(format!("{}/{}.toml", self.1, service))
+    }
+}
+
+/// Helpers for tests that need files (or a configuration directory) of their own
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::path::PathBuf;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Held by tests that change the environment, since it's shared by every test
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Create an empty directory for a test (in the system's temporary directory)
+    pub fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sproc-test-{}-{name}", std::process::id()));
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Point `XDG_CONFIG_HOME` (and `SPROC_CONFIG`, at `xsu-apps/sproc/services.toml` in it)
+    /// at a new [`temp_dir`], returning sproc's directory in it
+    ///
+    /// Other tests can't change the environment until the returned guard is dropped.
+    pub fn config_dir(name: &str) -> (PathBuf, MutexGuard<'static, ()>) {
+        let guard = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let home = temp_dir(name);
+        let dir = home.join("xsu-apps").join("sproc");
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", &home);
+        std::env::set_var("SPROC_CONFIG", dir.join("services.toml"));
+
+        (dir, guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn pre_config_check_runs_to_completion() {
+        let dir = testing::temp_dir("pre-config-check");
+        let marker = dir.join("ready");
+
+        ServicesConfiguration::run_pre_config_check(&format!("touch {}", marker.display()))
+            .unwrap();
+
+        assert!(marker.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failing_pre_config_check_is_an_error() {
+        let error = ServicesConfiguration::run_pre_config_check("exit 3").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn gc_removes_stale_artifacts() {
+        let (dir, _env) = testing::config_dir("gc");
+        let mut config = ServicesConfiguration::read(
+            r#"
+            [services.kept]
+            command = "true"
+            working_directory = "/"
+            "#
+            .to_string(),
+        );
+
+        // a state for a service that doesn't exist, and a running state without a process
+        config
+            .service_states
+            .insert("removed".to_string(), (ServiceState::Stopped, 0));
+        config
+            .service_states
+            .insert("kept".to_string(), (ServiceState::Running, u32::MAX - 1));
+
+        for name in ["removed", "kept"] {
+            std::fs::create_dir_all(dir.join("modules").join(name)).unwrap();
+        }
+
+        // a pid file for a dead process, and one for a live process
+        Service::write_pid_file("dead", u32::MAX - 1).unwrap();
+        Service::write_pid_file("alive", std::process::id()).unwrap();
+
+        // a week-old backup
+        let backup = backup_path(&dir.join("services.toml"));
+        std::fs::write(&backup, "").unwrap();
+        let week = std::time::Duration::from_secs(BACKUP_MAX_AGE_SECS + 60);
+        std::fs::File::options()
+            .write(true)
+            .open(&backup)
+            .unwrap()
+            .set_modified(SystemTime::now() - week)
+            .unwrap();
+
+        // dry run
+        let mut removed = config.gc(true).unwrap();
+        removed.sort();
+
+        assert_eq!(removed.len(), 5);
+        let modules = dir.join("modules");
+        assert_eq!(removed[0], format!("backup: {}", backup.display()));
+        assert_eq!(removed[1], format!("module: {}/removed", modules.display()));
+        assert_eq!(removed[2], format!("pid: {}", pid_path("dead").display()));
+        assert_eq!(removed[3..], ["state: kept", "state: removed"]);
+        assert_eq!(config.service_states.len(), 2);
+        assert!(modules.join("removed").exists());
+        assert!(pid_path("dead").exists() && backup.exists());
+
+        // remove
+        assert_eq!(config.gc(false).unwrap().len(), 5);
+        assert!(config.service_states.is_empty());
+        assert!(!modules.join("removed").exists());
+        assert!(modules.join("kept").exists());
+        assert!(!pid_path("dead").exists() && !backup.exists());
+        assert!(pid_path("alive").exists());
+
+        // a fresh backup is kept
+        std::fs::write(&backup, "").unwrap();
+        assert!(config.gc(false).unwrap().is_empty());
+        assert!(backup.exists());
+    }
+
+    #[test]
+    fn pinned_files_keep_their_format() {
+        let (dir, _env) = testing::config_dir("pinned_format");
+
+        for name in ["services.json", "services.yaml"] {
+            let path = dir.join(name);
+            std::env::set_var("SPROC_CONFIG", &path);
+
+            let config = ServicesConfiguration::read(
+                r#"
+                [services.web]
+                command = "serve"
+                working_directory = "/"
+                "#
+                .to_string(),
+            );
+            ServicesConfiguration::update_config(config).unwrap();
+
+            let contents = std::fs::read_to_string(&path).unwrap();
+            assert!(ServicesConfiguration::parse_file(name, &contents).is_ok());
+            assert_eq!(
+                ServicesConfiguration::get_config().services["web"].command,
+                "serve"
+            );
+
+            // unchanged definitions aren't written again
+            let config = ServicesConfiguration::get_config();
+            ServicesConfiguration::update_config(config).unwrap();
+            assert!(!backup_path(&path).exists());
+        }
+    }
+
+    #[test]
+    fn inherited_services_are_not_copied_into_the_pinned_file() {
+        let (dir, _env) = testing::config_dir("inherit_pinned");
+        let inherited = dir.join("inherited.toml");
+        let write_inherited = |command: &str| {
+            std::fs::write(
+                &inherited,
+                format!(
+                    "[services.web]\ncommand = \"{command}\"\nworking_directory = \"/\"\n\n\
+                     [services.worker]\ncommand = \"work\"\nworking_directory = \"/\"\n"
+                ),
+            )
+            .unwrap();
+        };
+
+        write_inherited("serve --port 8080");
+
+        let config = ServicesConfiguration::read(format!(
+            r#"
+            inherit = ["{}"]
+            inherit_conflicts = "first_wins"
+
+            [services.worker]
+            command = "work --own"
+            working_directory = "/"
+            "#,
+            inherited.display()
+        ));
+        ServicesConfiguration::update_config(config).unwrap();
+
+        // only the file's own definitions are written
+        let pinned = ServicesConfiguration::parse(
+            &std::fs::read_to_string(dir.join("services.toml")).unwrap(),
+        )
+        .unwrap();
+        assert!(!pinned.services.contains_key("web"));
+        assert_eq!(pinned.services["worker"].command, "work --own");
+
+        // changes to the inherited file are picked up instead of conflicting with a copy
+        write_inherited("serve --port 8081");
+
+        let config = ServicesConfiguration::get_config();
+        assert_eq!(config.services["web"].command, "serve --port 8081");
+        assert_eq!(config.services["worker"].command, "work --own");
+        assert_eq!(config.duplicate_services, ["worker"]);
+
+        // the file's own definition is kept when an inherited one wins
+        let mut config = config;
+        config.inherit_conflicts = InheritConflictPolicy::LastWins;
+        ServicesConfiguration::update_config(config).unwrap();
+
+        let config = ServicesConfiguration::get_config();
+        assert_eq!(config.services["worker"].command, "work");
+        ServicesConfiguration::update_config(config).unwrap();
+
+        let pinned = std::fs::read_to_string(dir.join("services.toml")).unwrap();
+        assert!(pinned.contains("work --own") && !pinned.contains("[services.web]"));
+    }
+
+    #[test]
+    fn runtime_state_round_trip() {
+        let (dir, _env) = testing::config_dir("runtime_state");
+        assert!(RuntimeState::get().is_none());
+
+        let state = RuntimeState {
+            service_states: HashMap::from([
+                ("web".to_string(), (ServiceState::Running, 1234)),
+                ("api".to_string(), (ServiceState::Failed(3, 1700000000), 0)),
+                (
+                    "migrate".to_string(),
+                    (ServiceState::Completed(1700000001), 0),
+                ),
+            ]),
+            restart_counts: HashMap::from([("api".to_string(), 2)]),
+            last_exits: HashMap::from([(
+                "api".to_string(),
+                ExitInfo {
+                    code: Some(3),
+                    signal: None,
+                    timestamp: 1700000000,
+                    restarts: 2,
+                },
+            )]),
+            schedules: HashMap::from([(
+                "backup".to_string(),
+                ScheduleState {
+                    last_run: Some(1700000000),
+                    next_run: Some(1700003600),
+                },
+            )]),
+            fingerprints: HashMap::from([(
+                "web".to_string(),
+                ProcessFingerprint {
+                    pid: 1234,
+                    start_time: 1699999999,
+                    name: "node".to_string(),
+                },
+            )]),
+            stopping: HashSet::from(["web".to_string()]),
+        };
+
+        state.update().unwrap();
+        assert!(dir.join("state.toml").exists());
+
+        let read = RuntimeState::get().unwrap();
+        assert_eq!(read.service_states, state.service_states);
+        assert_eq!(read.restart_counts, state.restart_counts);
+        assert_eq!(read.last_exits, state.last_exits);
+        assert_eq!(read.schedules, state.schedules);
+        assert_eq!(read.fingerprints, state.fingerprints);
+        assert_eq!(read.stopping, state.stopping);
+
+        // the state file takes priority over states kept in older configuration files
+        let mut config = ServicesConfiguration::read(
+            "services = {}\n[service_states]\nweb = [\"Stopped\", 1]\n".to_string(),
+        );
+        assert_eq!(config.service_states["web"], (ServiceState::Stopped, 1));
+
+        config.load_state();
+        assert_eq!(config.service_states, state.service_states);
+        assert_eq!(config.stopping, state.stopping);
+    }
+
+    #[test]
+    fn concurrent_modifications_are_not_lost() {
+        let (_dir, _env) = testing::config_dir("modify_contention");
+        ServicesConfiguration::update_config(ServicesConfiguration::default()).unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..10 {
+                        ServicesConfiguration::modify(|config| {
+                            *config.restart_counts.entry("web".to_string()).or_default() += 1;
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let config = ServicesConfiguration::get_config();
+        assert_eq!(config.restart_counts["web"], 80);
+    }
+
+    #[test]
+    fn corrupt_configurations_are_restored_from_their_backup() {
+        let (dir, _env) = testing::config_dir("backup");
+        let path = dir.join("services.toml");
+
+        let config = ServicesConfiguration::read(
+            r#"
+            [services.web]
+            command = "node index.js"
+            working_directory = "/srv/web"
+            "#
+            .to_string(),
+        );
+
+        // the second write backs up the first
+        ServicesConfiguration::update_config(config.clone()).unwrap();
+        ServicesConfiguration::update_config(ServicesConfiguration::default()).unwrap();
+        std::fs::write(&path, "services = [").unwrap();
+
+        let restored = ServicesConfiguration::try_get_config().unwrap();
+        assert!(restored.services.contains_key("web"));
+        assert!(ServicesConfiguration::parse(&std::fs::read_to_string(&path).unwrap()).is_ok());
+
+        // nothing to restore
+        std::fs::write(&path, "services = [").unwrap();
+        std::fs::write(backup_path(&path), "services = [").unwrap();
+
+        let error = ServicesConfiguration::try_get_config().unwrap_err();
+        assert!(matches!(error, SprocError::ConfigParse(_)));
+        assert!(ServicesConfiguration::modify(|_| ()).is_err());
+    }
+
+    #[test]
+    fn from_env_matches_toml() {
+        let (_, _env) = testing::config_dir("from-env");
+        let vars = [
+            ("SPROC_SERVER_PORT", "8080"),
+            ("SPROC_SERVER_KEY", "secret"),
+            ("SPROC_SERVICE_WEB_APP_COMMAND", "node index.js"),
+            ("SPROC_SERVICE_WEB_APP_WORKDIR", "/srv/web"),
+            ("SPROC_SERVICE_WEB_APP_RESTART", "true"),
+            ("SPROC_SERVICE_WORKER_COMMAND", "worker"),
+            ("SPROC_SERVICE_WORKER_WORKDIR", "/srv/worker"),
+            ("SPROC_SERVICE_WORKER_AUTOSTART", "1"),
+        ];
+
+        for (key, value) in vars {
+            env::set_var(key, value);
+        }
+
+        let config = ServicesConfiguration::from_env();
+
+        for (key, _) in vars {
+            env::remove_var(key);
+        }
+
+        let expected = ServicesConfiguration::read(
+            r#"
+            [server]
+            port = 8080
+            key = "secret"
+
+            [services.web-app]
+            command = "node index.js"
+            working_directory = "/srv/web"
+            restart = true
+
+            [services.worker]
+            command = "worker"
+            working_directory = "/srv/worker"
+            autostart = true
+            "#
+            .to_string(),
+        );
+
+        assert_eq!(
+            toml::Value::try_from(config.unwrap()).unwrap(),
+            toml::Value::try_from(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn from_env_leaves_the_configuration_file_alone() {
+        let (dir, _env) = testing::config_dir("from-env-file");
+        let path = dir.join("services.toml");
+        std::fs::write(&path, "services = [").unwrap();
+
+        env::set_var("SPROC_FROM_ENV", "1");
+        env::set_var("SPROC_SERVICE_WEB_COMMAND", "node index.js");
+        env::set_var("SPROC_SERVICE_WEB_WORKDIR", "/srv/web");
+
+        let res = ServicesConfiguration::modify(|config| {
+            config.set_running("web", u32::MAX - 1);
+            config.clone()
+        });
+        let reread = ServicesConfiguration::try_get_config();
+
+        for key in [
+            "SPROC_FROM_ENV",
+            "SPROC_SERVICE_WEB_COMMAND",
+            "SPROC_SERVICE_WEB_WORKDIR",
+        ] {
+            env::remove_var(key);
+        }
+
+        // the corrupt file isn't read or replaced, but the state is kept
+        assert!(res.unwrap().services.contains_key("web"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "services = [");
+        assert!(reread.unwrap().service_states["web"].0.is_running());
+        assert!(audit::read(audit::AuditFilter::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn from_env_requires_a_working_directory() {
+        let (_, _env) = testing::config_dir("from-env-workdir");
+        env::set_var("SPROC_SERVICE_WEB_COMMAND", "node index.js");
+
+        let error = ServicesConfiguration::from_env().unwrap_err();
+        env::remove_var("SPROC_SERVICE_WEB_COMMAND");
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn docker_compose_output() {
+        let config = ServicesConfiguration::read(
+            r#"
+            [services.api]
+            command = "node index.js"
+            working_directory = "/srv/api"
+            restart = true
+            environment = { PORT = "8080", NODE_ENV = "production" }
+
+            [services.worker]
+            command = "worker --queue jobs"
+            working_directory = "/srv/worker"
+            "#
+            .to_string(),
+        );
+
+        assert_eq!(
+            config.as_docker_compose(),
+            "# generated by sproc
+# image: NONE must be filled in for every service (sproc doesn't manage images)
+# applications, metadata, and build steps have no equivalent and are not exported
+version: '3'
+services:
+  api:
+    image: NONE
+    command:
+    - node
+    - index.js
+    working_dir: /srv/api
+    environment:
+      NODE_ENV: production
+      PORT: '8080'
+    restart: always
+  worker:
+    image: NONE
+    command:
+    - worker
+    - --queue
+    - jobs
+    working_dir: /srv/worker
+"
+        );
+    }
+
+    #[test]
+    fn config_updates_are_audited_when_definitions_change() {
+        let (_, _env) = testing::config_dir("audit-config-update");
+        let updates = || {
+            audit::read(audit::AuditFilter::default())
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.action == AuditAction::ConfigUpdate)
+                .collect::<Vec<AuditEntry>>()
+        };
+
+        let mut config = ServicesConfiguration::read(
+            r#"
+            [services.api]
+            command = "node index.js"
+            working_directory = "/srv/api"
+            "#
+            .to_string(),
+        );
+
+        ServicesConfiguration::update_config(config.clone()).unwrap();
+        assert_eq!(updates().len(), 1);
+        assert_eq!(updates()[0].old_value, None);
+
+        // states aren't definitions
+        config.set_running("api", 1);
+        ServicesConfiguration::update_config(config.clone()).unwrap();
+        assert_eq!(updates().len(), 1);
+
+        config.services.get_mut("api").unwrap().restart = true;
+        ServicesConfiguration::update_config(config).unwrap();
+
+        let updates = updates();
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[1].old_value, updates[0].new_value);
+        assert_ne!(updates[1].new_value, updates[0].new_value);
+    }
+
+    #[test]
+    fn rename_service_updates_references() {
+        let (_, _env) = testing::config_dir("rename-service");
+        let mut config = ServicesConfiguration::read(
+            r#"
+            [services.db]
+            command = "postgres"
+            working_directory = "/srv/db"
+
+            [services.cache]
+            command = "redis-server"
+            working_directory = "/srv/cache"
+
+            [services.api]
+            command = "node index.js"
+            working_directory = "/srv/api"
+            depends_on = ["db", "cache"]
+            "#
+            .to_string(),
+        );
+
+        config
+            .service_states
+            .insert("db".to_string(), (ServiceState::Stopped, 0));
+        config.restart_counts.insert("db".to_string(), 2);
+        config.stopping.insert("db".to_string());
+        ServicesConfiguration::update_config(config).unwrap();
+        Service::write_pid_file("db", 1234).unwrap();
+
+        let config = ServicesConfiguration::rename_service("db", "database").unwrap();
+
+        assert!(!config.services.contains_key("db"));
+        assert!(config.services.contains_key("database"));
+        assert_eq!(config.services["api"].depends_on, ["database", "cache"]);
+        assert!(config.service_states.contains_key("database"));
+        assert!(!config.service_states.contains_key("db"));
+        assert_eq!(config.restart_counts.get("database"), Some(&2));
+        assert!(config.stopping.contains("database") && !config.stopping.contains("db"));
+        assert!(!pid_path("db").exists());
+        assert_eq!(
+            std::fs::read_to_string(pid_path("database")).unwrap(),
+            "1234"
+        );
+
+        // written
+        let written = ServicesConfiguration::get_config();
+        assert_eq!(written.services["api"].depends_on, ["database", "cache"]);
+        assert!(written.service_states.contains_key("database"));
+
+        let renamed = audit::read(audit::AuditFilter {
+            service: Some("database".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(renamed.len(), 1);
+        assert_eq!(renamed[0].action, AuditAction::Renamed);
+        assert_eq!(renamed[0].old_value.as_deref(), Some("db"));
+
+        // conflicts
+        let error = ServicesConfiguration::rename_service("cache", "api").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+
+        let error = ServicesConfiguration::rename_service("db", "postgres").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    /// A service that waits for `condition` (for up to a second)
+    fn waiting_service(condition: WaitCondition) -> Service {
+        let mut config = ServicesConfiguration::read(
+            r#"
+            [services.web]
+            command = "sleep 5"
+            working_directory = "/"
+            wait_timeout_seconds = 1
+            "#
+            .to_string(),
+        );
+
+        let mut service = config.services.remove("web").unwrap();
+        service.wait_for = Some(condition);
+        service
+    }
+
+    /// A `web` service with the given extra TOML fields
+    fn web_service(fields: &str) -> Service {
+        let mut config = ServicesConfiguration::read(format!(
+            "[services.web]\ncommand = \"sleep 5\"\nworking_directory = \"/\"\n{fields}"
+        ));
+
+        config.services.remove("web").unwrap()
+    }
+
+    #[test]
+    fn restart_delays_back_off_to_a_ceiling() {
+        let service = web_service(
+            r#"
+            restart_delay_secs = 2
+            restart_backoff_factor = 3
+            restart_delay_max_secs = 30
+            "#,
+        );
+
+        let delays: Vec<u64> = (1..=5)
+            .map(|n| service.restart_delay(n).as_secs())
+            .collect();
+        assert_eq!(delays, [2, 6, 18, 30, 30]);
+        assert_eq!(service.restart_delay(u32::MAX).as_secs(), 30);
+    }
+
+    #[test]
+    fn stable_services_reset_their_failure_count() {
+        let service = web_service("stable_after_secs = 10");
+
+        assert_eq!(service.consecutive_failures(0, Duration::from_secs(1)), 1);
+        assert_eq!(service.consecutive_failures(4, Duration::from_secs(9)), 5);
+        assert_eq!(service.consecutive_failures(4, Duration::from_secs(10)), 1);
+    }
+
+    #[test]
+    fn service_environment_overrides_the_global_one() {
+        let global = HashMap::from([
+            ("RUST_LOG".to_string(), "info".to_string()),
+            ("PORT".to_string(), "8080".to_string()),
+        ]);
+
+        let service = web_service("environment = { RUST_LOG = \"debug\" }");
+        let environment = service
+            .effective_environment(&Some(global.clone()))
+            .unwrap();
+        assert_eq!(environment["RUST_LOG"], "debug");
+        assert_eq!(environment["PORT"], "8080");
+
+        let service = web_service("");
+        assert_eq!(
+            service
+                .effective_environment(&Some(global.clone()))
+                .unwrap(),
+            global
+        );
+    }
+
+    #[test]
+    fn expand_env_replaces_variables() {
+        let (_dir, _env) = testing::config_dir("expand_env");
+        std::env::set_var("SPROC_TEST_HOST", "localhost");
+        std::env::remove_var("SPROC_TEST_UNSET");
+
+        assert_eq!(
+            expand_env("http://${SPROC_TEST_HOST}:${SPROC_TEST_UNSET}80/"),
+            "http://localhost:80/"
+        );
+
+        // only `${VAR}` is replaced, and only once it's closed
+        assert_eq!(
+            expand_env("$SPROC_TEST_HOST ${SPROC_TEST_HOST"),
+            "$SPROC_TEST_HOST ${SPROC_TEST_HOST"
+        );
+    }
+
+    #[test]
+    fn read_env_file_parses_dotenv_lines() {
+        let vars = read_env_file(
+            r#"
+            # comment
+            PORT=8080
+            export HOST = localhost
+            NAME="a b"
+            QUOTED='x=y'
+            EMPTY=
+            not a variable
+            MISMATCHED="a'
+            "#,
+        );
+
+        assert_eq!(vars.len(), 6);
+        assert_eq!(vars["PORT"], "8080");
+        assert_eq!(vars["HOST"], "localhost");
+        assert_eq!(vars["NAME"], "a b");
+        assert_eq!(vars["QUOTED"], "x=y");
+        assert_eq!(vars["EMPTY"], "");
+        assert_eq!(vars["MISMATCHED"], "\"a'");
+    }
+
+    #[test]
+    fn env_files_are_read_from_the_working_directory() {
+        let dir = testing::temp_dir("env_file");
+        std::fs::write(dir.join(".env"), "PORT=9090\nRUST_LOG=warn\n").unwrap();
+
+        let mut service = web_service(
+            r#"
+            env_file = ".env"
+            environment = { RUST_LOG = "debug" }
+            "#,
+        );
+        service.working_directory = dir.to_string_lossy().to_string();
+
+        let environment = service.effective_environment(&None).unwrap();
+        assert_eq!(environment["PORT"], "9090");
+        assert_eq!(environment["RUST_LOG"], "debug");
+
+        // a missing file is an error
+        service.env_file = Some("missing.env".to_string());
+        assert!(service.effective_environment(&None).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failing_pre_start_hooks_prevent_spawning() {
+        let (dir, _env) = testing::config_dir("pre-start");
+        let marker = dir.join("started");
+        let config = ServicesConfiguration::read(format!(
+            r#"
+            [services.web]
+            command = "touch {}"
+            working_directory = "/"
+            pre_start = "exit 1"
+            "#,
+            marker.display()
+        ));
+
+        let error = Service::run("web".to_string(), config).unwrap_err();
+        assert!(matches!(error, SprocError::HookFailed(ref name, _) if name == "web"));
+
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(!marker.exists());
+        assert!(!pid_path("web").exists());
+    }
+
+    #[test]
+    fn pid_files_guard_against_starting_twice() {
+        let (_dir, _env) = testing::config_dir("pid-files");
+        let pid = std::process::id();
+
+        Service::write_pid_file("web", pid).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(pid_path("web")).unwrap(),
+            pid.to_string()
+        );
+        assert_eq!(Service::pid_file_process("web"), Some(pid));
+
+        let config = ServicesConfiguration::read(
+            r#"
+            [services.web]
+            command = "true"
+            working_directory = "/"
+            "#
+            .to_string(),
+        );
+        let error = Service::run("web".to_string(), config).unwrap_err();
+        assert!(matches!(error, SprocError::AlreadyRunning(_, Some(p)) if p == pid));
+
+        Service::remove_pid_file("web");
+        assert!(!pid_path("web").exists());
+        assert_eq!(Service::pid_file_process("web"), None);
+    }
+
+    #[cfg(unix)]
+    fn sleeping_child(stdout: Stdio) -> Child {
+        shell_command("echo starting; sleep 1; echo listening; sleep 5")
+            .stdout(stdout)
+            .spawn()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn wait_for_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let service = waiting_service(WaitCondition::Port(listener.local_addr().unwrap().port()));
+        let mut child = sleeping_child(Stdio::null());
+
+        service.wait_until_ready("web", &mut child).await.unwrap();
+        child.kill().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn wait_for_file() {
+        let path = testing::temp_dir("wait-for-file").join("ready");
+        let service = waiting_service(WaitCondition::File(path.to_string_lossy().to_string()));
+        let mut child = sleeping_child(Stdio::null());
+
+        let created = path.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            std::fs::write(created, "").unwrap();
+        });
+
+        service.wait_until_ready("web", &mut child).await.unwrap();
+        child.kill().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn wait_for_log_line_on_stdout() {
+        let mut service = waiting_service(WaitCondition::LogLine {
+            pattern: "^listening$".to_string(),
+        });
+        service.wait_timeout_seconds = 3;
+
+        let mut child = sleeping_child(Stdio::piped());
+
+        service.wait_until_ready("web", &mut child).await.unwrap();
+        child.kill().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn wait_for_log_line_in_stdout_file() {
+        let path = testing::temp_dir("wait-for-log-line").join("web.log");
+        std::fs::write(&path, "listening\n").unwrap(); // from an earlier run
+
+        let mut service = waiting_service(WaitCondition::LogLine {
+            pattern: "^listening$".to_string(),
+        });
+        service.wait_timeout_seconds = 3;
+
+        let log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        let mut child = sleeping_child(Stdio::from(log));
+
+        STDOUT_FILES
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(child.id(), (path.to_string_lossy().to_string(), 10));
+
+        let started = Instant::now();
+        service.wait_until_ready("web", &mut child).await.unwrap();
+        child.kill().unwrap();
+
+        // the line written before the service started isn't matched
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn wait_times_out() {
+        let path = testing::temp_dir("wait-times-out").join("never");
+        let service = waiting_service(WaitCondition::File(path.to_string_lossy().to_string()));
+        let mut child = sleeping_child(Stdio::null());
+
+        let error = service
+            .wait_until_ready("web", &mut child)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+
+        // killed and reaped
+        assert!(!child.try_wait().unwrap().unwrap().success());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn invalid_log_line_patterns_are_rejected_before_spawning() {
+        let (dir, _env) = testing::config_dir("invalid-pattern");
+        let marker = dir.join("started");
+        let config = ServicesConfiguration::read(format!(
+            r#"
+            [services.web]
+            command = "touch {}"
+            working_directory = "/"
+            wait_for = {{ LogLine = {{ pattern = "(" }} }}
+            "#,
+            marker.display()
+        ));
+
+        let error = Service::run("web".to_string(), config).unwrap_err();
+        assert!(matches!(error, SprocError::Io(ref e) if e.kind() == ErrorKind::InvalidInput));
+
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(!marker.exists());
+    }
+
+    /// A configuration of services that only have dependencies, `(name, depends_on)`
+    fn dependencies(services: &[(&str, &[&str])]) -> ServicesConfiguration {
+        let mut contents = String::new();
+
+        for (name, depends_on) in services {
+            contents.push_str(&format!("[services.{name}]\ndepends_on = {depends_on:?}\n"));
+            contents.push_str("command = \"true\"\nworking_directory = \"/\"\n");
+        }
+
+        ServicesConfiguration::read(contents)
+    }
+
+    #[test]
+    fn start_order_without_dependencies() {
+        let config = dependencies(&[("c", &[]), ("a", &[]), ("b", &[])]);
+        assert_eq!(config.simulate_start_order().unwrap(), [["a", "b", "c"]]);
+    }
 
-                // start service
-                Service::wait(name.clone(), &mut config)
-                    .await
-                    .expect("Failed to wait for service");
+    #[test]
+    fn start_order_of_a_chain() {
+        let config = dependencies(&[("web", &["api"]), ("api", &["db"]), ("db", &[])]);
+        assert_eq!(
+            config.simulate_start_order().unwrap(),
+            [["db"], ["api"], ["web"]]
+        );
+    }
 
-                // pull real config
-                // we have to do this so we don't restart if it was disabled while the service was running
-                let mut config = ServicesConfiguration::get_config();
-                let service = match config.services.get(&name) {
-                    Some(s) => s,
-                    None => return,
-                };
+    #[test]
+    fn start_order_of_a_diamond() {
+        let config = dependencies(&[
+            ("app", &["cache", "queue"]),
+            ("cache", &["db"]),
+            ("queue", &["db"]),
+            ("db", &[]),
+            ("metrics", &[]),
+        ]);
 
-                // update config
-                config.service_states.remove(&name);
-                ServicesConfiguration::update_config(config.clone())
-                    .expect("Failed to update config");
+        assert_eq!(
+            config.simulate_start_order().unwrap(),
+            vec![vec!["db", "metrics"], vec!["cache", "queue"], vec!["app"]]
+        );
+    }
 
-                // ...
-                if service.restart == false {
-                    // no need to loop again if we aren't supposed to restart the service
-                    break;
-                }
+    #[test]
+    fn start_order_cycles() {
+        let config = dependencies(&[
+            ("a", &["b"]),
+            ("b", &["c"]),
+            ("c", &["a"]),
+            ("d", &["a"]),
+            ("e", &[]),
+        ]);
 
-                // begin restart
-                println!("info: auto-restarting service \"{}\"", name);
-                continue; // service will be run again
-            }
-        });
+        assert_eq!(
+            config.simulate_start_order(),
+            Err(ConfigError::CircularDependency(vec![
+                "a".to_string(),
+                "b".to_string(),
+                "c".to_string(),
+                "a".to_string()
+            ]))
+        );
+    }
 
-        // return
-        Ok(())
+    #[test]
+    fn start_order_missing_dependency() {
+        let config = dependencies(&[("api", &["db"])]);
+        assert_eq!(
+            config.simulate_start_order(),
+            Err(ConfigError::MissingDependency(
+                "api".to_string(),
+                "db".to_string()
+            ))
+        );
     }
 
-    // package manager
+    #[test]
+    #[cfg(unix)]
+    fn spawn_waits_for_dependencies() {
+        let (_dir, _env) = testing::config_dir("spawn_dependencies");
+        ServicesConfiguration::update_config(ServicesConfiguration::read(
+            r#"
+            [services.migrate]
+            type = "oneshot"
+            command = "sh -c 'sleep 1; exit 3'"
+            working_directory = "/"
 
-    /// Run and init a [`Service`]'s `BuildConfiguration`
-    pub async fn bootstrap(&self, name: String) -> Result<()> {
-        let home = env::var("HOME").expect("failed to read $HOME");
+            [services.api]
+            command = "sleep 5"
+            working_directory = "/"
+            depends_on = ["migrate"]
+            "#
+            .to_string(),
+        ))
+        .unwrap();
 
-        // verify modules directory
-        if let Err(_) = fs::read_dir(format!("{home}/.config/xsu-apps/sproc/modules")) {
-            if let Err(e) = fs::create_dir(format!("{home}/.config/xsu-apps/sproc/modules")) {
-                panic!("{:?}", e);
-            }
-        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
 
-        // check for existing directory
-        let dir = format!("{home}/.config/xsu-apps/sproc/modules/{}", name);
+        // the failed dependency is reported, and nothing depending on it is started
+        let error = runtime
+            .block_on(Service::spawn("api".to_string()))
+            .unwrap_err();
+        assert!(matches!(error, SprocError::OneshotFailed(ref name, Some(3)) if name == "migrate"));
 
-        if let Ok(_) = fs::read_dir(&dir) {
-            return Err(Error::new(ErrorKind::AlreadyExists, "The requested service has already run its build commands or its build directory already exists."));
-        }
+        let config = ServicesConfiguration::get_config();
+        assert!(!config.service_states.contains_key("api"));
+    }
 
-        // create directory
-        fs::create_dir(&dir)?;
+    #[test]
+    fn start_groups_fall_back_to_every_service() {
+        let config = dependencies(&[("b", &["a"]), ("a", &["b"]), ("c", &[])]);
+        assert_eq!(config.start_groups(), [["a", "b", "c"]]);
 
-        // create build file
-        // TODO: make this work on other platforms
-        let build_file = format!("{dir}/build.artifact.sh");
-        fs::write(&build_file, self.metadata.build.join("\n"))?;
+        let config = dependencies(&[("b", &["a"]), ("a", &[])]);
+        assert_eq!(config.start_groups(), [["a"], ["b"]]);
+    }
 
-        // run build file
-        let command = format!("bash {build_file}");
-        let command_split: Vec<&str> = command.split(" ").collect();
-        let mut cmd = Command::new(command_split.get(0).unwrap());
+    #[test]
+    fn bulk_operations_survive_a_broken_dependency_graph() {
+        let (_dir, _env) = testing::config_dir("bulk_broken_graph");
+        let mut config = dependencies(&[("a", &["b"]), ("b", &["a"]), ("c", &[])]);
 
-        for arg in command_split.iter().skip(1) {
-            cmd.arg(arg);
+        // running states without processes
+        for name in ["b", "c"] {
+            config.set_running(name, u32::MAX - 1);
         }
 
-        cmd.current_dir(&dir);
+        ServicesConfiguration::update_config(config).unwrap();
 
-        // capture out
-        let child_stdout = cmd
-            .stdout(Stdio::piped())
-            .spawn()?
-            .stdout
-            .expect("failed to capture command output");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
 
-        let reader = BufReader::new(child_stdout);
+        // the circular services fail, the other one is still handled
+        let results = runtime.block_on(Service::start_all()).unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results["a"]
+            .error
+            .as_ref()
+            .is_some_and(|e| e.contains("Circular dependency")));
+        assert!(results["c"]
+            .error
+            .as_ref()
+            .is_some_and(|e| e.contains("already running")));
 
-        reader
-            .lines()
-            .filter_map(|l| l.ok())
-            .for_each(|l| println!("build: {l}"));
+        // only the services that were stopped lose their state
+        let results = runtime.block_on(Service::kill_all()).unwrap();
+        assert_eq!(results.len(), 2);
 
-        // return
-        Ok(())
+        let config = ServicesConfiguration::get_config();
+        assert!(config.service_states.is_empty());
     }
-}
 
-/// The state of a [`Service`]
-#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
-pub enum ServiceState {
-    Running,
-    Stopped,
-}
+    #[test]
+    fn validate_unknown_dependencies() {
+        let config = dependencies(&[("api", &["db", "cache"]), ("cache", &[])]);
+        let errors = config.validate();
 
-impl Default for ServiceState {
-    fn default() -> Self {
-        Self::Stopped
+        assert_eq!(
+            errors,
+            [ValidationError::UnknownDependency {
+                service: "api".to_string(),
+                dep: "db".to_string(),
+            }]
+        );
+
+        let report = config.check();
+        assert_eq!(report.errors, errors);
+        assert!(report.warnings.is_empty());
     }
-}
 
-/// General information about a [`ServiceState`]
-#[derive(Serialize, Deserialize)]
-pub struct ServiceInfo {
-    pub name: String,
-    pub pid: u32,
-    pub memory: u64,
-    pub cpu: f32,
-    pub status: String,
-    pub running_for_seconds: u64,
-}
+    #[test]
+    fn validate_invalid_working_directories() {
+        let dir = testing::temp_dir("validate_working_directory");
+        let file = dir.join("file");
+        std::fs::write(&file, "").unwrap();
 
-/// Configuration for `sproc serve`'s registry
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct RegistryConfiguration {
-    /// If the registry is enabled
-    pub enabled: bool,
-    /// Registry description, shown on the homepage
-    #[serde(default)]
-    pub description: String,
-    /// Registry name, shown on homepage
-    #[serde(default = "registry_default")]
-    pub name: String,
-    /// Navigation bar buttons, `(Text, Url)`
-    #[serde(default)]
-    pub nav: Vec<(String, String)>,
-}
+        let config = ServicesConfiguration::read(format!(
+            r#"
+            [services.missing]
+            command = "true"
+            working_directory = "{0}/missing"
 
-fn registry_default() -> String {
-    "Registry".to_owned()
-}
+            [services.file]
+            command = "true"
+            working_directory = "{1}"
 
-impl Default for RegistryConfiguration {
-    fn default() -> Self {
-        Self {
-            enabled: true,
-            description: String::new(),
-            name: registry_default(),
-            nav: Vec::new(),
-        }
+            [services.ok]
+            command = "true"
+            working_directory = "{0}"
+            "#,
+            dir.display(),
+            file.display()
+        ));
+
+        let report = config.check();
+        assert_eq!(
+            report.errors,
+            [
+                ValidationError::InvalidWorkingDirectory {
+                    service: "file".to_string(),
+                    path: file.display().to_string(),
+                },
+                ValidationError::InvalidWorkingDirectory {
+                    service: "missing".to_string(),
+                    path: format!("{}/missing", dir.display()),
+                },
+            ]
+        );
+        assert!(report.warnings.is_empty());
     }
-}
 
-/// Configuration for `sproc serve`
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct ServerConfiguration {
-    /// The port to serve the HTTP server on (6374 by default)
-    pub port: u16,
-    /// The key that is required to run operations from the HTTP server
-    pub key: String,
-    /// Configuration for the registry
-    #[serde(default)]
-    pub registry: RegistryConfiguration,
-}
+    #[test]
+    fn validate_duplicate_service_names() {
+        let (dir, _env) = testing::config_dir("validate_duplicates");
+        let inherited = dir.join("inherited.toml");
+        std::fs::write(
+            &inherited,
+            r#"
+            [services.web]
+            command = "serve --port 8081"
+            working_directory = "/"
 
-impl Default for ServerConfiguration {
-    fn default() -> Self {
-        Self {
-            port: 6374,
-            key: String::new(),
-            registry: RegistryConfiguration::default(),
-        }
-    }
-}
+            [services.worker]
+            command = "work"
+            working_directory = "/"
+            "#,
+        )
+        .unwrap();
 
-/// `services.toml` file
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct ServicesConfiguration {
-    /// The source file location
-    #[serde(default)]
-    pub source: String,
-    /// Inherited service definition files
-    pub inherit: Option<Vec<String>>,
-    /// Server configuration (`sproc serve`)
-    #[serde(default)]
-    pub server: ServerConfiguration,
-    /// Service definitions
-    pub services: HashMap<String, Service>,
-    /// Service states
-    #[serde(default)]
-    pub service_states: ServiceStates,
-}
+        let config = ServicesConfiguration::read(format!(
+            r#"
+            inherit = ["{}"]
 
-impl Default for ServicesConfiguration {
-    fn default() -> Self {
-        Self {
-            source: String::new(),
-            inherit: None,
-            services: HashMap::new(),
-            server: ServerConfiguration::default(),
-            service_states: HashMap::new(),
+            [services.web]
+            command = "serve --port 8080"
+            working_directory = "/"
+            "#,
+            inherited.display()
+        ));
+
+        // a warning, not an error
+        let report = config.check();
+        assert!(report.errors.is_empty());
+        assert_eq!(
+            report.warnings,
+            [ValidationError::DuplicateServiceName {
+                name: "web".to_string()
+            }]
+        );
+    }
+
+    fn info(
+        pid: u32,
+        memory: u64,
+        cpu: f32,
+        status: &str,
+        running_for_seconds: u64,
+    ) -> ServiceInfo {
+        ServiceInfo {
+            name: "api".to_string(),
+            pid,
+            memory,
+            cpu,
+            status: status.to_string(),
+            running_for_seconds,
+            log_file: None,
+            last_exit_code: None,
+            last_exit: None,
         }
     }
-}
 
-impl ServicesConfiguration {
-    /// Read configuration file into [`ServicesConfiguration`]
-    pub fn read(contents: String) -> Self {
-        let mut res = toml::from_str::<Self>(&contents).unwrap();
-
-        // handle inherits
-        if let Some(ref inherit) = res.inherit {
-            for path in inherit {
-                if let Ok(c) = fs::read_to_string(path) {
-                    for service in toml::from_str::<Self>(&c).unwrap().services {
-                        // push service to main service stack
-                        res.services.insert(service.0, service.1);
-                    }
-                }
+    #[test]
+    fn info_diff_of_the_same_process() {
+        let before = info(10, 2048, 5.0, "Run", 60);
+        let after = info(10, 1024, 7.5, "Sleep", 90);
+
+        assert_eq!(
+            ServiceInfo::diff(&before, &after),
+            ServiceInfoDiff {
+                memory_delta_bytes: -1024,
+                cpu_delta_percent: 2.5,
+                status_changed: true,
+                was_restarted: false,
             }
-        }
+        );
+    }
 
-        // return
-        res
+    #[test]
+    fn info_diff_of_a_restarted_process() {
+        let before = info(10, 1024, 5.0, "Run", 60);
+
+        // new PID
+        let diff = ServiceInfo::diff(&before, &info(11, 1024, 5.0, "Run", 5));
+        assert!(diff.was_restarted);
+        assert!(!diff.status_changed);
+        assert_eq!(diff.memory_delta_bytes, 0);
+
+        // same PID (reused), but it hasn't been running as long
+        assert!(ServiceInfo::diff(&before, &info(10, 1024, 5.0, "Run", 5)).was_restarted);
     }
 
-    /// Pull configuration file
-    pub fn get_config() -> Self {
-        let home = env::var("HOME").expect("failed to read $HOME");
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn ps_finds_untracked_processes() {
+        let dir = testing::temp_dir("ps");
+        let mut config = ServicesConfiguration::read(format!(
+            "[services.sleeper]\ncommand = \"sleep 60\"\nworking_directory = \"{}\"\n",
+            dir.display()
+        ));
 
-        if let Err(_) = fs::read_dir(format!("{home}/.config/xsu-apps/sproc")) {
-            // make sure .config exists
-            fs::mkdir(format!("{home}/.config")).expect("failed to create .config directory");
+        // started without sproc
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .current_dir(&dir)
+            .spawn()
+            .unwrap();
+
+        config
+            .service_states
+            .insert("sleeper".to_string(), (ServiceState::Running, u32::MAX - 1));
 
-            // make sure .config/xsu-apps exists
-            fs::mkdir(format!("{home}/.config/xsu-apps"))
-                .expect("failed to create xsu-apps directory");
+        let report = config.ps();
+        child.kill().unwrap();
+        child.wait().unwrap();
 
-            // create .config/xsu-apps/sproc
-            fs::mkdir(format!("{home}/.config/xsu-apps/sproc"))
-                .expect("failed to create sproc directory")
-        }
+        assert_eq!(report.tracked.len(), 1);
+        assert!(!report.tracked[0].alive);
 
-        match fs::read(format!("{home}/.config/xsu-apps/sproc/services.toml")) {
-            Ok(c) => ServicesConfiguration::read(c),
-            Err(_) => Self::default(),
-        }
+        let orphan = report.orphans.iter().find(|o| o.pid == child.id()).unwrap();
+        assert_eq!(orphan.name, "sleeper");
+        assert_eq!(orphan.executable, "sleep");
     }
 
-    /// Update configuration file
-    pub fn update_config(contents: Self) -> Result<()> {
-        let home = env::var("HOME").expect("failed to read $HOME");
+    #[test]
+    fn log_lines_have_every_field() {
+        let entry = ServiceEntry::new("api", ServiceState::Running, Some(42));
+        let extra = HashMap::from([
+            ("command".to_string(), "node index.js".to_string()),
+            ("service".to_string(), "not api".to_string()),
+        ]);
+
+        let line: serde_json::Value =
+            serde_json::from_str(&entry.to_log_line(ServiceEventKind::Started, &extra)).unwrap();
+
+        assert!(line["timestamp"].as_u64().unwrap() > 0);
+        assert_eq!(line["level"], "info");
+        assert_eq!(line["pid"], 42);
+        assert_eq!(line["state"], "Running");
+        assert_eq!(line["event"], "Started");
+        assert_eq!(line["command"], "node index.js");
+
+        // extra fields can't replace the standard ones
+        assert_eq!(line["service"], "api");
 
-        fs::write(
-            format!("{home}/.config/xsu-apps/sproc/services.toml"),
-            format!("# DO **NOT** MANUALLY EDIT THIS FILE! Please edit the source instead and run `sproc pin {{path}}`.\n{}", toml::to_string_pretty::<Self>(&contents).unwrap()),
+        let line: serde_json::Value = serde_json::from_str(
+            &ServiceEntry::new("api", ServiceState::Stopped, None)
+                .to_log_line(ServiceEventKind::Failed, &HashMap::new()),
         )
-    }
+        .unwrap();
 
-    /// Merge services from other [`ServicesConfiguration`]
-    pub fn merge_config(&mut self, other: Self) -> () {
-        for service in other.services {
-            // push service to main service stack
-            self.services.insert(service.0, service.1);
-        }
+        assert_eq!(line["level"], "error");
+        assert_eq!(line["pid"], serde_json::Value::Null);
     }
-}
 
-/// Request body for updating a service
-#[derive(Serialize, Deserialize)]
-pub struct RegistryPushRequestBody {
-    /// Auth key
-    pub key: String,
-    /// The service's content in TOML form
-    pub content: String,
-}
+    #[test]
+    fn log_events_are_appended_to_the_log_file() {
+        let path = testing::temp_dir("log-event").join("api.log");
+        let entry = ServiceEntry::new("api", ServiceState::Running, Some(42))
+            .with_log_file(Some(path.to_string_lossy().to_string()));
 
-/// Request body for deleting a service
-#[derive(Serialize, Deserialize)]
-pub struct RegistryDeleteRequestBody {
-    /// Auth key
-    pub key: String,
-}
+        entry.log_event(ServiceEventKind::Started, HashMap::new());
+        entry.log_event(ServiceEventKind::Stopped, HashMap::new());
 
-/// A simple registry for service files
-#[derive(Debug, Clone)]
-pub struct Registry(pub ServerConfiguration, pub String);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["event"].clone())
+            .collect();
 
-impl Registry {
-    /// Create a new [`Registry`]
-    pub fn new(config: ServerConfiguration) -> Self {
-        let home = env::var("HOME").expect("failed to read $HOME");
-        let dir = format!("{home}/.config/xsu-apps/sproc/registry"); // registry file storage location
+        assert_eq!(events, ["Started", "Stopped"]);
+    }
 
-        // create registry dir
-        fs::mkdir(&dir).expect("failed to create directory");
-        fs::mkdir(&format!("{home}/.config/xsu-apps/sproc/static"))
-            .expect("failed to create static directory");
+    #[test]
+    fn dependency_order_of_a_chain() {
+        let config = dependencies(&[("web", &["api"]), ("api", &["db"]), ("db", &[]), ("x", &[])]);
 
-        // return
-        Self(config, dir)
+        assert_eq!(
+            config.dependency_order("web").unwrap(),
+            ["db", "api", "web"]
+        );
+        assert_eq!(config.dependency_order("api").unwrap(), ["db", "api"]);
     }
 
-    /// Get a service given its name
-    pub fn get(&self, service: String) -> Result<String> {
-        if self.0.registry.enabled == false {
-            return Err(Error::new(
-                ErrorKind::PermissionDenied,
-                "Registry is disabled",
-            ));
-        }
+    #[test]
+    fn dependency_order_cycles() {
+        let config = dependencies(&[("web", &["api"]), ("api", &["db"]), ("db", &["web"])]);
 
-        // return
-        fs::read(format!("{}/{}.toml", self.1, service))
+        let error = config.dependency_order("web").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("web -> api -> db -> web"));
     }
 
-    /// Update (or create) a service given its name and value
-    pub fn push(&self, props: RegistryPushRequestBody, service: String) -> Result<()> {
-        if self.0.registry.enabled == false {
-            return Err(Error::new(
-                ErrorKind::PermissionDenied,
-                "Registry is disabled",
-            ));
-        }
-
-        // check key
-        if props.key != self.0.key {
-            return Err(Error::new(ErrorKind::PermissionDenied, "Key is invalid"));
-        }
+    #[test]
+    fn split_command_words() {
+        assert_eq!(
+            split_command("node  index.js\t--port 80").unwrap(),
+            ["node", "index.js", "--port", "80"]
+        );
 
-        // validate
-        if let Err(e) = toml::from_str::<Service>(&props.content) {
-            return Err(Error::new(ErrorKind::InvalidInput, e.to_string()));
-        };
+        // quotes
+        assert_eq!(
+            split_command(r#"sh -c 'echo "hi there"' "a b"c"#).unwrap(),
+            ["sh", "-c", "echo \"hi there\"", "a bc"]
+        );
+        assert_eq!(split_command("echo ''").unwrap(), ["echo", ""]);
 
-        // return
-        fs::write(format!("{}/{}.toml", self.1, service), &props.content)
+        // escapes
+        assert_eq!(
+            split_command(r#"echo a\ b "\"q\" \\ \n" '\n'"#).unwrap(),
+            ["echo", "a b", "\"q\" \\ \\n", "\\n"]
+        );
     }
 
-    /// Delete a service given its name
-    pub fn delete(&self, props: RegistryDeleteRequestBody, service: String) -> Result<()> {
-        if self.0.registry.enabled == false {
-            return Err(Error::new(
-                ErrorKind::PermissionDenied,
-                "Registry is disabled",
-            ));
+    #[test]
+    fn split_command_errors() {
+        for command in ["echo 'oops", "echo \"oops", "echo \"\\", "", "  "] {
+            let error = split_command(command).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput, "{command}");
         }
 
-        // check key
-        if props.key != self.0.key {
-            return Err(Error::new(ErrorKind::PermissionDenied, "Key is invalid"));
-        }
+        let error = split_command("echo 'oops").unwrap_err();
+        assert!(error.to_string().contains("Unterminated single quote"));
+    }
 
-        // return
-        fs::rm(format!("{}/{}.toml", self.1, service))
+    #[test]
+    fn shell_services_run_through_the_shell() {
+        let mut service = web_service("shell = true");
+        service.command = "echo 'a b' | wc -c".to_string();
+
+        let args = service.command_args().unwrap();
+        assert_eq!(args, shell_args("echo 'a b' | wc -c"));
+        assert_eq!(args.last().unwrap(), "echo 'a b' | wc -c");
+
+        // without it, the command is split into words (and the pipe is just an argument)
+        service.shell = false;
+        assert_eq!(
+            service.command_args().unwrap(),
+            ["echo", "a b", "|", "wc", "-c"]
+        );
     }
 }
@@ -0,0 +1,72 @@
+//! Sproc remote servers
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::model::AuthMode;
+use crate::webhook;
+
+/// Another machine's sproc server that operations can be sent to (`--remote {name}`)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Remote {
+    /// The URL of the server (`http://10.0.0.2:6374`)
+    pub url: String,
+    /// The server's key (or a token)
+    pub key: String,
+    /// How the server expects requests to prove they know the key
+    #[serde(default)]
+    pub auth_mode: AuthMode,
+}
+
+impl Remote {
+    /// Send a request (for a service, if `service` is given) to the remote server
+    /// (`POST /api/sproc/{endpoint}`), returning the response status and body
+    pub async fn request(
+        &self,
+        endpoint: &str,
+        service: Option<&str>,
+        query: &[(&str, String)],
+    ) -> Result<(u16, String)> {
+        let http = reqwest::Client::new();
+        let mut request = http
+            .post(format!(
+                "{}/api/sproc/{endpoint}",
+                self.url.trim_end_matches("/")
+            ))
+            .query(query)
+            .header("Content-Type", "application/json");
+
+        let mut body: HashMap<&str, &str> = HashMap::new();
+
+        if let Some(service) = service {
+            body.insert("service", service);
+        }
+
+        if self.auth_mode != AuthMode::Hmac {
+            body.insert("key", &self.key);
+        }
+
+        request = request.body(serde_json::to_string(&body).unwrap());
+
+        let mut request = match request.build() {
+            Ok(r) => r,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidInput, e.to_string())),
+        };
+
+        if self.auth_mode == AuthMode::Hmac {
+            webhook::sign_api_request(&self.key, &mut request);
+        }
+
+        let res = match http.execute(request).await {
+            Ok(r) => r,
+            Err(e) => return Err(Error::new(ErrorKind::NotConnected, e.to_string())),
+        };
+
+        let status = res.status().as_u16();
+
+        match res.text().await {
+            Ok(body) => Ok((status, body)),
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+}
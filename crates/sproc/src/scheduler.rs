@@ -0,0 +1,158 @@
+//! Sproc scheduled services (`schedule`)
+use chrono::{DateTime, TimeZone, Utc};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, time::Duration};
+
+use crate::model::{Service, ServicesConfiguration};
+
+/// When a scheduled service last ran and will next run (kept in
+/// [`RuntimeState`](crate::model::RuntimeState))
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct ScheduleState {
+    /// Unix timestamp (seconds) of the last run
+    pub last_run: Option<u64>,
+    /// Unix timestamp (seconds) of the next run
+    pub next_run: Option<u64>,
+}
+
+/// A scheduled service's upcoming run (from `GET /schedule`)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ScheduledRun {
+    pub service: String,
+    pub schedule: String,
+    pub last_run: Option<u64>,
+    pub next_run: Option<u64>,
+}
+
+/// Parse a cron expression
+///
+/// Standard 5-field expressions (`min hour day month weekday`) are accepted, as well as
+/// the 6 and 7-field forms with seconds (and years).
+pub fn parse(expression: &str) -> Result<Schedule, cron::error::Error> {
+    if expression.split_whitespace().count() == 5 {
+        return Schedule::from_str(&format!("0 {expression}"));
+    }
+
+    Schedule::from_str(expression)
+}
+
+/// Get the next time a schedule fires after `after` (Unix timestamp, seconds)
+fn next_after(schedule: &Schedule, after: u64) -> Option<u64> {
+    let after: DateTime<Utc> = Utc.timestamp_opt(after as i64, 0).single()?;
+    schedule.after(&after).next().map(|t| t.timestamp() as u64)
+}
+
+/// Get every scheduled service's upcoming run, sorted by name
+pub fn upcoming(config: &ServicesConfiguration) -> Vec<ScheduledRun> {
+    let mut runs: Vec<ScheduledRun> = config
+        .services
+        .iter()
+        .filter_map(|(name, service)| {
+            let schedule = service.schedule.as_ref()?;
+            let state = config.schedules.get(name).cloned().unwrap_or_default();
+
+            Some(ScheduledRun {
+                service: name.to_owned(),
+                schedule: schedule.to_owned(),
+                last_run: state.last_run,
+                next_run: state.next_run,
+            })
+        })
+        .collect();
+
+    runs.sort_by(|a, b| a.service.cmp(&b.service));
+    runs
+}
+
+/// Update schedule states, returning the services that are due to run
+fn due(config: &mut ServicesConfiguration, now: u64) -> Vec<String> {
+    let mut due = Vec::new();
+
+    // forget services that are no longer scheduled
+    let services = config.services.clone();
+    config.schedules.retain(|name, _| {
+        services
+            .get(name)
+            .map(|s| s.schedule.is_some())
+            .unwrap_or(false)
+    });
+
+    for (name, service) in &services {
+        let schedule = match service.schedule.as_ref().map(|s| parse(s)) {
+            Some(Ok(s)) => s,
+            _ => continue,
+        };
+
+        let state = config.schedules.entry(name.to_owned()).or_default();
+
+        match state.next_run {
+            Some(next) if next <= now => {
+                due.push(name.to_owned());
+                state.last_run = Some(now);
+                state.next_run = next_after(&schedule, now);
+            }
+            Some(_) => (),
+            None => state.next_run = next_after(&schedule, now),
+        }
+    }
+
+    due
+}
+
+/// Run scheduled services when they're due, forever
+///
+/// Runs are skipped if the service is still running from the previous run.
+pub async fn run() {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let now = (crate::model::epoch_timestamp() / 1000) as u64;
+
+        // only take the lock if something changes
+        let mut config = match ServicesConfiguration::try_get_config() {
+            Ok(c) => c,
+            Err(e) => {
+                println!("warn: {e}");
+                continue;
+            }
+        };
+        let before = config.schedules.clone();
+        due(&mut config, now);
+
+        if config.schedules == before {
+            continue;
+        }
+
+        let names = match ServicesConfiguration::modify(|config| due(config, now)) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("warn: {e}");
+                continue;
+            }
+        };
+
+        for name in names {
+            let running = match ServicesConfiguration::try_get_config() {
+                Ok(c) => c
+                    .service_states
+                    .get(&name)
+                    .map(|s| s.0.is_running())
+                    .unwrap_or(false),
+                Err(e) => {
+                    println!("warn: {e}");
+                    continue;
+                }
+            };
+
+            if running {
+                println!("warn: skipping scheduled run, service is still running ({name})");
+                continue;
+            }
+
+            if let Err(e) = Service::spawn(name).await {
+                println!("warn: {e}");
+            }
+        }
+    }
+}
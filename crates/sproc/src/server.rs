@@ -1,18 +1,36 @@
 //! Sproc HTTP endpoints
 use askama_axum::Template;
-use axum::extract::Path;
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRef, FromRequest, Path, Query, Request};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use axum::routing::{delete, get, post};
 use axum::{Form, Router};
+use axum_server::tls_rustls::RustlsConfig;
 use axum::{extract::State, response::Html, Json};
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::process::Command;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::Instant;
 
+use crate::audit::{self, AuditAction, AuditContext, AuditEntry, AuditFilter};
+use crate::doctor::DoctorReport;
+use crate::{events, logs, metrics, scheduler, snapshot, webhook};
 use crate::model::{
-    Registry, RegistryConfiguration, RegistryDeleteRequestBody, RegistryPushRequestBody, Service,
-    ServicesConfiguration as ServConf,
+    config_path, epoch_timestamp, parse_signal, read_tail, AuthMode, BulkResults, ConfigDiff,
+    ConfigFormat, OutputFormat, Registry, RegistryConfiguration, RegistryDeleteRequestBody,
+    RegistryPushRequestBody, Service, ServerConfiguration, ServiceInfo, ServiceInfoDiff,
+    ServiceState, ServiceType, ServicesConfiguration as ServConf, ServicesInfo, ServicesSummary,
+    SprocError, TokenScope, ValidationError, ValidationReport,
 };
 use xsu_authman::{Database as AuthDatabase, model::AuthError};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::{Stream, StreamExt};
 
 #[derive(Serialize, Deserialize)]
 pub struct APIReturn<T> {
@@ -20,6 +38,203 @@ pub struct APIReturn<T> {
     pub data: T,
 }
 
+/// A single invalid field in a request body
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FieldError {
+    /// The path of the field (`.` for the whole body)
+    pub field: String,
+    /// What is wrong with the field
+    pub message: String,
+}
+
+impl FieldError {
+    /// Create a new [`FieldError`]
+    pub fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A request body that can check its own fields
+pub trait Validate {
+    /// Get every invalid field (empty if the body is valid)
+    fn validate(&self) -> Vec<FieldError>;
+}
+
+/// Check that a field isn't empty
+fn require(errors: &mut Vec<FieldError>, field: &str, value: &str) {
+    if value.trim().is_empty() {
+        errors.push(FieldError::new(field, "must not be empty"));
+    }
+}
+
+/// [`Json`] extractor that also runs [`Validate::validate`]
+///
+/// Rejects with `400` and `{ "ok": false, "data": [FieldError] }`.
+pub struct ValidatedJson<T>(pub T);
+
+#[axum::async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<APIReturn<Vec<FieldError>>>);
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let signed = req.extensions().get::<SignatureVerified>().is_some();
+        let reject = |errors: Vec<FieldError>| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(APIReturn::<Vec<FieldError>> {
+                    ok: false,
+                    data: errors,
+                }),
+            )
+        };
+
+        let bytes = match Bytes::from_request(req, state).await {
+            Ok(b) => b,
+            Err(e) => return Err(reject(vec![FieldError::new(".", &e.body_text())])),
+        };
+
+        // deserialize
+        let deserializer = &mut serde_json::Deserializer::from_slice(&bytes);
+        let value: T = match serde_path_to_error::deserialize(deserializer) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(reject(vec![FieldError::new(
+                    &e.path().to_string(),
+                    &e.inner().to_string(),
+                )]))
+            }
+        };
+
+        // validate (signed requests don't need a key)
+        let errors: Vec<FieldError> = value
+            .validate()
+            .into_iter()
+            .filter(|e| !(signed && e.field == "key"))
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(reject(errors));
+        }
+
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// Request extension added by [`verify_signature`] once a request's signature is checked
+#[derive(Clone, Copy, Debug)]
+pub struct SignatureVerified;
+
+/// Check if a request is allowed to run operations
+///
+/// Requests are always allowed under [`AuthMode::Hmac`] since [`verify_signature`]
+/// has already rejected anything unsigned.
+pub fn authorized(config: &ServConf, key: &str) -> bool {
+    match config.server.auth_mode {
+        AuthMode::Plaintext => key == config.server.key,
+        AuthMode::Hmac => true,
+    }
+}
+
+/// Check if a request is allowed to run an operation on a service (or every service if `None`)
+///
+/// This also accepts `server.tokens` with the scope (under [`AuthMode::Plaintext`], since
+/// signatures are made with the server key).
+pub fn authorized_for(
+    config: &ServConf,
+    key: &str,
+    scope: TokenScope,
+    service: Option<&str>,
+) -> bool {
+    if authorized(config, key) {
+        return true;
+    }
+
+    if config.server.auth_mode != AuthMode::Plaintext || key.is_empty() {
+        return false;
+    }
+
+    config
+        .server
+        .tokens
+        .iter()
+        .any(|t| t.token == key && t.allows(scope, service))
+}
+
+/// The largest request body read by [`verify_signature`] (the same as axum's default limit)
+pub const MAX_SIGNED_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Middleware rejecting requests without a valid `X-Sproc-Signature` (under [`AuthMode::Hmac`])
+///
+/// The signature covers the request's method, path, query, body, and `X-Sproc-Timestamp`
+/// (see [`webhook::request_message`]), which must be within [`webhook::SIGNATURE_MAX_AGE_SECS`]
+/// of now, so captured requests can't be replayed later.
+pub async fn verify_signature(
+    State(config): State<ServConf>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if config.server.auth_mode != AuthMode::Hmac {
+        return next.run(req).await;
+    }
+
+    let unauthorized = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn::<u16> {
+                ok: false,
+                data: 401,
+            }),
+        )
+            .into_response()
+    };
+
+    let (mut parts, body) = req.into_parts();
+
+    let bytes = match axum::body::to_bytes(body, MAX_SIGNED_BODY_BYTES).await {
+        Ok(b) => b,
+        Err(_) => return status_response(StatusCode::PAYLOAD_TOO_LARGE).into_response(),
+    };
+
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string()
+    };
+
+    // reject old (or future) timestamps
+    let now = (epoch_timestamp() / 1000) as u64;
+    let timestamp = match header("x-sproc-timestamp").parse::<u64>() {
+        Ok(t) if now.abs_diff(t) <= webhook::SIGNATURE_MAX_AGE_SECS => t,
+        _ => return unauthorized(),
+    };
+
+    let message = webhook::request_message(
+        timestamp,
+        parts.method.as_str(),
+        parts.uri.path(),
+        parts.uri.query().unwrap_or(""),
+        &bytes,
+    );
+
+    if !webhook::verify(&config.server.key, &message, &header("x-sproc-signature")) {
+        return unauthorized();
+    }
+
+    parts.extensions.insert(SignatureVerified);
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
 #[derive(Deserialize)]
 pub struct IndexBody {
     key: String,
@@ -30,8 +245,116 @@ pub struct IndexBody {
 pub struct BasicServiceRequestBody {
     /// The name of the service
     pub service: String,
-    /// Auth key
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
     pub key: String,
+    /// Send the operation to this remote server instead (`remotes.{name}`, only for `/start`,
+    /// `/kill`, and `/info`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+}
+
+impl Validate for BasicServiceRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "service", &self.service);
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+/// Request body for adopting a process
+#[derive(Serialize, Deserialize)]
+pub struct AdoptRequestBody {
+    /// The name of the service
+    pub service: String,
+    /// The PID of the process to adopt
+    pub pid: u32,
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+}
+
+impl Validate for AdoptRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "service", &self.service);
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+/// Request body for scaling a template
+#[derive(Serialize, Deserialize)]
+pub struct ScaleRequestBody {
+    /// The name of the template
+    pub service: String,
+    /// How many instances should be running
+    pub replicas: u32,
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+}
+
+impl Validate for ScaleRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "service", &self.service);
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+/// Request body for sending a signal to a service
+#[derive(Serialize, Deserialize)]
+pub struct SignalRequestBody {
+    /// The name of the service
+    pub service: String,
+    /// The signal to send (`"HUP"`, `"USR1"`, `"USR2"`, ...)
+    pub signal: String,
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+}
+
+impl Validate for SignalRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "service", &self.service);
+        require(&mut errors, "key", &self.key);
+
+        if parse_signal(&self.signal).is_err() {
+            errors.push(FieldError::new("signal", "must be a known signal"));
+        }
+
+        errors
+    }
+}
+
+/// Request body for writing to a service's stdin
+#[derive(Serialize, Deserialize)]
+pub struct StdinRequestBody {
+    /// The name of the service
+    pub service: String,
+    /// What to write (as it is, include a newline to end a command)
+    pub data: String,
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+}
+
+impl Validate for StdinRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "service", &self.service);
+        require(&mut errors, "key", &self.key);
+
+        if self.data.is_empty() {
+            errors.push(FieldError::new("data", "must not be empty"));
+        }
+
+        errors
+    }
 }
 
 /// Basic request body for operations on a specific service
@@ -41,121 +364,1379 @@ pub struct InstallRequestBody {
     pub registry: String,
     /// The name of the service
     pub service: String,
-    /// Auth key
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+}
+
+/// A service and its current state (from [`list_request`])
+#[derive(Serialize, Deserialize)]
+pub struct ServiceSummary {
+    pub name: String,
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    pub restart: bool,
+    /// Consecutive automatic restarts
+    pub restarts: u32,
+}
+
+/// Request body for operations that don't target a specific service
+#[derive(Serialize, Deserialize)]
+pub struct KeyRequestBody {
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+}
+
+impl Validate for KeyRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+/// Request body for validating a configuration
+#[derive(Serialize, Deserialize)]
+pub struct ValidateRequestBody {
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+    /// Configuration file contents to check instead of the pinned configuration
+    ///
+    /// `inherit` isn't applied to these contents.
+    #[serde(default)]
+    pub config: Option<String>,
+}
+
+impl Validate for ValidateRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+/// Request body for replacing the pinned configuration
+#[derive(Serialize, Deserialize)]
+pub struct ConfigRequestBody {
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+    /// The new configuration document
+    pub config: String,
+    /// The document's format (`toml` by default)
+    #[serde(default)]
+    pub format: ConfigFormat,
+    /// Only return the changes, without applying them
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl Validate for ConfigRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "config", &self.config);
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+/// Query parameters for operations that don't target a specific service
+#[derive(Serialize, Deserialize)]
+pub struct KeyQuery {
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+}
+
+/// Request body for reading a service's log file
+#[derive(Serialize, Deserialize)]
+pub struct LogsRequestBody {
+    /// The name of the service
+    pub service: String,
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
     pub key: String,
+    /// How many bytes to read from the end of the file (8192 by default)
+    pub bytes: Option<u64>,
+}
+
+impl Validate for LogsRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "service", &self.service);
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+/// Query parameters for [`logs_stream_request`]
+#[derive(Serialize, Deserialize)]
+pub struct LogsQuery {
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+    /// How many lines to send from the end of the file (200 by default)
+    pub lines: Option<usize>,
+    /// Keep sending lines as they're written
+    #[serde(default)]
+    pub follow: bool,
+}
+
+/// Query parameters for [`info_request`]
+#[derive(Serialize, Deserialize)]
+pub struct InfoRequestQuery {
+    /// Compare against the cached snapshot taken at (or before) this Unix timestamp (milliseconds)
+    pub since: Option<u128>,
+    /// How [`info_request`] returns the info (`toml` by default, `json` returns it like
+    /// [`info_v2_request`])
+    #[serde(default)]
+    pub format: OutputFormat,
+}
+
+impl InfoRequestQuery {
+    /// The query as parameters (for forwarding to a remote server)
+    fn params(&self) -> Vec<(&str, String)> {
+        use clap::ValueEnum;
+        let mut params = Vec::new();
+
+        if let Some(since) = self.since {
+            params.push(("since", since.to_string()));
+        }
+
+        if let Some(format) = self.format.to_possible_value() {
+            params.push(("format", format.get_name().to_string()));
+        }
+
+        params
+    }
+}
+
+/// Response data for [`info_v2_request`] (and [`info_request`] when `since` is provided)
+#[derive(Serialize, Deserialize)]
+pub struct InfoWithDiff {
+    pub current: ServiceInfo,
+    /// Changes since the snapshot closest to `since`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<ServiceInfoDiff>,
+}
+
+/// The number of [`ServiceInfo`] snapshots kept per service
+pub const INFO_SNAPSHOT_LIMIT: usize = 100;
+
+/// Cached [`ServiceInfo`] snapshots, `service -> [(timestamp, info)]` (oldest first)
+fn info_snapshots() -> &'static Mutex<HashMap<String, Vec<(u128, ServiceInfo)>>> {
+    static SNAPSHOTS: OnceLock<Mutex<HashMap<String, Vec<(u128, ServiceInfo)>>>> = OnceLock::new();
+    SNAPSHOTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// The configuration used by [`sproc_api`] routes, replaced by [`reload`]
+///
+/// Handlers extract [`ServConf`] from it, getting the configuration as of the last reload.
+#[derive(Clone)]
+pub struct SharedConfig(pub Arc<RwLock<ServConf>>);
+
+impl From<ServConf> for SharedConfig {
+    fn from(config: ServConf) -> Self {
+        Self(Arc::new(RwLock::new(config)))
+    }
+}
+
+impl FromRef<SharedConfig> for ServConf {
+    fn from_ref(shared: &SharedConfig) -> Self {
+        shared.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// The result of [`reload`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReloadReport {
+    /// Changes from the previous configuration
+    pub diff: ConfigDiff,
+    /// Added `autostart` services that were started
+    pub started: Vec<String>,
+    /// Problems that need attention (e.g. removed services that are still running)
+    pub warnings: Vec<String>,
+}
+
+/// Re-read the pinned configuration into `shared`
+///
+/// Added services with `autostart` are started. Services that were removed but are still
+/// running keep running, and are reported in [`ReloadReport::warnings`].
+pub fn reload(shared: &SharedConfig) -> Result<ReloadReport, SprocError> {
+    let new = ServConf::try_get_config()?;
+    let diff = ServConf::from_ref(shared).diff(&new);
+
+    let mut started = Vec::new();
+    let mut warnings = Vec::new();
+
+    for name in &diff.added {
+        let service = &new.services[name];
+        let running = new
+            .service_states
+            .get(name)
+            .map(|s| s.0.is_running())
+            .unwrap_or(false);
+
+        if !service.autostart || running || service.r#type == ServiceType::Application {
+            continue;
+        }
+
+        let task_name = name.to_owned();
+        tokio::task::spawn(async move {
+            if let Err(e) = Service::spawn(task_name).await {
+                println!("warn: {e}");
+            }
+        });
+
+        started.push(name.to_owned());
+    }
+
+    for name in &diff.removed {
+        if let Some((state, pid)) = new.service_states.get(name) {
+            if state.is_running() {
+                warnings.push(format!(
+                    "Service was removed but is still running. ({name}, {pid})"
+                ));
+            }
+        }
+    }
+
+    *shared.0.write().unwrap_or_else(|e| e.into_inner()) = new;
+    Ok(ReloadReport {
+        diff,
+        started,
+        warnings,
+    })
+}
+
+/// Reload the configuration every time the process receives `SIGHUP`
+#[cfg(unix)]
+pub async fn reload_on_hangup(shared: SharedConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("warn: failed to listen for SIGHUP: {e}");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        match reload(&shared) {
+            Ok(report) => {
+                println!(
+                    "info: reloaded configuration ({} added, {} removed, {} changed)",
+                    report.diff.added.len(),
+                    report.diff.removed.len(),
+                    report.diff.changed.len()
+                );
+
+                for name in report.started {
+                    println!("info: started service ({name})");
+                }
+
+                for warning in report.warnings {
+                    println!("warn: {warning}");
+                }
+            }
+            Err(e) => println!("warn: failed to reload configuration: {e}"),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_hangup(_: SharedConfig) {}
+
+/// Check if this process should run the server's background tasks
+///
+/// `SPROC_BACKGROUND_TASKS` (`1` or `0`) takes priority over `default`.
+pub fn background_tasks_enabled(default: bool) -> bool {
+    match std::env::var("SPROC_BACKGROUND_TASKS").as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        _ => default,
+    }
+}
+
+/// Spawn the server's background tasks: reconciling states (and starting `autostart`
+/// services), running scheduled services, keeping a process snapshot, rotating log files,
+/// and reloading `shared` on `SIGHUP`
+///
+/// Only one process should run these for a configuration, or services are started twice.
+pub fn spawn_background_tasks(shared: SharedConfig) {
+    // reconcile states and start autostart services
+    tokio::task::spawn(async {
+        match Service::boot().await {
+            Ok(started) => {
+                for name in started {
+                    println!("info: started service ({name})");
+                }
+            }
+            Err(e) => println!("warn: {e}"),
+        }
+    });
+
+    // run scheduled services
+    tokio::task::spawn(scheduler::run());
+
+    // keep a snapshot of service processes
+    tokio::task::spawn(snapshot::run());
+
+    // rotate and prune log files
+    tokio::task::spawn(logs::run());
+
+    // reload configuration on SIGHUP
+    tokio::task::spawn(reload_on_hangup(shared));
+}
+
+/// State shared by the health endpoints
+#[derive(Clone)]
+pub struct AppState {
+    /// When the server was started
+    pub started: Instant,
+}
+
+/// Overall server health
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// All services are running
+    Healthy,
+    /// Some services are not running
+    Degraded,
+    /// The server can't read its configuration
+    Unhealthy,
+}
+
+/// Response body for [`health_request`]
+#[derive(Serialize, Deserialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub services_running: usize,
+    pub services_failed: usize,
+    /// Services that are running but failing their health check
+    pub services_unhealthy: usize,
+    pub services_total: usize,
+    pub uptime_seconds: u64,
+    pub version: String,
+}
+
+/// Request body for renaming a service
+#[derive(Serialize, Deserialize)]
+pub struct RenameRequestBody {
+    /// The current name of the service
+    pub service: String,
+    /// The new name of the service
+    pub new_name: String,
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+}
+
+/// Query parameters for [`audit_request`]
+#[derive(Serialize, Deserialize)]
+pub struct AuditRequestQuery {
+    /// Auth key (optional when requests are signed, see [`AuthMode::Hmac`])
+    #[serde(default)]
+    pub key: String,
+    /// Only include entries for this service
+    pub service: Option<String>,
+    /// Only include entries at or after this Unix timestamp (milliseconds)
+    pub since: Option<u128>,
+    /// Only include the last `limit` entries
+    pub limit: Option<usize>,
+}
+
+/// Build an [`AuditContext`] from request headers
+pub fn audit_context(headers: &HeaderMap) -> AuditContext {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(",").next().unwrap_or(v).trim().to_string())
+    };
+
+    AuditContext {
+        caller_ip: header("x-forwarded-for").or_else(|| header("x-real-ip")),
+        request_id: header("x-request-id"),
+    }
+}
+
+/// Record an [`AuditEntry`], logging (not returning) failures
+fn audit_record(headers: &HeaderMap, action: AuditAction, service: &str, success: bool) {
+    if let Err(e) = audit::record(AuditEntry::new(
+        audit_context(headers),
+        action,
+        Some(service.to_string()),
+        success,
+    )) {
+        println!("warn: failed to write audit log: {e}");
+    }
+}
+
+impl Validate for InstallRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "registry", &self.registry);
+        require(&mut errors, "service", &self.service);
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+impl Validate for RenameRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "service", &self.service);
+        require(&mut errors, "new_name", &self.new_name);
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+impl Validate for RegistryPushRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "key", &self.key);
+        require(&mut errors, "content", &self.content);
+        errors
+    }
+}
+
+impl Validate for RegistryDeleteRequestBody {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        require(&mut errors, "key", &self.key);
+        errors
+    }
+}
+
+/// Default 404 response
+/// { "ok": false, "data": (http status) }
+pub async fn not_found() -> impl IntoResponse {
+    Json(APIReturn::<u16> {
+        ok: false,
+        data: 404,
+    })
+}
+
+/// Get the HTTP status for a [`SprocError`]
+pub fn error_status(error: &SprocError) -> StatusCode {
+    match error {
+        SprocError::ServiceNotFound(_) | SprocError::NotLoaded(_) => StatusCode::NOT_FOUND,
+        SprocError::AlreadyRunning(..)
+        | SprocError::NotRunning(_)
+        | SprocError::StdinUnavailable(_) => StatusCode::CONFLICT,
+        SprocError::ProcessNotFound(_) => StatusCode::GONE,
+        SprocError::ConfigParse(_) | SprocError::ProcessMismatch(..) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Respond with the given status
+/// { "ok": (if the status is a success), "data": (http status) }
+fn status_response(status: StatusCode) -> (StatusCode, Json<APIReturn<u16>>) {
+    (
+        status,
+        Json(APIReturn::<u16> {
+            ok: status.is_success(),
+            data: status.as_u16(),
+        }),
+    )
+}
+
+/// Send a request for a service to a remote server (`remotes.{remote}`), responding with the
+/// remote server's response as it was received
+async fn remote_response(
+    config: &ServConf,
+    remote: &str,
+    endpoint: &str,
+    service: &str,
+    query: &[(&str, String)],
+) -> Response {
+    let remote = match config.remote(remote) {
+        Ok(r) => r,
+        Err(_) => return status_response(StatusCode::NOT_FOUND).into_response(),
+    };
+
+    match remote.request(endpoint, Some(service), query).await {
+        Ok((status, body)) => (
+            StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY),
+            [(header::CONTENT_TYPE, "application/json")],
+            body,
+        )
+            .into_response(),
+        Err(_) => status_response(StatusCode::BAD_GATEWAY).into_response(),
+    }
+}
+
+/// Start and observe a service (POST /start)
+pub async fn observe_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<BasicServiceRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Start, Some(&body.service)) {
+        return status_response(StatusCode::UNAUTHORIZED).into_response();
+    }
+
+    // forward to remote
+    if let Some(ref remote) = body.remote {
+        let res = remote_response(&config, remote, "start", &body.service, &[]).await;
+        let service = format!("{remote}/{}", body.service);
+        let ok = res.status().is_success();
+        audit_record(&headers, AuditAction::Start, &service, ok);
+        return res;
+    }
+
+    // start
+    if let Err(e) = Service::spawn(body.service.clone()).await {
+        audit_record(&headers, AuditAction::Start, &body.service, false);
+        return status_response(error_status(&e)).into_response();
+    };
+
+    audit_record(&headers, AuditAction::Start, &body.service, true);
+
+    // return
+    status_response(StatusCode::OK).into_response()
+}
+
+/// Manage a process that was started outside of sproc as a service (POST /adopt)
+pub async fn adopt_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<AdoptRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Start, Some(&body.service)) {
+        return status_response(StatusCode::UNAUTHORIZED);
+    }
+
+    // adopt
+    if let Err(e) = Service::adopt(body.service.clone(), body.pid) {
+        audit_record(&headers, AuditAction::Adopt, &body.service, false);
+        return status_response(error_status(&e));
+    };
+
+    audit_record(&headers, AuditAction::Adopt, &body.service, true);
+
+    // return
+    status_response(StatusCode::OK)
+}
+
+/// Kill a service (POST /kill)
+pub async fn kill_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<BasicServiceRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Kill, Some(&body.service)) {
+        return status_response(StatusCode::UNAUTHORIZED).into_response();
+    }
+
+    // forward to remote
+    if let Some(ref remote) = body.remote {
+        let res = remote_response(&config, remote, "kill", &body.service, &[]).await;
+        let service = format!("{remote}/{}", body.service);
+        let ok = res.status().is_success();
+        audit_record(&headers, AuditAction::Kill, &service, ok);
+        return res;
+    }
+
+    // kill
+    let res = match ServConf::try_get_config() {
+        Ok(c) => Service::kill_task(body.service.clone(), c).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = res {
+        audit_record(&headers, AuditAction::Kill, &body.service, false);
+        return status_response(error_status(&e)).into_response();
+    };
+
+    audit_record(&headers, AuditAction::Kill, &body.service, true);
+
+    // update config
+    if ServConf::modify(|config| {
+        config.service_states.remove(&body.service);
+    })
+    .is_err()
+    {
+        return status_response(StatusCode::INTERNAL_SERVER_ERROR).into_response();
+    }
+
+    // return
+    status_response(StatusCode::OK).into_response()
+}
+
+/// Send a signal to a running service (POST /signal)
+pub async fn signal_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<SignalRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Kill, Some(&body.service)) {
+        return status_response(StatusCode::UNAUTHORIZED);
+    }
+
+    // signal
+    let res = Service::signal(body.service.clone(), &body.signal);
+    audit_record(&headers, AuditAction::Signal, &body.service, res.is_ok());
+
+    if let Err(e) = res {
+        return status_response(error_status(&e));
+    }
+
+    // return
+    status_response(StatusCode::OK)
+}
+
+/// Write to a running service's stdin (POST /stdin)
+pub async fn stdin_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<StdinRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Start, Some(&body.service)) {
+        return status_response(StatusCode::UNAUTHORIZED);
+    }
+
+    // write
+    let res = Service::write_stdin(body.service.clone(), &body.data).await;
+    audit_record(&headers, AuditAction::Stdin, &body.service, res.is_ok());
+
+    if let Err(e) = res {
+        return status_response(error_status(&e));
+    }
+
+    // return
+    status_response(StatusCode::OK)
+}
+
+/// Record a bulk operation's results in the audit log and build its response
+fn bulk_response(
+    headers: &HeaderMap,
+    action: AuditAction,
+    res: Result<BulkResults, SprocError>,
+) -> (StatusCode, Json<APIReturn<BulkResults>>) {
+    let results = match res {
+        Ok(r) => r,
+        Err(e) => {
+            return (
+                error_status(&e),
+                Json(APIReturn {
+                    ok: false,
+                    data: BulkResults::new(),
+                }),
+            )
+        }
+    };
+
+    for (name, result) in &results {
+        audit_record(headers, action.clone(), name, result.ok);
+    }
+
+    (
+        StatusCode::OK,
+        Json(APIReturn {
+            ok: results.values().all(|r| r.ok),
+            data: results,
+        }),
+    )
+}
+
+/// Spawn every service that isn't running (POST /start-all)
+pub async fn start_all_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<KeyRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Start, None) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn {
+                ok: false,
+                data: BulkResults::new(),
+            }),
+        );
+    }
+
+    // start
+    bulk_response(&headers, AuditAction::Start, Service::start_all().await)
+}
+
+/// Kill every running service (POST /kill-all)
+pub async fn kill_all_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<KeyRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Kill, None) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn {
+                ok: false,
+                data: BulkResults::new(),
+            }),
+        );
+    }
+
+    // kill
+    bulk_response(&headers, AuditAction::Kill, Service::kill_all().await)
+}
+
+/// Kill and re-spawn a service (POST /restart)
+pub async fn restart_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<BasicServiceRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !(authorized_for(&config, &body.key, TokenScope::Start, Some(&body.service))
+        && authorized_for(&config, &body.key, TokenScope::Kill, Some(&body.service)))
+    {
+        return status_response(StatusCode::UNAUTHORIZED);
+    }
+
+    // restart
+    if let Err(e) = Service::restart(body.service.clone()).await {
+        audit_record(&headers, AuditAction::Restart, &body.service, false);
+        return status_response(error_status(&e));
+    };
+
+    audit_record(&headers, AuditAction::Restart, &body.service, true);
+
+    // return
+    status_response(StatusCode::OK)
+}
+
+/// Start or kill instances of a template until `replicas` are running (POST /scale)
+pub async fn scale_request(
+    headers: HeaderMap,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<ScaleRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !(authorized_for(&config, &body.key, TokenScope::Start, Some(&body.service))
+        && authorized_for(&config, &body.key, TokenScope::Kill, Some(&body.service)))
+    {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn {
+                ok: false,
+                data: BulkResults::new(),
+            }),
+        );
+    }
+
+    // scale
+    bulk_response(
+        &headers,
+        AuditAction::Scale,
+        Service::scale(body.service, body.replicas).await,
+    )
+}
+
+/// Get service info, caching a snapshot and diffing against the one closest to `since`
+fn info_with_snapshot(service: &str, since: Option<u128>) -> Result<InfoWithDiff, SprocError> {
+    // get updated config
+    let config = ServConf::try_get_config()?;
+    let info = Service::info(service.to_string(), config.service_states)?;
+
+    // cache snapshot
+    let mut snapshots = info_snapshots().lock().unwrap();
+    let service_snapshots = snapshots.entry(service.to_string()).or_default();
+
+    let previous = since.and_then(|since| {
+        service_snapshots
+            .iter()
+            .rev()
+            .find(|s| s.0 <= since)
+            .or(service_snapshots.first())
+            .map(|s| s.1.clone())
+    });
+
+    service_snapshots.push((epoch_timestamp(), info.clone()));
+
+    if service_snapshots.len() > INFO_SNAPSHOT_LIMIT {
+        service_snapshots.remove(0);
+    }
+
+    // return
+    Ok(InfoWithDiff {
+        diff: previous.map(|p| ServiceInfo::diff(&p, &info)),
+        current: info,
+    })
+}
+
+/// Get service info as TOML, JSON, or a table (POST /info)
+pub async fn info_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Query(query): Query<InfoRequestQuery>,
+    ValidatedJson(body): ValidatedJson<BasicServiceRequestBody>,
+) -> impl IntoResponse {
+    // structured
+    if query.format == OutputFormat::Json {
+        return info_v2_request(State(config), Query(query), ValidatedJson(body))
+            .await
+            .into_response();
+    }
+
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Info, Some(&body.service)) {
+        return Json(APIReturn::<String> {
+            ok: false,
+            data: String::new(),
+        })
+        .into_response();
+    }
+
+    // forward to remote
+    if let Some(ref remote) = body.remote {
+        return remote_response(&config, remote, "info", &body.service, &query.params()).await;
+    }
+
+    // return
+    Json(APIReturn::<String> {
+        ok: true,
+        data: match info_with_snapshot(&body.service, query.since) {
+            Ok(i) => match i.diff {
+                Some(_) => toml::to_string_pretty(&i).unwrap(),
+                None => query.format.render_info(&i.current),
+            },
+            Err(e) => {
+                return Json(APIReturn::<String> {
+                    ok: false,
+                    data: e.to_string(),
+                })
+                .into_response()
+            }
+        },
+    })
+    .into_response()
+}
+
+/// Get service info (POST /info/v2)
+pub async fn info_v2_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Query(query): Query<InfoRequestQuery>,
+    ValidatedJson(body): ValidatedJson<BasicServiceRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Info, Some(&body.service)) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn::<Option<InfoWithDiff>> {
+                ok: false,
+                data: None,
+            }),
+        )
+            .into_response();
+    }
+
+    // forward to remote
+    if let Some(ref remote) = body.remote {
+        return remote_response(&config, remote, "info/v2", &body.service, &query.params()).await;
+    }
+
+    // return
+    match info_with_snapshot(&body.service, query.since) {
+        Ok(i) => (
+            StatusCode::OK,
+            Json(APIReturn::<Option<InfoWithDiff>> {
+                ok: true,
+                data: Some(i),
+            }),
+        )
+            .into_response(),
+        Err(e) => (
+            error_status(&e),
+            Json(APIReturn::<Option<InfoWithDiff>> {
+                ok: false,
+                data: None,
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// List all services and their states (POST /list)
+pub async fn list_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<KeyRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Info, None) {
+        return (
+            StatusCode::OK,
+            Json(APIReturn::<Vec<ServiceSummary>> {
+                ok: false,
+                data: Vec::new(),
+            }),
+        );
+    }
+
+    // get updated config
+    let config = match ServConf::try_get_config() {
+        Ok(c) => c,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(APIReturn::<Vec<ServiceSummary>> {
+                    ok: false,
+                    data: Vec::new(),
+                }),
+            )
+        }
+    };
+
+    let mut services = Vec::new();
+
+    for (name, service) in config.services {
+        let state = config.service_states.get(&name);
+
+        services.push(ServiceSummary {
+            state: state.map(|s| s.0.clone()).unwrap_or_default(),
+            pid: state.map(|s| s.1),
+            restart: service.restart,
+            restarts: config.restart_counts.get(&name).copied().unwrap_or(0),
+            name,
+        });
+    }
+
+    services.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // return
+    (
+        StatusCode::OK,
+        Json(APIReturn::<Vec<ServiceSummary>> {
+            ok: true,
+            data: services,
+        }),
+    )
+}
+
+/// Get every running service's info with their total resource usage (POST /info-all)
+pub async fn info_all_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<KeyRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &body.key, TokenScope::Info, None) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn::<Option<ServicesInfo>> {
+                ok: false,
+                data: None,
+            }),
+        );
+    }
+
+    // return
+    match ServConf::try_get_config() {
+        Ok(c) => (
+            StatusCode::OK,
+            Json(APIReturn::<Option<ServicesInfo>> {
+                ok: true,
+                data: Some(c.info_all()),
+            }),
+        ),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(APIReturn::<Option<ServicesInfo>> {
+                ok: false,
+                data: None,
+            }),
+        ),
+    }
+}
+
+/// Get every service with its state and process info (GET /services)
+pub async fn services_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Query(query): Query<KeyQuery>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &query.key, TokenScope::Info, None) {
+        return (
+            StatusCode::OK,
+            Json(APIReturn::<Option<ServicesSummary>> {
+                ok: false,
+                data: None,
+            }),
+        );
+    }
+
+    // return
+    match ServConf::try_get_config() {
+        Ok(c) => (
+            StatusCode::OK,
+            Json(APIReturn::<Option<ServicesSummary>> {
+                ok: true,
+                data: Some(c.summary()),
+            }),
+        ),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(APIReturn::<Option<ServicesSummary>> {
+                ok: false,
+                data: None,
+            }),
+        ),
+    }
+}
+
+/// Get service gauges in the Prometheus text format (GET /metrics)
+pub async fn metrics_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Query(query): Query<KeyQuery>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &query.key, TokenScope::Info, None) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            String::new(),
+        );
+    }
+
+    // return
+    match ServConf::try_get_config() {
+        Ok(c) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            metrics::render(&c),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            e.to_string(),
+        ),
+    }
+}
+
+/// Stream service events as they happen (GET /events, server-sent events)
+///
+/// Each event is named after its [`webhook::ServiceEventKind`] and carries a
+/// [`webhook::WebhookPayload`] as JSON.
+pub async fn events_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Query(query): Query<KeyQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    // check key
+    if !authorized_for(&config, &query.key, TokenScope::Info, None) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // subscribe (events missed by lagging clients are skipped)
+    let stream = BroadcastStream::new(events::subscribe()).filter_map(|payload| {
+        let payload = payload.ok()?;
+
+        Event::default()
+            .event(format!("{:?}", payload.event).to_lowercase())
+            .json_data(&payload)
+            .ok()
+            .map(Ok)
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
-/// Default 404 response
-/// { "ok": false, "data": (http status) }
-pub async fn not_found() -> impl IntoResponse {
-    Json(APIReturn::<u16> {
-        ok: false,
-        data: 404,
-    })
+/// Look for common problems with the configuration and services (GET /doctor)
+pub async fn doctor_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Query(query): Query<KeyQuery>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &query.key, TokenScope::Info, None) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn::<Option<DoctorReport>> {
+                ok: false,
+                data: None,
+            }),
+        );
+    }
+
+    // return
+    match Service::doctor(None) {
+        Ok(report) => (
+            StatusCode::OK,
+            Json(APIReturn::<Option<DoctorReport>> {
+                ok: true,
+                data: Some(report),
+            }),
+        ),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(APIReturn::<Option<DoctorReport>> {
+                ok: false,
+                data: None,
+            }),
+        ),
+    }
 }
 
-/// Start and observe a service (POST /start)
-pub async fn observe_request(
-    State(config): State<ServConf>, // inital config from server start
-    Json(body): Json<BasicServiceRequestBody>,
+/// Check a configuration for problems (POST /validate)
+pub async fn validate_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<ValidateRequestBody>,
 ) -> impl IntoResponse {
     // check key
-    if body.key != config.server.key {
-        return Json(APIReturn::<u16> {
-            ok: false,
-            data: 401,
-        });
+    if !authorized_for(&config, &body.key, TokenScope::Info, None) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn::<Option<ValidationReport>> {
+                ok: false,
+                data: None,
+            }),
+        );
     }
 
-    // start
-    if let Err(_) = Service::spawn(body.service.clone()).await {
-        return Json(APIReturn::<u16> {
-            ok: false,
-            data: 400,
-        });
+    // parse
+    let report = match body.config {
+        Some(ref contents) => match ServConf::parse(contents) {
+            Ok(c) => c.check(),
+            Err(e) => ValidationReport {
+                errors: vec![ValidationError::InvalidSyntax {
+                    error: e.to_string(),
+                }],
+                warnings: Vec::new(),
+            },
+        },
+        None => match ServConf::try_get_config() {
+            Ok(c) => c.check(),
+            Err(e) => ValidationReport {
+                errors: vec![ValidationError::InvalidSyntax {
+                    error: e.to_string(),
+                }],
+                warnings: Vec::new(),
+            },
+        },
     };
 
     // return
-    Json(APIReturn::<u16> {
-        ok: true,
-        data: 200,
-    })
+    (
+        StatusCode::OK,
+        Json(APIReturn::<Option<ValidationReport>> {
+            ok: report.errors.is_empty(),
+            data: Some(report),
+        }),
+    )
 }
 
-/// Kill a service (POST /kill)
-pub async fn kill_request(
-    State(config): State<ServConf>, // inital config from server start
-    Json(body): Json<BasicServiceRequestBody>,
+/// Replace the pinned configuration, returning what changed (POST /config)
+pub async fn config_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<ConfigRequestBody>,
 ) -> impl IntoResponse {
     // check key
-    if body.key != config.server.key {
-        return Json(APIReturn::<u16> {
-            ok: false,
-            data: 401,
-        });
+    if !authorized(&config, &body.key) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn::<Option<ConfigDiff>> {
+                ok: false,
+                data: None,
+            }),
+        );
     }
 
-    // get updated config
-    let mut config = ServConf::get_config();
+    // apply
+    match ServConf::apply_document(&body.config, body.format, body.dry_run) {
+        Ok(diff) => (
+            StatusCode::OK,
+            Json(APIReturn::<Option<ConfigDiff>> {
+                ok: true,
+                data: Some(diff),
+            }),
+        ),
+        Err(e) => (
+            error_status(&e),
+            Json(APIReturn::<Option<ConfigDiff>> {
+                ok: false,
+                data: None,
+            }),
+        ),
+    }
+}
 
-    // kill
-    // TODO: try to clone less
-    if let Err(_) = Service::kill(body.service.clone(), config.clone()) {
-        return Json(APIReturn::<u16> {
-            ok: false,
-            data: 400,
-        });
-    };
+/// Re-read the pinned configuration (POST /reload)
+pub async fn reload_request(
+    State(shared): State<SharedConfig>,
+    ValidatedJson(body): ValidatedJson<KeyRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized(&ServConf::from_ref(&shared), &body.key) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn::<Option<ReloadReport>> {
+                ok: false,
+                data: None,
+            }),
+        );
+    }
 
-    // update config
-    config.service_states.remove(&body.service);
-    ServConf::update_config(config.clone()).unwrap();
+    // reload
+    match reload(&shared) {
+        Ok(report) => (
+            StatusCode::OK,
+            Json(APIReturn::<Option<ReloadReport>> {
+                ok: true,
+                data: Some(report),
+            }),
+        ),
+        Err(e) => (
+            error_status(&e),
+            Json(APIReturn::<Option<ReloadReport>> {
+                ok: false,
+                data: None,
+            }),
+        ),
+    }
+}
+
+/// Get every scheduled service's last and next run (GET /schedule)
+pub async fn schedule_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Query(query): Query<KeyQuery>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized_for(&config, &query.key, TokenScope::Info, None) {
+        return (
+            StatusCode::OK,
+            Json(APIReturn::<Vec<scheduler::ScheduledRun>> {
+                ok: false,
+                data: Vec::new(),
+            }),
+        );
+    }
 
     // return
-    Json(APIReturn::<u16> {
-        ok: true,
-        data: 200,
-    })
+    match ServConf::try_get_config() {
+        Ok(c) => (
+            StatusCode::OK,
+            Json(APIReturn::<Vec<scheduler::ScheduledRun>> {
+                ok: true,
+                data: scheduler::upcoming(&c),
+            }),
+        ),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(APIReturn::<Vec<scheduler::ScheduledRun>> {
+                ok: false,
+                data: Vec::new(),
+            }),
+        ),
+    }
 }
 
-/// Get service info (POST /info)
-pub async fn info_request(
-    State(config): State<ServConf>, // inital config from server start
-    Json(body): Json<BasicServiceRequestBody>,
+/// Get the end of a service's log file as plain text (POST /logs)
+pub async fn logs_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<LogsRequestBody>,
 ) -> impl IntoResponse {
     // check key
-    if body.key != config.server.key {
-        return Json(APIReturn::<String> {
-            ok: false,
-            data: String::new(),
-        });
+    if !authorized_for(&config, &body.key, TokenScope::Logs, Some(&body.service)) {
+        return (StatusCode::UNAUTHORIZED, String::new());
     }
 
     // get updated config
-    let config = ServConf::get_config();
+    let config = match ServConf::try_get_config() {
+        Ok(c) => c,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
 
-    // return
-    Json(APIReturn::<String> {
-        ok: true,
-        data: match Service::info(body.service.clone(), config.service_states) {
-            Ok(i) => i,
-            Err(e) => {
-                return Json(APIReturn::<String> {
-                    ok: false,
-                    data: e.to_string(),
-                })
+    let path = match config.services.get(&body.service) {
+        Some(s) => match s.log_path(&body.service) {
+            Some(p) => p,
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("Service has no log file. ({})", body.service),
+                )
             }
         },
-    })
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("Service does not exist. ({})", body.service),
+            )
+        }
+    };
+
+    // read tail
+    match read_tail(&path, body.bytes.unwrap_or(8192)) {
+        Ok(s) => (StatusCode::OK, s),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// Stream a service's log file as server-sent events (GET /logs/:service)
+///
+/// Each line is sent as a `line` event. With `follow`, the stream stays open and lines are sent
+/// as they're written (like `tail -f`).
+pub async fn logs_stream_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Path(service): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    // check key
+    if !authorized_for(&config, &query.key, TokenScope::Logs, Some(&service)) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // get updated config
+    let config = match ServConf::try_get_config() {
+        Ok(c) => c,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let path = config
+        .services
+        .get(&service)
+        .and_then(|s| s.log_path(&service));
+
+    let path = match path {
+        Some(p) => p,
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+
+    // read tail
+    let (lines, offset) = match logs::read_lines(&path, query.lines.unwrap_or(200)) {
+        Ok(r) => r,
+        Err(_) => return Err(StatusCode::NOT_FOUND),
+    };
+
+    // follow (the stream ends after the tail if the sender is dropped)
+    let followed = if query.follow {
+        logs::follow(path, offset)
+    } else {
+        tokio::sync::mpsc::channel(1).1
+    };
+
+    let stream = tokio_stream::iter(lines)
+        .chain(ReceiverStream::new(followed))
+        .map(|line| Ok(Event::default().event("line").data(line.replace("\r", ""))));
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
 /// Install a service (POST /install)
 pub async fn install_request(
-    State(config): State<ServConf>, // inital config from server start
-    Json(body): Json<InstallRequestBody>,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<InstallRequestBody>,
 ) -> impl IntoResponse {
     // check key
-    if body.key != config.server.key {
+    if !authorized(&config, &body.key) {
         return Json(APIReturn::<String> {
             ok: false,
             data: String::new(),
@@ -178,11 +1759,11 @@ pub async fn install_request(
 
 /// Uninstall a service (POST /uninstall)
 pub async fn uninstall_request(
-    State(config): State<ServConf>, // inital config from server start
-    Json(body): Json<BasicServiceRequestBody>,
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<BasicServiceRequestBody>,
 ) -> impl IntoResponse {
     // check key
-    if body.key != config.server.key {
+    if !authorized(&config, &body.key) {
         return Json(APIReturn::<String> {
             ok: false,
             data: String::new(),
@@ -209,6 +1790,156 @@ pub async fn uninstall_request(
     })
 }
 
+/// Rename a service (POST /rename)
+pub async fn rename_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    ValidatedJson(body): ValidatedJson<RenameRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized(&config, &body.key) {
+        return Json(APIReturn::<String> {
+            ok: false,
+            data: String::new(),
+        });
+    }
+
+    // rename
+    if let Err(e) = ServConf::rename_service(&body.service, &body.new_name) {
+        return Json(APIReturn::<String> {
+            ok: false,
+            data: e.to_string(),
+        });
+    }
+
+    // return
+    Json(APIReturn::<String> {
+        ok: true,
+        data: String::new(),
+    })
+}
+
+/// Read the pinned configuration without panicking on errors
+fn read_config() -> Option<ServConf> {
+    let path = config_path();
+    let mut config = match std::fs::read_to_string(&path) {
+        Ok(c) => ServConf::parse_file(&path.to_string_lossy(), &c).ok()?,
+        Err(_) => ServConf::default(),
+    };
+
+    config.load_state();
+    Some(config)
+}
+
+/// Get server health (GET /health)
+pub async fn health_request(State(state): State<AppState>) -> impl IntoResponse {
+    let uptime_seconds = state.started.elapsed().as_secs();
+    let version = env!("CARGO_PKG_VERSION").to_string();
+
+    let config = match read_config() {
+        Some(c) => c,
+        None => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(HealthReport {
+                    status: HealthStatus::Unhealthy,
+                    services_running: 0,
+                    services_failed: 0,
+                    services_unhealthy: 0,
+                    services_total: 0,
+                    uptime_seconds,
+                    version,
+                }),
+            )
+        }
+    };
+
+    let services_total = config
+        .services
+        .values()
+        .filter(|s| s.r#type == ServiceType::Service)
+        .count();
+    let services_running = config
+        .service_states
+        .values()
+        .filter(|s| s.0 == ServiceState::Running)
+        .count();
+
+    let services_failed = config
+        .service_states
+        .values()
+        .filter(|s| matches!(s.0, ServiceState::Failed(..)))
+        .count();
+
+    let services_unhealthy = config
+        .service_states
+        .values()
+        .filter(|s| s.0 == ServiceState::Unhealthy)
+        .count();
+
+    (
+        StatusCode::OK,
+        Json(HealthReport {
+            status: if services_running >= services_total {
+                HealthStatus::Healthy
+            } else {
+                HealthStatus::Degraded
+            },
+            services_running,
+            services_failed,
+            services_unhealthy,
+            services_total,
+            uptime_seconds,
+            version,
+        }),
+    )
+}
+
+/// Check if all services that should restart are running (GET /health/ready)
+pub async fn health_ready_request() -> impl IntoResponse {
+    let config = match read_config() {
+        Some(c) => c,
+        None => return StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    for (name, service) in config.services {
+        if !service.restarts() {
+            continue;
+        }
+
+        match config.service_states.get(&name) {
+            Some(s) if s.0 == ServiceState::Running => continue,
+            _ => return StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    StatusCode::OK
+}
+
+/// Read the audit log (GET /admin/audit)
+pub async fn audit_request(
+    State(config): State<ServConf>, // config from server start (or the last reload)
+    Query(query): Query<AuditRequestQuery>,
+) -> impl IntoResponse {
+    // check key
+    if !authorized(&config, &query.key) {
+        return Json(APIReturn::<Vec<AuditEntry>> {
+            ok: false,
+            data: Vec::new(),
+        });
+    }
+
+    // return
+    Json(APIReturn::<Vec<AuditEntry>> {
+        ok: true,
+        data: audit::read(AuditFilter {
+            service: query.service,
+            since: query.since,
+            limit: query.limit,
+        })
+        .unwrap_or_default(),
+    })
+}
+
 // registry
 
 #[derive(Template)]
@@ -354,7 +2085,10 @@ pub async fn registry_manage_server_request(
 
     // service manager
     let mut services = Vec::new();
-    let config = ServConf::get_config();
+    let config = match ServConf::try_get_config() {
+        Ok(c) => c,
+        Err(e) => return Html(e.to_string()),
+    };
 
     for service in config.services {
         services.push((
@@ -381,7 +2115,7 @@ pub async fn registry_manage_server_request(
 /// [`Registry::get`]
 pub async fn registry_get_request(
     Path(name): Path<String>,
-    State(registry): State<Registry>, // inital config from server start
+    State(registry): State<Registry>, // config from server start (or the last reload)
 ) -> impl IntoResponse {
     Json(APIReturn::<String> {
         ok: true,
@@ -400,8 +2134,8 @@ pub async fn registry_get_request(
 /// [`Registry::push`]
 pub async fn registry_push_request(
     Path(name): Path<String>,
-    State(registry): State<Registry>, // inital config from server start
-    Json(props): Json<RegistryPushRequestBody>,
+    State(registry): State<Registry>, // config from server start (or the last reload)
+    ValidatedJson(props): ValidatedJson<RegistryPushRequestBody>,
 ) -> impl IntoResponse {
     Json(APIReturn::<String> {
         ok: true,
@@ -420,8 +2154,8 @@ pub async fn registry_push_request(
 /// [`Registry::delete`]
 pub async fn registry_delete_request(
     Path(name): Path<String>,
-    State(registry): State<Registry>, // inital config from server start
-    Json(props): Json<RegistryDeleteRequestBody>,
+    State(registry): State<Registry>, // config from server start (or the last reload)
+    ValidatedJson(props): ValidatedJson<RegistryDeleteRequestBody>,
 ) -> impl IntoResponse {
     Json(APIReturn::<String> {
         ok: true,
@@ -440,14 +2174,126 @@ pub async fn registry_delete_request(
 // ...
 
 /// Sproc API endpoints
-pub fn sproc_api(config: ServConf) -> Router {
+/// Serve an app on the configured Unix domain socket, or `host` and `port`
+pub async fn serve(config: &ServerConfiguration, app: Router) -> std::io::Result<()> {
+    if let Some(ref path) = config.unix_socket {
+        println!("Starting server at unix:{path}!");
+        return serve_unix(path, app).await;
+    }
+
+    if let Some(ref tls) = config.tls {
+        let address = match tokio::net::lookup_host((config.host.as_str(), config.port))
+            .await?
+            .next()
+        {
+            Some(a) => a,
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrNotAvailable,
+                    format!("Failed to resolve server host. ({})", config.host),
+                ))
+            }
+        };
+
+        let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
+
+        println!("Starting server at {}!", config.url());
+        return axum_server::bind_rustls(address, rustls)
+            .serve(app.into_make_service())
+            .await;
+    }
+
+    let listener = tokio::net::TcpListener::bind((config.host.as_str(), config.port)).await?;
+
+    println!("Starting server at {}!", config.url());
+    axum::serve(listener, app).await
+}
+
+/// Serve an app on a Unix domain socket, replacing any existing socket file
+#[cfg(unix)]
+async fn serve_unix(path: &str, app: Router) -> std::io::Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use std::os::unix::fs::PermissionsExt;
+    use tower::ServiceExt;
+
+    let _ = std::fs::remove_file(path);
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let service =
+                hyper::service::service_fn(move |request: Request<hyper::body::Incoming>| {
+                    app.clone().oneshot(request)
+                });
+
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(socket), service)
+                .await
+            {
+                println!("warn: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_: &str, _: Router) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix domain sockets are not supported on this platform.",
+    ))
+}
+
+/// Sproc API routes
+///
+/// Keep a clone of `config` to [`reload`] it later (e.g. with [`reload_on_hangup`]).
+pub fn sproc_api(config: SharedConfig) -> Router {
     Router::new()
         .route("/start", post(observe_request))
         .route("/kill", post(kill_request))
+        .route("/restart", post(restart_request))
+        .route("/adopt", post(adopt_request))
+        .route("/scale", post(scale_request))
+        .route("/signal", post(signal_request))
+        .route("/stdin", post(stdin_request))
+        .route("/start-all", post(start_all_request))
+        .route("/kill-all", post(kill_all_request))
         .route("/info", post(info_request))
+        .route("/info/v2", post(info_v2_request))
+        .route("/info-all", post(info_all_request))
+        .route("/list", post(list_request))
+        .route("/services", get(services_request))
+        .route("/metrics", get(metrics_request))
+        .route("/events", get(events_request))
+        .route("/schedule", get(schedule_request))
+        .route("/validate", post(validate_request))
+        .route("/doctor", get(doctor_request))
+        .route("/config", post(config_request))
+        .route("/reload", post(reload_request))
+        .route("/logs", post(logs_request))
+        .route("/logs/:service", get(logs_stream_request))
         .route("/install", post(install_request))
         .route("/uninstall", post(uninstall_request))
+        .route("/rename", post(rename_request))
+        .route("/admin/audit", get(audit_request))
+        .layer(middleware::from_fn_with_state(
+            config.clone(),
+            verify_signature,
+        ))
         .with_state(config)
+        .merge(
+            Router::new()
+                .route("/health", get(health_request))
+                .route("/health/ready", get(health_ready_request))
+                .with_state(AppState {
+                    started: Instant::now(),
+                }),
+        )
 }
 
 /// Registry API routes
@@ -471,3 +2317,351 @@ pub fn registry_public(config: ServConf, database: AuthDatabase) -> Router {
         .fallback(registry_not_found)
         .with_state((Registry::new(config.server), database))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ApiToken;
+    use axum::http::Request as HttpRequest;
+    use tower::ServiceExt;
+
+    /// An app that answers `POST /kill` behind [`verify_signature`] (with key `key`)
+    fn signed_app() -> Router {
+        let mut config = ServConf::default();
+        config.server.key = "key".to_string();
+        config.server.auth_mode = AuthMode::Hmac;
+
+        Router::new()
+            .route("/kill", post(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(config, verify_signature))
+    }
+
+    fn request(timestamp: u64, signature: &str) -> HttpRequest<Body> {
+        HttpRequest::post("/kill")
+            .header("x-sproc-timestamp", timestamp.to_string())
+            .header("x-sproc-signature", signature)
+            .body(Body::from("{}"))
+            .unwrap()
+    }
+
+    fn now() -> u64 {
+        (epoch_timestamp() / 1000) as u64
+    }
+
+    #[tokio::test]
+    async fn signed_requests_are_accepted() {
+        let signature = webhook::sign_request("key", now(), "POST", "/kill", "", b"{}");
+        let res = signed_app()
+            .oneshot(request(now(), &signature))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn forged_signatures_are_rejected() {
+        let signature = webhook::sign_request("not the key", now(), "POST", "/kill", "", b"{}");
+        let res = signed_app()
+            .oneshot(request(now(), &signature))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn replayed_requests_are_rejected() {
+        let old = now() - webhook::SIGNATURE_MAX_AGE_SECS - 1;
+        let signature = webhook::sign_request("key", old, "POST", "/kill", "", b"{}");
+        let res = signed_app()
+            .oneshot(request(old, &signature))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn large_bodies_are_rejected() {
+        let res = signed_app()
+            .oneshot(
+                HttpRequest::post("/kill")
+                    .body(Body::from(vec![b' '; MAX_SIGNED_BODY_BYTES + 1]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn tokens_are_limited_to_their_scopes_and_services() {
+        let mut config = ServConf::default();
+        config.server.key = "key".to_string();
+        config.server.tokens = vec![
+            ApiToken {
+                token: "deploy".to_string(),
+                scopes: vec![TokenScope::Start, TokenScope::Kill],
+                services: Some(vec!["api".to_string()]),
+            },
+            ApiToken {
+                token: "monitor".to_string(),
+                scopes: vec![TokenScope::Info],
+                services: None,
+            },
+        ];
+
+        // the server key can do anything
+        assert!(authorized_for(&config, "key", TokenScope::Logs, None));
+        assert!(authorized_for(&config, "key", TokenScope::Kill, Some("db")));
+
+        // tokens are limited to their scopes
+        assert!(authorized_for(
+            &config,
+            "deploy",
+            TokenScope::Start,
+            Some("api")
+        ));
+        assert!(authorized_for(
+            &config,
+            "deploy",
+            TokenScope::Kill,
+            Some("api")
+        ));
+        assert!(!authorized_for(
+            &config,
+            "deploy",
+            TokenScope::Info,
+            Some("api")
+        ));
+        assert!(authorized_for(
+            &config,
+            "monitor",
+            TokenScope::Info,
+            Some("db")
+        ));
+        assert!(authorized_for(&config, "monitor", TokenScope::Info, None));
+        assert!(!authorized_for(
+            &config,
+            "monitor",
+            TokenScope::Kill,
+            Some("db")
+        ));
+
+        // ... and to their services (which excludes every service at once)
+        assert!(!authorized_for(
+            &config,
+            "deploy",
+            TokenScope::Start,
+            Some("db")
+        ));
+        assert!(!authorized_for(&config, "deploy", TokenScope::Kill, None));
+
+        // unknown and empty keys are rejected
+        assert!(!authorized_for(&config, "nope", TokenScope::Info, None));
+        assert!(!authorized_for(&config, "", TokenScope::Info, None));
+
+        // signed requests were already checked against the server key
+        config.server.auth_mode = AuthMode::Hmac;
+        assert!(authorized_for(&config, "", TokenScope::Kill, None));
+    }
+
+    /// Run a handler to completion outside of a runtime (so tests can hold locks while it
+    /// runs), returning its status and body
+    fn respond<R: IntoResponse>(
+        handler: impl std::future::Future<Output = R>,
+    ) -> (StatusCode, Bytes) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let res = handler.await.into_response();
+            let status = res.status();
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, body)
+        })
+    }
+
+    fn health() -> (StatusCode, HealthStatus) {
+        let state = AppState {
+            started: Instant::now(),
+        };
+
+        let (status, body) = respond(health_request(State(state)));
+        let report: HealthReport = serde_json::from_slice(&body).unwrap();
+        (status, report.status)
+    }
+
+    #[test]
+    fn health_status_codes() {
+        let (dir, _env) = crate::model::testing::config_dir("health");
+
+        // unreadable configuration
+        std::fs::write(dir.join("services.toml"), "services = [").unwrap();
+        assert_eq!(
+            health(),
+            (StatusCode::SERVICE_UNAVAILABLE, HealthStatus::Unhealthy)
+        );
+        assert_eq!(
+            respond(health_ready_request()).0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // a service that restarts isn't running
+        let mut config = ServConf::read(
+            r#"
+            [services.api]
+            command = "node index.js"
+            working_directory = "/srv/api"
+            restart = true
+            "#
+            .to_string(),
+        );
+
+        ServConf::update_config(config.clone()).unwrap();
+        assert_eq!(health(), (StatusCode::OK, HealthStatus::Degraded));
+        assert_eq!(
+            respond(health_ready_request()).0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // everything is running
+        config.set_running("api", 1);
+        ServConf::update_config(config).unwrap();
+        assert_eq!(health(), (StatusCode::OK, HealthStatus::Healthy));
+        assert_eq!(respond(health_ready_request()).0, StatusCode::OK);
+    }
+
+    /// Send `body` to an app that answers with the validated service name, returning the
+    /// status and the fields of any errors
+    async fn validated(body: &str, signed: bool) -> (StatusCode, Vec<String>) {
+        let app = Router::new().route(
+            "/",
+            post(
+                |ValidatedJson(body): ValidatedJson<BasicServiceRequestBody>| async move {
+                    body.service
+                },
+            ),
+        );
+
+        let mut request = HttpRequest::post("/")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        if signed {
+            request.extensions_mut().insert(SignatureVerified);
+        }
+
+        let res = app.oneshot(request).await.unwrap();
+        let status = res.status();
+
+        if status != StatusCode::BAD_REQUEST {
+            return (status, Vec::new());
+        }
+
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(res["ok"], false);
+
+        let errors: Vec<FieldError> = serde_json::from_value(res["data"].clone()).unwrap();
+        (status, errors.into_iter().map(|e| e.field).collect())
+    }
+
+    #[tokio::test]
+    async fn valid_bodies_are_accepted() {
+        let res = validated(r#"{ "service": "api", "key": "key" }"#, false).await;
+        assert_eq!(res, (StatusCode::OK, Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn empty_fields_are_rejected() {
+        let res = validated(r#"{ "service": " ", "key": "" }"#, false).await;
+        assert_eq!(
+            res,
+            (
+                StatusCode::BAD_REQUEST,
+                vec!["service".to_string(), "key".to_string()]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn mistyped_fields_are_rejected() {
+        let res = validated(r#"{ "service": 1, "key": "key" }"#, false).await;
+        assert_eq!(res, (StatusCode::BAD_REQUEST, vec!["service".to_string()]));
+
+        let res = validated(r#""api""#, false).await;
+        assert_eq!(res, (StatusCode::BAD_REQUEST, vec![".".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn signed_bodies_need_no_key() {
+        let res = validated(r#"{ "service": "api" }"#, true).await;
+        assert_eq!(res, (StatusCode::OK, Vec::new()));
+
+        let res = validated(r#"{ "service": "api" }"#, false).await;
+        assert_eq!(res, (StatusCode::BAD_REQUEST, vec!["key".to_string()]));
+    }
+
+    /// The PID of a running service, once its state says so (within a few seconds)
+    async fn running_pid(name: &str) -> Option<u32> {
+        for _ in 0..40 {
+            let config = ServConf::get_config();
+
+            if let Some(s) = config.service_states.get(name) {
+                if s.0.is_running() && s.1 != 0 {
+                    return Some(s.1);
+                }
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        }
+
+        None
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn restarting_replaces_the_process() {
+        let (dir, _env) = crate::model::testing::config_dir("restart");
+        std::fs::write(
+            dir.join("services.toml"),
+            "[services.web]\ncommand = \"sleep 30\"\nworking_directory = \"/\"\n",
+        )
+        .unwrap();
+
+        let mut config = ServConf::default();
+        config.server.key = "key".to_string();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            Service::spawn("web".to_string()).await.unwrap();
+            let pid = running_pid("web").await.expect("service didn't start");
+
+            let body = serde_json::from_str(r#"{ "service": "web", "key": "key" }"#).unwrap();
+            let res = restart_request(HeaderMap::new(), State(config), ValidatedJson(body))
+                .await
+                .into_response();
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let restarted = running_pid("web").await.expect("service didn't restart");
+            assert_ne!(restarted, pid);
+
+            Service::kill_task("web".to_string(), ServConf::get_config())
+                .await
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,81 @@
+//! Sproc shared process snapshot
+use std::{sync::OnceLock, time::Duration};
+use sysinfo::{Pid, System};
+use tokio::sync::RwLock;
+
+use crate::model::ServicesConfiguration;
+
+/// How often [`run`] refreshes the shared snapshot
+pub const REFRESH_INTERVAL_SECS: u64 = 2;
+
+/// The processes of running services, kept up to date by [`run`] (in the server)
+static SNAPSHOT: OnceLock<RwLock<System>> = OnceLock::new();
+
+/// Load only the given processes (instead of every process on the host)
+pub fn load(pids: &[u32]) -> System {
+    let mut sys = System::new();
+
+    for pid in pids {
+        sys.refresh_process(Pid::from(*pid as usize));
+    }
+
+    sys
+}
+
+/// Call `f` with a [`System`] that includes the given processes
+///
+/// The shared snapshot is used if [`run`] is keeping one and it has every process (and isn't
+/// being refreshed), otherwise only the given processes are loaded.
+pub fn with_processes<T>(pids: &[u32], f: impl FnOnce(&System) -> T) -> T {
+    if let Some(sys) = SNAPSHOT.get().and_then(|s| s.try_read().ok()) {
+        if pids
+            .iter()
+            .all(|pid| sys.process(Pid::from(*pid as usize)).is_some())
+        {
+            return f(&sys);
+        }
+    }
+
+    f(&load(pids))
+}
+
+/// Refresh the processes of running services in the shared snapshot, forever
+///
+/// Only those processes are refreshed, so CPU usage is measured between refreshes.
+pub async fn run() {
+    let snapshot = SNAPSHOT.get_or_init(|| RwLock::new(System::new()));
+
+    loop {
+        let pids: Vec<u32> = match ServicesConfiguration::try_get_config() {
+            Ok(c) => c
+                .service_states
+                .values()
+                .filter(|s| s.0.is_running())
+                .map(|s| s.1)
+                .collect(),
+            Err(e) => {
+                println!("warn: {e}");
+                tokio::time::sleep(Duration::from_secs(REFRESH_INTERVAL_SECS)).await;
+                continue;
+            }
+        };
+
+        {
+            let mut sys = snapshot.write().await;
+            let mut exited = false;
+
+            for pid in &pids {
+                if !sys.refresh_process(Pid::from(*pid as usize)) {
+                    exited = true;
+                }
+            }
+
+            // start over without processes that exited or are no longer managed
+            if exited || sys.processes().len() > pids.len() {
+                *sys = load(&pids);
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(REFRESH_INTERVAL_SECS)).await;
+    }
+}
@@ -0,0 +1,369 @@
+//! Sproc webhook delivery
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    io::{Error, ErrorKind, Result},
+    time::Duration,
+};
+
+use crate::events;
+use crate::model::epoch_timestamp;
+
+/// The kind of event a [`Webhook`] is notified about
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum ServiceEventKind {
+    /// The service process was started
+    Started,
+    /// The service process exited
+    Stopped,
+    /// The service is being restarted automatically
+    Restarted,
+    /// The service failed to start
+    Failed,
+    /// The service started failing its health check
+    Unhealthy,
+    /// The service passed its health check again after being unhealthy
+    Healthy,
+    /// A test event sent by `sproc webhooks test`
+    Test,
+}
+
+/// A URL that is sent service events
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Webhook {
+    /// The URL events are sent to (POST)
+    pub url: String,
+    /// The secret used to sign request bodies (`X-Hub-Signature-256`)
+    pub secret: String,
+    /// The events this webhook should receive
+    #[serde(default)]
+    pub events: Vec<ServiceEventKind>,
+}
+
+/// Request body sent to a [`Webhook`]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct WebhookPayload {
+    /// The name of the service
+    pub service: String,
+    /// The event that happened
+    pub event: ServiceEventKind,
+    /// Unix timestamp (milliseconds)
+    pub timestamp: u128,
+    /// The service process ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// The exit code of the service process (for [`ServiceEventKind::Stopped`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+impl WebhookPayload {
+    /// Create a new [`WebhookPayload`] timestamped now
+    pub fn new(service: String, event: ServiceEventKind, pid: Option<u32>) -> Self {
+        Self {
+            service,
+            event,
+            timestamp: epoch_timestamp(),
+            pid,
+            exit_code: None,
+        }
+    }
+
+    /// Set the exit code of the service process
+    pub fn with_exit_code(mut self, exit_code: Option<i32>) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+}
+
+/// Sign a request body, returning the value of the `X-Hub-Signature-256` header
+pub fn sign(secret: &str, body: &str) -> String {
+    sign_bytes(secret, body.as_bytes())
+}
+
+/// Sign a message, returning `sha256={hex hmac}`
+fn sign_bytes(secret: &str, message: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(message);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// How far a signed API request's `X-Sproc-Timestamp` can be from the server's clock
+pub const SIGNATURE_MAX_AGE_SECS: u64 = 300;
+
+/// Get the message a signed API request's `X-Sproc-Signature` covers:
+/// `{timestamp}\n{METHOD}\n{path}\n{query}\n{body}`
+///
+/// `path` is relative to `/api/sproc` (`/kill`) and `query` is the raw query string (without
+/// `?`, empty if there isn't one).
+pub fn request_message(
+    timestamp: u64,
+    method: &str,
+    path: &str,
+    query: &str,
+    body: &[u8],
+) -> Vec<u8> {
+    let method = method.to_uppercase();
+    let mut message = format!("{timestamp}\n{method}\n{path}\n{query}\n").into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// Sign an API request (see [`request_message`]), returning the value of the
+/// `X-Sproc-Signature` header
+pub fn sign_request(
+    secret: &str,
+    timestamp: u64,
+    method: &str,
+    path: &str,
+    query: &str,
+    body: &[u8],
+) -> String {
+    sign_bytes(
+        secret,
+        &request_message(timestamp, method, path, query, body),
+    )
+}
+
+/// Sign an outgoing API request (`/api/sproc/...`) now, adding its `X-Sproc-Timestamp` and
+/// `X-Sproc-Signature` headers
+pub fn sign_api_request(secret: &str, request: &mut reqwest::Request) {
+    let timestamp = (epoch_timestamp() / 1000) as u64;
+
+    let url = request.url();
+    let path = match url.path().split_once("/api/sproc") {
+        Some((_, path)) => path,
+        None => url.path(),
+    };
+
+    let body = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .unwrap_or_default();
+    let signature = sign_request(
+        secret,
+        timestamp,
+        request.method().as_str(),
+        path,
+        url.query().unwrap_or(""),
+        body,
+    );
+
+    let headers = request.headers_mut();
+    headers.insert("x-sproc-timestamp", timestamp.into());
+
+    if let Ok(signature) = signature.parse() {
+        headers.insert("x-sproc-signature", signature);
+    }
+}
+
+/// Check a `sha256={hex}` signature of a request body
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match signature
+        .strip_prefix("sha256=")
+        .and_then(|s| hex::decode(s).ok())
+    {
+        Some(s) => s,
+        None => return false,
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+impl Webhook {
+    /// Deliver a payload to this webhook, retrying up to 3 times on failure
+    pub async fn deliver(&self, payload: &WebhookPayload) -> Result<()> {
+        let body = serde_json::to_string(payload).unwrap();
+        let signature = sign(&self.secret, &body);
+        let client = reqwest::Client::new();
+
+        let mut delay = Duration::from_secs(1);
+        let mut last_error = String::new();
+
+        for attempt in 0..4 {
+            if attempt > 0 {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+
+            match client
+                .post(&self.url)
+                .body(body.clone())
+                .header("Content-Type", "application/json")
+                .header("X-Hub-Signature-256", &signature)
+                .send()
+                .await
+            {
+                Ok(r) => {
+                    if r.status().is_success() {
+                        return Ok(());
+                    }
+
+                    last_error = r.status().to_string();
+                }
+                Err(e) => last_error = e.to_string(),
+            }
+        }
+
+        Err(Error::new(
+            ErrorKind::Other,
+            format!("Failed to deliver webhook. ({}: {last_error})", self.url),
+        ))
+    }
+}
+
+/// Deliver a payload to every webhook subscribed to its event (in new tasks), and publish it
+/// to [`events`] subscribers
+pub fn dispatch(webhooks: &Option<Vec<Webhook>>, payload: WebhookPayload) {
+    events::publish(payload.clone());
+
+    let webhooks = match webhooks {
+        Some(w) => w,
+        None => return,
+    };
+
+    for webhook in webhooks {
+        if !webhook.events.contains(&payload.event) {
+            continue;
+        }
+
+        let webhook = webhook.clone();
+        let payload = payload.clone();
+
+        tokio::task::spawn(async move {
+            if let Err(e) = webhook.deliver(&payload).await {
+                println!("warn: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn signed_requests_verify() {
+        let signature = sign_request("key", 100, "post", "/kill", "", b"{}");
+        let message = request_message(100, "POST", "/kill", "", b"{}");
+
+        assert!(verify("key", &message, &signature));
+    }
+
+    #[test]
+    fn forged_signatures_are_rejected() {
+        let message = request_message(100, "POST", "/kill", "", b"{}");
+
+        assert!(!verify("key", &message, &sign_bytes("other key", &message)));
+        assert!(!verify("key", &message, "sha256=00"));
+        assert!(!verify("key", &message, ""));
+    }
+
+    #[test]
+    fn signatures_cover_every_part() {
+        let signature = sign_request("key", 100, "POST", "/kill", "a=1", b"{}");
+
+        for message in [
+            request_message(101, "POST", "/kill", "a=1", b"{}"),
+            request_message(100, "GET", "/kill", "a=1", b"{}"),
+            request_message(100, "POST", "/start", "a=1", b"{}"),
+            request_message(100, "POST", "/kill", "a=2", b"{}"),
+            request_message(100, "POST", "/kill", "a=1", b"{\"service\":\"a\"}"),
+        ] {
+            assert!(!verify("key", &message, &signature));
+        }
+    }
+
+    #[test]
+    fn api_requests_are_signed_relative_to_the_api() {
+        let mut request = reqwest::Client::new()
+            .post("http://localhost:6374/api/sproc/kill?a=1")
+            .body("{}")
+            .build()
+            .unwrap();
+
+        sign_api_request("key", &mut request);
+
+        let header = |name| request.headers()[name].to_str().unwrap().to_string();
+        let timestamp: u64 = header("x-sproc-timestamp").parse().unwrap();
+        let message = request_message(timestamp, "POST", "/kill", "a=1", b"{}");
+
+        assert!(verify("key", &message, &header("x-sproc-signature")));
+    }
+
+    /// Serve a webhook receiver that fails the first `failures` deliveries, returning its URL
+    /// and the (signature, body) of every delivery
+    async fn receiver(failures: usize) -> (String, Arc<Mutex<Vec<(String, String)>>>) {
+        use axum::{http::HeaderMap, http::StatusCode, routing::post, Router};
+
+        let deliveries = Arc::new(Mutex::new(Vec::new()));
+        let received = deliveries.clone();
+
+        let app = Router::new().route(
+            "/",
+            post(move |headers: HeaderMap, body: String| async move {
+                let signature = headers["x-hub-signature-256"].to_str().unwrap().to_string();
+                let mut received = received.lock().unwrap();
+                received.push((signature, body));
+
+                match received.len() > failures {
+                    true => StatusCode::OK,
+                    false => StatusCode::INTERNAL_SERVER_ERROR,
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::task::spawn(async move { axum::serve(listener, app).await });
+
+        (url, deliveries)
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed() {
+        let (url, deliveries) = receiver(0).await;
+        let webhook = Webhook {
+            url,
+            secret: "secret".to_string(),
+            events: vec![ServiceEventKind::Started],
+        };
+
+        let payload =
+            WebhookPayload::new("example".to_string(), ServiceEventKind::Started, Some(1));
+        webhook.deliver(&payload).await.unwrap();
+
+        let deliveries = deliveries.lock().unwrap();
+        assert_eq!(deliveries.len(), 1);
+
+        let (ref signature, ref body) = deliveries[0];
+        assert!(verify("secret", body.as_bytes(), signature));
+        assert!(!verify("other secret", body.as_bytes(), signature));
+
+        let received: WebhookPayload = serde_json::from_str(body).unwrap();
+        assert_eq!(received.service, "example");
+        assert_eq!(received.event, ServiceEventKind::Started);
+        assert_eq!(received.pid, Some(1));
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried() {
+        let (url, deliveries) = receiver(1).await;
+        let webhook = Webhook {
+            url,
+            secret: "secret".to_string(),
+            events: Vec::new(),
+        };
+
+        let payload = WebhookPayload::new("example".to_string(), ServiceEventKind::Stopped, None);
+        webhook.deliver(&payload).await.unwrap();
+
+        assert_eq!(deliveries.lock().unwrap().len(), 2);
+    }
+}
@@ -4,18 +4,29 @@ use axum::Router;
 /// Main server process
 #[tokio::main]
 pub async fn main() {
+    // run pre-config check
+    if let Some(ref command) = sproc::model::ServicesConfiguration::get_bootstrap().pre_config_check
+    {
+        if let Err(e) = sproc::model::ServicesConfiguration::run_pre_config_check(command) {
+            panic!("{e}");
+        }
+    }
+
     let config = sproc::model::ServicesConfiguration::get_config();
 
-    // create app
-    let app = Router::new().nest_service("/api/sproc", sproc::server::sproc_api(config.clone()));
+    for error in config.validate() {
+        println!("warn: {error}");
+    }
+
+    // reconcile states, start autostart services, run scheduled services, ...
+    let shared = sproc::server::SharedConfig::from(config.clone());
 
-    let listener = tokio::net::TcpListener::bind(format!("127.0.0.1:{}", config.server.port))
-        .await
-        .unwrap();
+    if sproc::server::background_tasks_enabled(true) {
+        sproc::server::spawn_background_tasks(shared.clone());
+    }
+
+    // create app
+    let app = Router::new().nest_service("/api/sproc", sproc::server::sproc_api(shared));
 
-    println!(
-        "Starting server at http://localhost:{}!",
-        config.server.port
-    );
-    axum::serve(listener, app).await.unwrap();
+    sproc::server::serve(&config.server, app).await.unwrap();
 }
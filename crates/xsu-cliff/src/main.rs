@@ -357,8 +357,15 @@ async fn profile_redirect(Path(username): Path<String>) -> impl IntoResponse {
 pub async fn main() {
     let config = sproc::model::ServicesConfiguration::get_config();
 
-    let home = std::env::var("HOME").expect("failed to read $HOME");
-    let static_dir = format!("{home}/.config/xsu-apps/sproc/static");
+    // reconcile states, start autostart services, run scheduled services, ...
+    // (only if asked to, since these are usually left to sprocd)
+    let shared = sproc::server::SharedConfig::from(config.clone());
+
+    if sproc::server::background_tasks_enabled(false) {
+        sproc::server::spawn_background_tasks(shared.clone());
+    }
+
+    let static_dir = sproc::model::data_dir().join("static");
 
     // create databases
     let auth_database = AuthDatabase::new(
@@ -381,7 +388,7 @@ pub async fn main() {
         .route("/", get(homepage_request))
         .route("/account", get(auth_request))
         // api
-        .nest_service("/api/sproc", sproc::server::sproc_api(config.clone()))
+        .nest_service("/api/sproc", sproc::server::sproc_api(shared))
         .nest_service("/api/registry", sproc::server::registry_api(config.clone()))
         .nest_service("/api/auth", AuthApi::routes(auth_database.clone()))
         .nest_service("/api/ds", DsApi::routes(ds_database.clone()))
@@ -401,13 +408,5 @@ pub async fn main() {
         )
         .with_state((Registry::new(config.clone().server), auth_database.clone()));
 
-    let listener = tokio::net::TcpListener::bind(format!("127.0.0.1:{}", config.server.port))
-        .await
-        .unwrap();
-
-    println!(
-        "Starting server at http://localhost:{}!",
-        config.server.port
-    );
-    axum::serve(listener, app).await.unwrap();
+    sproc::server::serve(&config.server, app).await.unwrap();
 }